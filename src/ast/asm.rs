@@ -18,6 +18,7 @@ use super::{
 use crate::{CodegenContext, LocalContext, MacroToken};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum Dir {
   Out,
   InOut,
@@ -33,6 +34,7 @@ impl ToTokens for Dir {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum RegConstraint {
   Reg,
   RegAbcd,
@@ -67,6 +69,7 @@ impl ToTokens for RegConstraint {
 ///   );
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Asm<'t> {
   pub(crate) template: Vec<String>,
   pub(crate) outputs: Vec<(Dir, RegConstraint, Expr<'t>)>,
@@ -16,6 +16,7 @@ pub(crate) enum Associativity {
 
 /// A binary expression operator.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
   /// `lhs * rhs`
   Mul,
@@ -172,6 +173,7 @@ impl ToTokens for BinaryOp {
 /// #define BINARY_EXPR 7 % c
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinaryExpr<'t> {
   /// Left-hand side expression.
   pub lhs: Box<Expr<'t>>,
@@ -206,6 +208,13 @@ impl<'t> BinaryExpr<'t> {
       None
     };
 
+    // Both operands of a logical operator are conditions.
+    if matches!(self.op, BinaryOp::And | BinaryOp::Or) {
+      self.lhs.finish_condition(ctx)?;
+      self.rhs.finish_condition(ctx)?;
+      return Ok(Some(Type::BuiltIn(BuiltInType::Bool)))
+    }
+
     let mut lhs_ty = self.lhs.finish(ctx)?;
     let mut rhs_ty = self.rhs.finish(ctx)?;
 
@@ -12,6 +12,7 @@ use super::{Associativity, Expr, Lit, LitInt, Type};
 /// #define CAST (int*)&var
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cast<'t> {
   /// A type.
   pub ty: Type<'t>,
@@ -1,13 +1,15 @@
 use std::borrow::Cow;
 
 use nom::{
-  bytes::complete::{tag, take_until},
-  combinator::all_consuming,
-  sequence::delimited,
+  branch::alt,
+  bytes::complete::{is_not, tag, take_until},
+  combinator::{all_consuming, map, opt},
+  sequence::{delimited, preceded},
 };
 
 /// A comment.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Comment<'t> {
   pub(crate) comment: Cow<'t, str>,
 }
@@ -16,7 +18,10 @@ impl<'t> TryFrom<&'t str> for Comment<'t> {
   type Error = nom::Err<nom::error::Error<&'t str>>;
 
   fn try_from(s: &'t str) -> Result<Self, Self::Error> {
-    let (_, comment) = all_consuming(delimited(tag("/*"), take_until("*/"), tag("*/")))(s)?;
+    let (_, comment) = all_consuming(alt((
+      delimited(tag("/*"), take_until("*/"), tag("*/")),
+      map(preceded(tag("//"), opt(is_not("\n"))), |comment| comment.unwrap_or_default()),
+    )))(s)?;
     Ok(Self { comment: Cow::Borrowed(comment) })
   }
 }
@@ -19,6 +19,7 @@ use crate::{CodegenContext, LocalContext, MacroArgType, MacroToken, UnaryOp};
 
 /// An expression.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(missing_docs)]
 pub enum Expr<'t> {
   Arg(MacroArg),
@@ -188,6 +189,8 @@ impl<'t> Expr<'t> {
               (expr, Access::UnaryOp(op)) if !was_unary_postfix_op => {
                 Self::Unary(UnaryExpr { op, expr: Box::new(expr) })
               },
+              // `_Pragma` is an operator, not a function.
+              (Self::Var(Var { ref name }), Access::Fn(_)) if name.as_str() == "_Pragma" => return Err(()),
               // TODO: Support calling expressions as functions.
               (name @ Self::Arg(_) | name @ Self::Var(_), Access::Fn(args)) => {
                 Self::FunctionCall(FunctionCall { name: Box::new(name), args })
@@ -351,14 +354,34 @@ impl<'t> Expr<'t> {
     )(tokens)
   }
 
-  fn parse_term_prec13<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
+  fn parse_term_prec12<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
     let (tokens, term) = Self::parse_term_prec10(tokens)?;
 
+    fold_many0(
+      preceded(delimited(meta, punct("&&"), meta), Self::parse_term_prec10),
+      move || term.clone(),
+      |lhs, rhs| Self::Binary(BinaryExpr { lhs: Box::new(lhs), op: BinaryOp::And, rhs: Box::new(rhs) }),
+    )(tokens)
+  }
+
+  fn parse_term_prec13<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
+    let (tokens, term) = Self::parse_term_prec12(tokens)?;
+
+    fold_many0(
+      preceded(delimited(meta, punct("||"), meta), Self::parse_term_prec12),
+      move || term.clone(),
+      |lhs, rhs| Self::Binary(BinaryExpr { lhs: Box::new(lhs), op: BinaryOp::Or, rhs: Box::new(rhs) }),
+    )(tokens)
+  }
+
+  fn parse_term_prec14<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
+    let (tokens, term) = Self::parse_term_prec13(tokens)?;
+
     // Parse ternary.
     if let Ok((tokens, _)) = delimited(meta, punct("?"), meta)(tokens) {
       let (tokens, if_branch) = Self::parse(tokens)?;
       let (tokens, _) = delimited(meta, punct(":"), meta)(tokens)?;
-      let (tokens, else_branch) = Self::parse_term_prec13(tokens)?;
+      let (tokens, else_branch) = Self::parse_term_prec14(tokens)?;
       return Ok((
         tokens,
         Self::Ternary(TernaryExpr {
@@ -372,8 +395,8 @@ impl<'t> Expr<'t> {
     Ok((tokens, term))
   }
 
-  fn parse_term_prec14<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
-    let (tokens, term) = Self::parse_term_prec13(tokens)?;
+  fn parse_term_prec15<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
+    let (tokens, term) = Self::parse_term_prec14(tokens)?;
 
     fold_many0(
       pair(
@@ -394,7 +417,7 @@ impl<'t> Expr<'t> {
           )),
           meta,
         ),
-        Self::parse_term_prec14,
+        Self::parse_term_prec15,
       ),
       move || term.clone(),
       |lhs, (op, rhs)| Self::Binary(BinaryExpr { lhs: Box::new(lhs), op, rhs: Box::new(rhs) }),
@@ -403,7 +426,7 @@ impl<'t> Expr<'t> {
 
   /// Parse an expression.
   pub(crate) fn parse<'i>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
-    Self::parse_term_prec14(tokens)
+    Self::parse_term_prec15(tokens)
   }
 
   pub(crate) fn finish_condition<C>(
@@ -594,6 +617,7 @@ impl<'t> Expr<'t> {
           },
           (UnaryOp::Not, Self::Literal(Lit::Int(LitInt { value: i, suffix: None }))) => {
             *self = Self::Literal(Lit::Int(LitInt { value: (*i == 0).into(), suffix: None }));
+            return self.finish(ctx)
           },
           (UnaryOp::Not, Self::Literal(Lit::Float(f))) => {
             *self = Self::Literal(Lit::Int(LitInt {
@@ -604,6 +628,7 @@ impl<'t> Expr<'t> {
               } as i128,
               suffix: None,
             }));
+            return self.finish(ctx)
           },
           (UnaryOp::Not, expr) => {
             if ty != Some(Type::BuiltIn(BuiltInType::Bool)) {
@@ -617,6 +642,8 @@ impl<'t> Expr<'t> {
 
               *self = Self::Binary(BinaryExpr { lhs: Box::new(lhs), op: BinaryOp::Eq, rhs: Box::new(rhs) })
             }
+
+            return Ok(Some(Type::BuiltIn(BuiltInType::Bool)))
           },
           (UnaryOp::Comp, Self::Literal(Lit::Float(_) | Lit::String(_))) => {
             return Err(crate::CodegenError::UnsupportedExpression)
@@ -923,6 +950,31 @@ mod tests {
     );
   }
 
+  #[test]
+  fn parse_logical() {
+    parse_tokens!(
+      Expr => [id!(a), punct!("||"), id!(b), punct!("&&"), id!(c)],
+      Expr::Binary(BinaryExpr {
+        lhs: Box::new(var!(a)),
+        op: BinaryOp::Or,
+        rhs: Box::new(Expr::Binary(BinaryExpr { lhs: Box::new(var!(b)), op: BinaryOp::And, rhs: Box::new(var!(c)) })),
+      })
+    );
+
+    parse_tokens!(
+      Expr => [id!(a), punct!("&&"), id!(b), punct!("?"), lit_int!(1), punct!(":"), lit_int!(2)],
+      Expr::Ternary(TernaryExpr {
+        condition: Box::new(Expr::Binary(BinaryExpr {
+          lhs: Box::new(var!(a)),
+          op: BinaryOp::And,
+          rhs: Box::new(var!(b))
+        })),
+        if_branch: Box::new(lit!(1)),
+        else_branch: Box::new(lit!(2)),
+      })
+    );
+  }
+
   #[test]
   fn parse_function_call() {
     parse_tokens!(
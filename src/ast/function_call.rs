@@ -12,6 +12,7 @@ use crate::{CodegenContext, LocalContext, MacroArgType};
 /// #define FUNC f(1, 2, 3)
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionCall<'t> {
   /// The function name identifier.
   pub(crate) name: Box<Expr<'t>>,
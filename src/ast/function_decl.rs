@@ -22,6 +22,7 @@ use crate::{CodegenContext, LocalContext, MacroArgType, MacroToken};
 /// #define FUNC_DECL void f(int a, int b, int c)
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionDecl<'t> {
   ret_ty: Type<'t>,
   name: Expr<'t>,
@@ -27,6 +27,7 @@ pub(crate) fn is_identifier(s: &str) -> bool {
 
 /// An identifier.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Identifier<'t> {
   pub(crate) id: Cow<'t, str>,
 }
@@ -79,6 +80,7 @@ impl<'t> TryFrom<&'t str> for Identifier<'t> {
 
 /// An identifier continuation.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct IdentifierContinue<'t> {
   pub(crate) id_cont: Cow<'t, str>,
 }
@@ -28,6 +28,7 @@ pub use self::string::*;
 
 /// A literal.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Lit<'t> {
   /// A character literal.
   Char(LitChar),
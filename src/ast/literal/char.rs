@@ -42,6 +42,7 @@ impl LitCharPrefix {
 
 /// A character literal.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LitChar {
   /// An ordinary character (`char`) literal.
   ///
@@ -42,6 +42,7 @@ impl LitFloatSuffix {
 /// #define FLOAT 3.14L
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LitFloat {
   /// A `float`.
   Float(f32),
@@ -60,6 +60,7 @@ impl LitIntSizeSuffix {
 /// #define INT 4 ## ULL
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LitInt {
   /// The integer value.
   pub value: i128,
@@ -29,6 +29,7 @@ use super::escaped_char;
 
 /// A string literal.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LitString<'t> {
   /// An ordinary string (`const char*`) literal.
   ///
@@ -1,5 +1,6 @@
 /// A macro argument.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MacroArg {
   pub(crate) index: usize,
 }
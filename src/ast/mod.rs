@@ -46,6 +46,9 @@ pub use statement::*;
 mod var_decl;
 pub use var_decl::*;
 
+mod pragma;
+pub use pragma::*;
+
 mod punctuation;
 pub use punctuation::*;
 
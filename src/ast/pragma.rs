@@ -0,0 +1,77 @@
+use std::fmt::Debug;
+
+use nom::{
+  combinator::map_opt,
+  sequence::{preceded, terminated},
+  IResult,
+};
+
+use super::{
+  tokens::{id, meta, parenthesized, take_one},
+  Lit, LitString,
+};
+use crate::MacroToken;
+
+/// A `_Pragma` operator.
+///
+/// ```c
+/// #define DIAGNOSTIC_PUSH _Pragma("GCC diagnostic push")
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pragma {
+  pub(crate) directive: String,
+}
+
+impl Pragma {
+  /// Parse a `_Pragma` operator.
+  pub(crate) fn parse<'i, 't>(tokens: &'i [MacroToken<'t>]) -> IResult<&'i [MacroToken<'t>], Self> {
+    preceded(
+      terminated(id("_Pragma"), meta),
+      parenthesized(map_opt(take_one, |token| {
+        let directive = match token {
+          MacroToken::Lit(Lit::String(LitString::Ordinary(bytes))) => String::from_utf8(bytes.to_vec()).ok()?,
+          MacroToken::Lit(Lit::String(LitString::Wide(chars))) => {
+            chars.iter().map(|&c| char::from_u32(c)).collect::<Option<String>>()?
+          },
+          _ => return None,
+        };
+
+        Some(Self { directive })
+      })),
+    )(tokens)
+  }
+
+  /// The destringized pragma directive, e.g. `GCC diagnostic push`.
+  pub fn directive(&self) -> &str {
+    &self.directive
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ast::*;
+
+  #[test]
+  fn parse_pragma() {
+    parse_tokens!(
+      Pragma => [id!(_Pragma), punct!("("), lit_string!("GCC diagnostic push"), punct!(")")],
+      Pragma { directive: "GCC diagnostic push".into() },
+    );
+  }
+
+  #[test]
+  fn parse_pragma_escaped() {
+    parse_tokens!(
+      Pragma => [id!(_Pragma), punct!("("), lit_string!("push_macro(\"X\")"), punct!(")")],
+      Pragma { directive: "push_macro(\"X\")".into() },
+    );
+  }
+
+  #[test]
+  fn parse_pragma_invalid() {
+    let tokens = crate::macro_token::tokens![id!(_Pragma), punct!("("), id!(once), punct!(")")];
+    assert!(Pragma::parse(&tokens).is_err());
+  }
+}
@@ -11,6 +11,15 @@ macro_rules! impl_punct {
         &self.punctuation
       }
 
+      pub(crate) fn to_static(&self) -> Punctuation<'static> {
+        Punctuation {
+          punctuation: match self.punctuation {
+            $($p => $p,)*
+            p => unreachable!("invalid punctuation {p}"),
+          }
+        }
+      }
+
       pub(crate) fn concat(&self, other: &Self) -> Option<Self> {
         Some(Self {
           punctuation: match (self.punctuation, other.punctuation) {
@@ -84,6 +93,25 @@ impl PartialEq<Punctuation<'_>> for &str {
   }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Punctuation<'_> {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.punctuation)
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Punctuation<'_> {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let punctuation = String::deserialize(deserializer)?;
+
+    match Punctuation::try_from(punctuation.as_str()) {
+      Ok(punctuation) => Ok(punctuation.to_static()),
+      Err(punctuation) => Err(serde::de::Error::custom(format!("invalid punctuation {punctuation}"))),
+    }
+  }
+}
+
 impl_punct![
   "[", "]", "(", ")", "{", "}", ".", "->", "++", "--", "&", "*", "+", "-", "~", "!", "/", "%", "<<", ">>", "<", ">",
   "<=", ">=", "==", "!=", "^", "|", "&&", "||", "?", ":", ";", "...", "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=",
@@ -25,6 +25,7 @@ use crate::{CodegenContext, LocalContext, MacroToken};
 /// } while (0)
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(missing_docs)]
 pub enum Statement<'t> {
   Asm(Asm<'t>),
@@ -47,6 +48,8 @@ pub enum Statement<'t> {
     block: Vec<Statement<'t>>,
     condition: Expr<'t>,
   },
+  /// A `_Pragma` operator.
+  Pragma(Pragma),
 }
 
 impl<'t> Statement<'t> {
@@ -58,6 +61,7 @@ impl<'t> Statement<'t> {
 
     alt((
       map(terminated(Asm::parse, semicolon_or_eof), Self::Asm),
+      map(Pragma::parse, Self::Pragma),
       map(
         tuple((
           preceded(terminated(id("if"), meta), condition),
@@ -134,6 +138,8 @@ impl<'t> Statement<'t> {
 
         condition.finish_condition(ctx)?;
       },
+      // Pragmas only affect the C compiler.
+      Self::Pragma(_) => (),
     }
 
     Ok(Some(Type::BuiltIn(BuiltInType::Void)))
@@ -191,6 +197,7 @@ impl<'t> Statement<'t> {
           }
         })
       },
+      Self::Pragma(_) => (),
     }
   }
 
@@ -336,4 +343,27 @@ mod tests {
       Statement::DoWhile { block: vec![Statement::Expr(var!(a))], condition: var!(b) },
     );
   }
+
+  #[test]
+  fn parse_pragma_block() {
+    parse_tokens!(
+      Statement => [
+        id!(_Pragma),
+        punct!("("),
+        lit_string!("GCC diagnostic push"),
+        punct!(")"),
+        id!(a),
+        punct!(";"),
+        id!(_Pragma),
+        punct!("("),
+        lit_string!("GCC diagnostic pop"),
+        punct!(")"),
+      ],
+      Statement::Block(vec![
+        Statement::Pragma(Pragma { directive: "GCC diagnostic push".into() }),
+        Statement::Expr(var!(a)),
+        Statement::Pragma(Pragma { directive: "GCC diagnostic pop".into() }),
+      ]),
+    );
+  }
 }
@@ -21,6 +21,7 @@ use crate::{CodegenContext, LocalContext, MacroArgType, MacroToken};
 /// #define STRINGIFY(x) #x
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stringify<'t> {
   pub(crate) arg: Box<Expr<'t>>,
 }
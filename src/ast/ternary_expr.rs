@@ -11,6 +11,7 @@ use super::{Expr, Type};
 /// #define TERNARY_EXPR(cond) cond ? 1 : 2
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TernaryExpr<'t> {
   /// A boolean condition.
   pub condition: Box<Expr<'t>>,
@@ -140,6 +140,7 @@ pub(crate) use parse_tokens;
 macro_rules! assert_eq_tokens {
   ($expr:expr, $expected:expr) => {
     let mut ctx = LocalContext {
+      macro_name: Default::default(),
       arg_names: Default::default(),
       arg_types: Default::default(),
       export_as_macro: false,
@@ -11,6 +11,7 @@ use super::{BuiltInType, Identifier, Type, TypeQualifier};
 /// #define VAR abc
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Var<'t> {
   pub(crate) name: Identifier<'t>,
 }
@@ -33,6 +34,17 @@ impl<'t> Var<'t> {
           qualifier: TypeQualifier::Const,
         }))
       },
+      // The name of the generated function, there is no enclosing function for a variable-like macro.
+      "__func__" | "__FUNCTION__" => {
+        if ctx.is_variable_macro {
+          return Err(crate::CodegenError::UnsupportedExpression)
+        }
+
+        Ok(Some(Type::Qualified {
+          ty: Box::new(Type::Ptr { ty: Box::new(Type::BuiltIn(BuiltInType::Char)) }),
+          qualifier: TypeQualifier::Const,
+        }))
+      },
       "__SCHAR_MAX__" => Ok(Some(Type::BuiltIn(BuiltInType::SChar))),
       "__SHRT_MAX__" => Ok(Some(Type::BuiltIn(BuiltInType::Short))),
       "__INT_MAX__" => Ok(Some(Type::BuiltIn(BuiltInType::Int))),
@@ -64,6 +76,17 @@ impl<'t> Var<'t> {
           }
         }
       },
+      "__func__" | "__FUNCTION__" => {
+        let name = &ctx.macro_name;
+
+        let trait_prefix = ctx.trait_prefix().into_iter();
+        quote! {
+          {
+            const BYTES: &[u8] = #(#trait_prefix::)*concat!(#name, '\0').as_bytes();
+            BYTES.as_ptr() as *const #(#ffi_prefix::)*c_char
+          }
+        }
+      },
       "__SCHAR_MAX__" => quote! { #(#ffi_prefix::)*c_schar::MAX },
       "__SHRT_MAX__" => quote! { #(#ffi_prefix::)*c_short::MAX },
       "__INT_MAX__" => quote! { #(#ffi_prefix::)*c_int::MAX },
@@ -13,6 +13,7 @@ use crate::{CodegenContext, LocalContext, MacroToken};
 /// #define DECL int var = 77
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(missing_docs)]
 pub struct VarDecl<'t> {
   pub ty: Type<'t>,
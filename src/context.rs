@@ -20,6 +20,7 @@ pub(crate) enum MacroArgType<'t> {
 /// Local code generation context.
 #[derive(Debug, Clone)]
 pub(crate) struct LocalContext<'g, 't, C> {
+  pub(crate) macro_name: String,
   pub(crate) arg_names: Vec<String>,
   pub(crate) arg_types: Vec<MacroArgType<'t>>,
   pub(crate) export_as_macro: bool,
@@ -34,6 +35,7 @@ where
 {
   pub fn new(cx: &'g C) -> Self {
     Self {
+      macro_name: Default::default(),
       arg_names: Default::default(),
       arg_types: Default::default(),
       export_as_macro: false,
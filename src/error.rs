@@ -1,7 +1,10 @@
 use std::fmt;
 
+use crate::{location::impl_located_error, SourceLocation};
+
 /// An error during parsing.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParserError {
   /// Invalid macro name.
   InvalidMacroName,
@@ -9,14 +12,27 @@ pub enum ParserError {
   InvalidMacroArgs,
   /// Invalid macro body.
   InvalidMacroBody,
+  /// Unterminated comment.
+  UnterminatedComment,
+  /// An error at the given location.
+  Located {
+    /// The source location.
+    location: SourceLocation,
+    /// The error.
+    error: Box<Self>,
+  },
 }
 
+impl_located_error!(ParserError);
+
 impl fmt::Display for ParserError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       Self::InvalidMacroName => write!(f, "invalid macro name"),
       Self::InvalidMacroArgs => write!(f, "invalid macro arguments"),
       Self::InvalidMacroBody => write!(f, "invalid macro body"),
+      Self::UnterminatedComment => write!(f, "unterminated comment"),
+      Self::Located { location, error } => write!(f, "{}: {}", location, error),
     }
   }
 }
@@ -25,6 +41,7 @@ impl std::error::Error for ParserError {}
 
 /// An error during code generation.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CodegenError {
   /// Recursive macro definition.
   RecursiveDefinition(String),
@@ -36,8 +53,17 @@ pub enum CodegenError {
   UnsupportedType(String),
   /// Variable is unknown.
   UnknownVariable(String),
+  /// An error at the given location.
+  Located {
+    /// The source location.
+    location: SourceLocation,
+    /// The error.
+    error: Box<Self>,
+  },
 }
 
+impl_located_error!(CodegenError);
+
 impl fmt::Display for CodegenError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
@@ -46,6 +72,7 @@ impl fmt::Display for CodegenError {
       Self::UnsupportedExpression => write!(f, "unsupported expression"),
       Self::UnsupportedType(ty) => write!(f, "unsupported type {}", ty),
       Self::UnknownVariable(var_name) => write!(f, "unknown variable {}", var_name),
+      Self::Located { location, error } => write!(f, "{}: {}", location, error),
     }
   }
 }
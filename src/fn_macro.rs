@@ -12,7 +12,7 @@ use quote::{quote, TokenStreamExt};
 
 use crate::{
   ast::{macro_id, meta, punct},
-  is_identifier, CodegenContext, LocalContext, MacroArgType, MacroBody, MacroToken, Type,
+  is_identifier, CodegenContext, LocalContext, MacroArgType, MacroBody, MacroToken, SourceLocation, Type,
 };
 
 /// A function-like macro.
@@ -31,7 +31,7 @@ use crate::{
 /// let mut macro_set = MacroSet::new();
 ///
 /// // #define FUNC(a, b, c) a + b * c
-/// macro_set.define_fn_macro("FUNC", &["a", "b", "c"], &["a", "+", "b", "*", "c"]);
+/// macro_set.define_fn_macro("FUNC", &["a", "b", "c"], &["a", "+", "b", "*", "c"])?;
 ///
 /// let (args, body) = macro_set.expand_fn_macro("FUNC")?;
 /// let mut fn_macro = FnMacro::parse("FUNC", &args, &body)?;
@@ -80,7 +80,7 @@ use crate::{
 /// let mut macro_set = MacroSet::new();
 ///
 /// // #define FUNC(a, b, c) a + b * c
-/// macro_set.define_fn_macro("FUNC", &["a", "b", "c"], &["a", "+", "b", "*", "c"]);
+/// macro_set.define_fn_macro("FUNC", &["a", "b", "c"], &["a", "+", "b", "*", "c"])?;
 ///
 /// let (args, body) = macro_set.expand_fn_macro("FUNC")?;
 /// let mut fn_macro = FnMacro::parse("FUNC", &args, &body)?;
@@ -104,6 +104,7 @@ pub struct FnMacro<'t> {
   name: String,
   args: Vec<String>,
   body: MacroBody<'t>,
+  location: Option<SourceLocation>,
 }
 
 impl<'t> FnMacro<'t> {
@@ -142,11 +143,38 @@ impl<'t> FnMacro<'t> {
 
     let (_, body) = MacroBody::parse(body).map_err(|_err| crate::ParserError::InvalidMacroBody)?;
 
-    Ok(Self { name, args, body })
+    Ok(Self { name, args, body, location: None })
+  }
+
+  /// Parse a function-like macro like [`FnMacro::parse`], recording the location of its definition,
+  /// e.g. from [`MacroSet::location`](crate::MacroSet::location).
+  ///
+  /// Errors when parsing the macro or generating code will contain this location.
+  pub fn parse_with_location(
+    name: &str,
+    args: &[MacroToken<'t>],
+    body: &[MacroToken<'t>],
+    location: SourceLocation,
+  ) -> Result<Self, crate::ParserError> {
+    let mut fn_macro = Self::parse(name, args, body).map_err(|err| err.with_location(location.clone()))?;
+    fn_macro.location = Some(location);
+    Ok(fn_macro)
   }
 
   /// Infer the type of this function macro and generate corresponding Rust code.
   pub fn generate<C>(&mut self, cx: C) -> Result<TokenStream, crate::CodegenError>
+  where
+    C: CodegenContext,
+  {
+    let location = self.location.clone();
+
+    self.generate_tokens(cx).map_err(|err| match location {
+      Some(location) => err.with_location(location),
+      None => err,
+    })
+  }
+
+  fn generate_tokens<C>(&mut self, cx: C) -> Result<TokenStream, crate::CodegenError>
   where
     C: CodegenContext,
   {
@@ -167,6 +195,7 @@ impl<'t> FnMacro<'t> {
       .collect::<Result<_, _>>()?;
 
     let mut ctx = LocalContext::new(&cx);
+    ctx.macro_name = self.name.clone();
     ctx.arg_names = self.args.clone();
     ctx.arg_types = arg_types;
     let ret_ty = self.body.finish(&mut ctx)?;
@@ -274,4 +303,9 @@ impl<'t> FnMacro<'t> {
   pub fn body(&self) -> &MacroBody {
     &self.body
   }
+
+  /// The location of the definition of this function macro, if known.
+  pub fn location(&self) -> Option<&SourceLocation> {
+    self.location.as_ref()
+  }
 }
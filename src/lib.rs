@@ -6,6 +6,15 @@
 //! as variable-like macros (e.g. `#define VAR 4 + 7 * 82`) are supported.
 //!
 //! See [`FnMacro`] and [`VarMacro`] on how to parse macros.
+//!
+//! # Features
+//!
+//! - `serde`: Implement `Serialize` and `Deserialize` for [`MacroSet`], [`MacroToken`],
+//!   the AST (e.g. [`Expr`], [`Statement`], [`Type`] and [`Lit`]) and the error types.
+//!   AST types use serde's default representation, i.e. enums are externally tagged,
+//!   while identifiers, comments and punctuation are plain strings. See
+//!   [`MacroSet`'s `Serialize` implementation](MacroSet#impl-Serialize-for-MacroSet)
+//!   for its format.
 
 #![warn(missing_debug_implementations)]
 #![warn(missing_docs)]
@@ -14,6 +23,8 @@ pub mod ast;
 pub use ast::*;
 mod error;
 pub use error::*;
+mod location;
+pub use location::SourceLocation;
 mod macro_body;
 pub use macro_body::*;
 mod context;
@@ -25,8 +36,16 @@ pub use var_macro::VarMacro;
 mod fn_macro;
 pub use fn_macro::FnMacro;
 
+mod tokenizer;
+pub use tokenizer::tokenize;
+
 mod macro_set;
-pub use macro_set::{ExpansionError, MacroSet};
+pub use macro_set::{
+  render_trace, Compiler, ConditionError, ConditionValue, DataModel, DefinitionOrder, DependencyCycle, DependencyGraph,
+  Expansion, ExpansionError, ExpansionStep, FeatureQuery, FileLookup, FileSystem, GenerateError, GeneratedMacro,
+  Limits, MacroDefinition, MacroDiff, MacroKind, MacroSet, PreviousDefinition, Profile, Redefinition, SourceError,
+  Standard, TracedToken,
+};
 
 pub(crate) mod macro_token;
 pub use macro_token::MacroToken;
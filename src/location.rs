@@ -0,0 +1,93 @@
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+};
+
+/// A location in C source code.
+///
+/// # Example
+///
+/// ```
+/// use cmacro::SourceLocation;
+///
+/// let location = SourceLocation::new(12, 9).with_file("include/header.h");
+/// assert_eq!(location.to_string(), "include/header.h:12:9");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceLocation {
+  file: Option<PathBuf>,
+  line: usize,
+  column: usize,
+}
+
+impl SourceLocation {
+  /// Create a new location from a 1-based line and column.
+  pub fn new(line: usize, column: usize) -> Self {
+    Self { file: None, line, column }
+  }
+
+  /// Set the file of this location.
+  pub fn with_file<P: Into<PathBuf>>(mut self, file: P) -> Self {
+    self.file = Some(file.into());
+    self
+  }
+
+  /// The file of this location, if known.
+  pub fn file(&self) -> Option<&Path> {
+    self.file.as_deref()
+  }
+
+  /// The 1-based line.
+  pub fn line(&self) -> usize {
+    self.line
+  }
+
+  /// The 1-based column.
+  pub fn column(&self) -> usize {
+    self.column
+  }
+}
+
+/// Implement `with_location`, `location` and `without_location` for an error type
+/// with a `Located { location, error }` variant.
+macro_rules! impl_located_error {
+  ($error:ty) => {
+    impl $error {
+      /// Add a location to this error, unless it already has one.
+      pub fn with_location(self, location: $crate::SourceLocation) -> Self {
+        match self {
+          Self::Located { .. } => self,
+          error => Self::Located { location, error: Box::new(error) },
+        }
+      }
+
+      /// The location of this error, if known.
+      pub fn location(&self) -> Option<&$crate::SourceLocation> {
+        match self {
+          Self::Located { location, .. } => Some(location),
+          _ => None,
+        }
+      }
+
+      /// This error without its location.
+      pub fn without_location(&self) -> &Self {
+        match self {
+          Self::Located { error, .. } => error,
+          error => error,
+        }
+      }
+    }
+  };
+}
+pub(crate) use impl_located_error;
+
+impl fmt::Display for SourceLocation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(file) = &self.file {
+      write!(f, "{}:", file.display())?;
+    }
+
+    write!(f, "{}:{}", self.line, self.column)
+  }
+}
@@ -0,0 +1,300 @@
+use std::{error::Error, fmt, panic, thread};
+
+use super::{Entries, ExpansionError, ExpansionState, MacroDefinition, MacroSet};
+use crate::{CodegenContext, CodegenError, FnMacro, MacroToken, ParserError, VarMacro};
+
+/// The expansion of a macro, see [`MacroSet::expand_all`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expansion<'t> {
+  /// The expansion of a variable-like macro, see [`MacroSet::expand_var_macro`].
+  Var(Vec<MacroToken<'t>>),
+  /// The parameters and the expansion of a function-like macro, see [`MacroSet::expand_fn_macro`].
+  Fn(Vec<MacroToken<'t>>, Vec<MacroToken<'t>>),
+}
+
+/// The Rust code generated for a macro, see [`MacroSet::generate_all`].
+///
+/// Since token streams cannot be sent between threads, the code is returned as strings,
+/// which can be converted back using `str::parse::<TokenStream>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GeneratedMacro {
+  /// The value and type of a variable-like macro, see [`VarMacro::generate`].
+  Var(String, Option<String>),
+  /// The function generated for a function-like macro, see [`FnMacro::generate`].
+  Fn(String),
+}
+
+/// An error while generating code for a macro, see [`MacroSet::generate_all`].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GenerateError {
+  /// The macro could not be expanded.
+  Expansion(ExpansionError),
+  /// The expansion could not be parsed.
+  Parser(ParserError),
+  /// No code could be generated for the parsed macro.
+  Codegen(CodegenError),
+}
+
+impl fmt::Display for GenerateError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Expansion(err) => err.fmt(f),
+      Self::Parser(err) => err.fmt(f),
+      Self::Codegen(err) => err.fmt(f),
+    }
+  }
+}
+
+impl Error for GenerateError {}
+
+impl From<ExpansionError> for GenerateError {
+  fn from(err: ExpansionError) -> Self {
+    Self::Expansion(err)
+  }
+}
+
+impl From<ParserError> for GenerateError {
+  fn from(err: ParserError) -> Self {
+    Self::Parser(err)
+  }
+}
+
+impl From<CodegenError> for GenerateError {
+  fn from(err: CodegenError) -> Self {
+    Self::Codegen(err)
+  }
+}
+
+impl MacroSet {
+  /// Call the given function for all defined macros in parallel.
+  ///
+  /// Each thread passes its own memo to the function, so that expansions of shared
+  /// macros are reused without locking the cache of the set. The results are in
+  /// definition order, see [`MacroSet::definitions`].
+  fn map_definitions<'s, T, F>(&'s self, f: F) -> Vec<(MacroDefinition<'s>, T)>
+  where
+    T: Send,
+    F: Fn(&mut Entries, MacroDefinition<'s>) -> T + Sync,
+  {
+    let definitions = self.definitions().collect::<Vec<_>>();
+
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = definitions.len().div_ceil(threads).max(1);
+
+    let map_chunk = |chunk: &[MacroDefinition<'s>]| {
+      let mut memo = Entries::default();
+      chunk.iter().map(|&d| (d, f(&mut memo, d))).collect::<Vec<_>>()
+    };
+
+    thread::scope(|scope| {
+      let handles = definitions.chunks(chunk_size).map(|chunk| scope.spawn(|| map_chunk(chunk))).collect::<Vec<_>>();
+
+      // Joining the threads in order keeps the results in definition order.
+      handles.into_iter().flat_map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err))).collect()
+    })
+  }
+
+  /// Expand a macro without arguments during a batch expansion.
+  fn expand_with_memo(
+    &self,
+    definition: MacroDefinition<'_>,
+    memo: &mut Entries,
+  ) -> Result<Expansion<'_>, ExpansionError> {
+    let name = definition.name();
+    let state = ExpansionState::local(name, memo);
+
+    if definition.is_function_like() {
+      let (args, body) = self.expand_fn_macro_with_state(name, state)?;
+      Ok(Expansion::Fn(args, body))
+    } else {
+      Ok(Expansion::Var(self.expand_var_macro_with_state(name, state)?))
+    }
+  }
+
+  /// Expand all defined macros in parallel.
+  ///
+  /// The expansions are returned along with their definitions, in definition order,
+  /// see [`MacroSet::definitions`]. Expansions cached by [`MacroSet::cache_expansions`]
+  /// are used, and other shared macros are only expanded once per thread.
+  ///
+  /// Like with [`MacroSet::expand_var_macro`], each macro is expanded as if it were
+  /// expanded first, see [`MacroSet::set_counter`].
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{Expansion, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_var_macro("ONE", ["1"])?;
+  /// macro_set.define_fn_macro("INC", ["x"], ["x", "+", "ONE"])?;
+  ///
+  /// let expansions = macro_set.expand_all();
+  /// assert_eq!(expansions[0].0.name(), "ONE");
+  /// assert_eq!(expansions[0].1, Ok(Expansion::Var(macro_set.expand(&["1"])?)));
+  /// assert_eq!(expansions[1].0.name(), "INC");
+  /// assert!(matches!(expansions[1].1, Ok(Expansion::Fn(_, _))));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn expand_all(&self) -> Vec<(MacroDefinition<'_>, Result<Expansion<'_>, ExpansionError>)> {
+    self.map_definitions(|memo, definition| self.expand_with_memo(definition, memo))
+  }
+
+  /// Expand all defined macros and generate Rust code for them in parallel.
+  ///
+  /// Each macro is parsed using [`VarMacro::parse_with_location`] or [`FnMacro::parse_with_location`]
+  /// if its location is known, and code is generated using the given context. The results are
+  /// returned along with their definitions, in definition order, see [`MacroSet::definitions`].
+  ///
+  /// Macros are expanded like in [`MacroSet::expand_all`].
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{GeneratedMacro, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_var_macro("ONE", ["1"])?;
+  /// macro_set.define_var_macro("TWO", ["ONE", "+", "ONE"])?;
+  ///
+  /// let generated = macro_set.generate_all(());
+  /// assert_eq!(generated[1].0.name(), "TWO");
+  /// assert_eq!(generated[1].1, Ok(GeneratedMacro::Var("2".into(), None)));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn generate_all<C>(&self, cx: C) -> Vec<(MacroDefinition<'_>, Result<GeneratedMacro, GenerateError>)>
+  where
+    C: CodegenContext + Sync,
+  {
+    self.map_definitions(|memo, definition| {
+      let name = definition.name();
+      let location = definition.location().cloned();
+
+      match self.expand_with_memo(definition, memo)? {
+        Expansion::Fn(args, body) => {
+          let mut fn_macro = match location {
+            Some(location) => FnMacro::parse_with_location(name, &args, &body, location)?,
+            None => FnMacro::parse(name, &args, &body)?,
+          };
+
+          Ok(GeneratedMacro::Fn(fn_macro.generate(&cx)?.to_string()))
+        },
+        Expansion::Var(value) => {
+          let mut var_macro = match location {
+            Some(location) => VarMacro::parse_with_location(name, &value, location)?,
+            None => VarMacro::parse(name, &value)?,
+          };
+
+          let (value, ty) = var_macro.generate(&cx)?;
+          Ok(GeneratedMacro::Var(value.to_string(), ty.map(|ty| ty.to_string())))
+        },
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    ast::{arg, id, lit_int, punct},
+    macro_token::*,
+    SourceLocation,
+  };
+
+  #[test]
+  fn send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<MacroSet>();
+    assert_send_sync::<MacroDefinition<'_>>();
+    assert_send_sync::<Expansion<'_>>();
+  }
+
+  #[test]
+  fn expand_all() {
+    let mut macro_set = MacroSet::new();
+
+    for i in 0..1000 {
+      macro_set.define_var_macro(format!("V{i}"), ["F".into(), "(".into(), i.to_string(), ")".into()]).unwrap();
+    }
+    macro_set.define_fn_macro("F", ["x"], ["x", "+", "x"]).unwrap();
+    macro_set.define_var_macro("BAD", ["F", "(", "1", ",", "2", ")"]).unwrap();
+
+    let expansions = macro_set.expand_all();
+    assert_eq!(expansions.len(), 1002);
+
+    for (definition, expansion) in &expansions {
+      let expected = match definition.is_function_like() {
+        true => macro_set.expand_fn_macro(definition.name()).map(|(args, body)| Expansion::Fn(args, body)),
+        false => macro_set.expand_var_macro(definition.name()).map(Expansion::Var),
+      };
+      assert_eq!(*expansion, expected);
+    }
+
+    let names = expansions.iter().map(|(definition, _)| definition.name()).collect::<Vec<_>>();
+    assert_eq!(names, macro_set.definitions().map(|definition| definition.name()).collect::<Vec<_>>());
+    assert!(expansions[1001].1.is_err());
+  }
+
+  #[test]
+  fn expand_all_counter() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("NEXT", ["__COUNTER__"]).unwrap();
+    for i in 0..100 {
+      macro_set.define_var_macro(format!("PAIR{i}"), ["NEXT", "+", "__COUNTER__"]).unwrap();
+    }
+    macro_set.define_fn_macro("F", ["x"], ["x", "+", "NEXT", "+", "NEXT"]).unwrap();
+
+    // Each macro is expanded as if it were expanded first.
+    let expansions = macro_set.expand_all();
+    assert_eq!(expansions[0].1, Ok(Expansion::Var(macro_set.expand(&["0"]).unwrap())));
+    for (_, expansion) in &expansions[1..101] {
+      assert_eq!(*expansion, Ok(Expansion::Var(macro_set.expand(&["0", "+", "1"]).unwrap())));
+    }
+    let body = tokens![arg!(0), punct!("+"), lit_int!(0), punct!("+"), lit_int!(1)];
+    assert_eq!(expansions[101].1, Ok(Expansion::Fn(tokens![id!(x)], body)));
+
+    for _ in 0..10 {
+      assert_eq!(macro_set.expand_all(), expansions);
+    }
+
+    // The configured first value is used.
+    macro_set.set_counter(1);
+    let expansions = macro_set.expand_all();
+    assert_eq!(expansions[1].1, Ok(Expansion::Var(macro_set.expand(&["1", "+", "2"]).unwrap())));
+  }
+
+  #[test]
+  fn generate_all() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    macro_set.define_fn_macro("NEG", ["x"], ["-", "x"]).unwrap();
+    let stmt = ["do", "{", "}", "while", "(", "0", ")"];
+    macro_set.define_var_macro_with_location("STMT", stmt, SourceLocation::new(3, 9)).unwrap();
+    macro_set.define_var_macro("BAD", ["NEG", "(", "1", ",", "2", ")"]).unwrap();
+
+    let generated = macro_set.generate_all(());
+    let names = generated.iter().map(|(definition, _)| definition.name()).collect::<Vec<_>>();
+    assert_eq!(names, ["ONE", "NEG", "STMT", "BAD"]);
+
+    assert_eq!(generated[0].1, Ok(GeneratedMacro::Var("1".into(), None)));
+    let (args, body) = macro_set.expand_fn_macro("NEG").unwrap();
+    let fn_macro = FnMacro::parse("NEG", &args, &body).unwrap().generate(()).map(|f| GeneratedMacro::Fn(f.to_string()));
+    assert_eq!(generated[1].1, fn_macro.map_err(GenerateError::from));
+    assert_eq!(
+      generated[2].1,
+      Err(GenerateError::Codegen(CodegenError::NonExpressionVarMacro.with_location(SourceLocation::new(3, 9))))
+    );
+    assert!(matches!(generated[3].1, Err(GenerateError::Expansion(_))));
+  }
+}
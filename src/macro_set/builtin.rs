@@ -0,0 +1,155 @@
+use std::{
+  borrow::Cow,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{ExpansionState, MacroSet, Token};
+use crate::ast::{Lit, LitInt, LitString};
+
+/// Names of built-in macros expanded by a [`MacroSet`].
+pub(super) const BUILTIN_MACROS: [&str; 3] = ["__COUNTER__", "__DATE__", "__TIME__"];
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Convert days since the Unix epoch to a year, month and day.
+fn civil_from_days(days: u64) -> (u64, usize, u64) {
+  // See https://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+  let z = days + 719_468;
+  let era = z / 146_097;
+  let doe = z - era * 146_097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + u64::from(month <= 2);
+
+  (year, month as usize, day)
+}
+
+fn string_literal(s: String) -> Token<'static> {
+  let lit = Lit::String(LitString::Ordinary(Cow::Owned(s.clone().into_bytes())));
+  Token::Literal(lit, Cow::Owned(format!("\"{s}\"")))
+}
+
+impl MacroSet {
+  /// Set the date and time used for `__DATE__` and `__TIME__`.
+  ///
+  /// By default, the Unix epoch is used, i.e. `__DATE__` expands to `"Jan  1 1970"`
+  /// and `__TIME__` expands to `"00:00:00"`, so that expansions are reproducible.
+  /// Built-in macros are not expanded if a macro with the same name is defined.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use std::time::{Duration, UNIX_EPOCH};
+  ///
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.set_timestamp(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+  ///
+  /// assert_eq!(macro_set.expand(&["__DATE__"])?, macro_set.expand(&["\"Nov 14 2023\""])?);
+  /// assert_eq!(macro_set.expand(&["__TIME__"])?, macro_set.expand(&["\"22:13:20\""])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_timestamp(&mut self, timestamp: SystemTime) {
+    self.timestamp = timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    self.cache.clear();
+  }
+
+  /// Set the first value of `__COUNTER__` in each expansion.
+  ///
+  /// Expanding macros does not modify a macro set, so `__COUNTER__` is incremented
+  /// on each use within a single expansion, starting at this value, which is `0` by default.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// assert_eq!(macro_set.expand(&["__COUNTER__", "__COUNTER__"])?, macro_set.expand(&["0", "1"])?);
+  ///
+  /// macro_set.set_counter(2);
+  /// assert_eq!(macro_set.expand(&["__COUNTER__", "__COUNTER__"])?, macro_set.expand(&["2", "3"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_counter(&mut self, value: usize) {
+    self.counter = value;
+  }
+
+  /// Expand a built-in macro, i.e. `__COUNTER__`, `__DATE__` or `__TIME__`.
+  ///
+  /// `__LINE__` and `__FILE__` are not expanded since they depend on where the
+  /// macro is used, see [`VarMacro`](crate::VarMacro) and [`FnMacro`](crate::FnMacro).
+  ///
+  /// `__COUNTER__` is incremented on each use within the given state.
+  pub(super) fn expand_builtin(&self, name: &str, state: &mut ExpansionState<'_>) -> Option<Token<'static>> {
+    Some(match name {
+      "__COUNTER__" => {
+        state.dependencies.set_volatile();
+
+        let counter = state.counter.get_or_insert(self.counter);
+        let value = *counter;
+        *counter += 1;
+
+        Token::Literal(Lit::Int(LitInt { value: value as i128, suffix: None }), Cow::Owned(value.to_string()))
+      },
+      "__DATE__" => {
+        let (year, month, day) = civil_from_days(self.timestamp / 86400);
+        string_literal(format!("{} {:2} {}", MONTHS[month - 1], day, year))
+      },
+      "__TIME__" => {
+        let seconds = self.timestamp % 86400;
+        string_literal(format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60))
+      },
+      _ => return None,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{ast::lit_string, macro_token::*};
+
+  #[test]
+  fn counter() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("CAT", ["a", "b"], ["a", "##", "b"]).unwrap();
+    macro_set.define_fn_macro("XCAT", ["a", "b"], ["CAT", "(", "a", ",", "b", ")"]).unwrap();
+    macro_set.define_var_macro("UNIQUE", ["XCAT", "(", "var_", ",", "__COUNTER__", ")"]).unwrap();
+
+    assert_eq!(macro_set.expand(&["__COUNTER__", "__COUNTER__"]), macro_set.expand(&["0", "1"]));
+    assert_eq!(macro_set.expand(&["UNIQUE", "UNIQUE"]), macro_set.expand(&["var_0", "var_1"]));
+    assert_eq!(macro_set.expand(&["CAT", "(", "x", ",", "__COUNTER__", ")"]), macro_set.expand(&["x__COUNTER__"]));
+
+    // Each expansion starts at the configured value.
+    macro_set.set_counter(5);
+    assert_eq!(macro_set.expand(&["UNIQUE"]), macro_set.expand(&["var_5"]));
+    assert_eq!(macro_set.expand(&["UNIQUE"]), macro_set.expand(&["var_5"]));
+  }
+
+  #[test]
+  fn date_time() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.expand(&["__DATE__"]), Ok(tokens![lit_string!("Jan  1 1970")]));
+    assert_eq!(macro_set.expand(&["__TIME__"]), Ok(tokens![lit_string!("00:00:00")]));
+
+    macro_set.set_timestamp(UNIX_EPOCH + Duration::from_secs(951_825_599));
+    assert_eq!(macro_set.expand(&["__DATE__"]), Ok(tokens![lit_string!("Feb 29 2000")]));
+    assert_eq!(macro_set.expand(&["__TIME__"]), Ok(tokens![lit_string!("11:59:59")]));
+
+    macro_set.define_var_macro("__DATE__", ["0"]).unwrap();
+    assert_eq!(macro_set.expand(&["__DATE__"]), macro_set.expand(&["0"]));
+  }
+}
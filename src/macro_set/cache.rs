@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{traced, untraced, ExpansionError, ExpansionState, MacroSet, Token, Trace};
+
+/// The names looked up while expanding a macro.
+#[derive(Debug, Default)]
+pub(super) struct Dependencies {
+  names: HashSet<String>,
+  /// Whether the expansion uses `__COUNTER__`, i.e. must not be cached.
+  volatile: bool,
+  /// Whether an argument was stringified, which depends on the enclosing expansions.
+  stringified: bool,
+}
+
+impl Dependencies {
+  /// Dependencies of the expansion of the given macro, which depends on its own definition.
+  pub fn new(name: &str) -> Self {
+    let mut dependencies = Self::default();
+    dependencies.insert(name);
+    dependencies
+  }
+
+  /// Record that the given name was looked up.
+  pub fn insert(&mut self, name: &str) {
+    if !self.names.contains(name) {
+      self.names.insert(name.to_owned());
+    }
+  }
+
+  /// Record that the expansion must not be cached.
+  pub fn set_volatile(&mut self) {
+    self.volatile = true;
+  }
+
+  /// Record that an argument was stringified.
+  pub fn set_stringified(&mut self) {
+    self.stringified = true;
+  }
+
+  /// Add the dependencies of a cached expansion.
+  fn extend(&mut self, entry: &Entry) {
+    for name in &entry.dependencies {
+      self.insert(name);
+    }
+  }
+}
+
+/// A cached expansion.
+#[derive(Debug, Clone)]
+struct Entry {
+  tokens: Vec<Token<'static>>,
+  dependencies: HashSet<String>,
+  /// Whether the expansion can be reused inside of other expansions.
+  nestable: bool,
+}
+
+/// Cached expansions along with the names they depend on.
+///
+/// Shared by a [`MacroSet`] through an [`ExpansionCache`], or used as a local memo
+/// during [`MacroSet::expand_all`] and [`MacroSet::generate_all`].
+#[derive(Debug, Clone, Default)]
+pub(super) struct Entries {
+  entries: HashMap<String, Entry>,
+  /// The names of the cached expansions depending on each name.
+  dependents: HashMap<String, HashSet<String>>,
+}
+
+impl Entries {
+  /// Get the cached expansion of a macro.
+  pub fn get(&self, name: &str) -> Option<Vec<Token<'static>>> {
+    self.entries.get(name).map(|entry| entry.tokens.clone())
+  }
+
+  /// Get the cached expansion of a variable-like macro for use inside of another
+  /// expansion, which is only possible if it does not look up any of the names
+  /// which are currently not replaced.
+  pub fn get_nested(
+    &self,
+    name: &str,
+    non_replaced_names: &HashSet<&str>,
+    dependencies: &mut Dependencies,
+  ) -> Option<Vec<Token<'static>>> {
+    let entry = self.entries.get(name)?;
+
+    if !entry.nestable || non_replaced_names.iter().any(|n| *n != name && entry.dependencies.contains(*n)) {
+      return None
+    }
+
+    dependencies.extend(entry);
+    Some(entry.tokens.clone())
+  }
+
+  /// Cache the expansion of a macro.
+  pub fn insert(&mut self, name: &str, tokens: Vec<Token<'static>>, dependencies: Dependencies) {
+    if dependencies.volatile {
+      return
+    }
+
+    self.remove(name);
+
+    for dependency in &dependencies.names {
+      self.dependents.entry(dependency.clone()).or_default().insert(name.to_owned());
+    }
+
+    let entry = Entry { tokens, dependencies: dependencies.names, nestable: !dependencies.stringified };
+    self.entries.insert(name.to_owned(), entry);
+  }
+
+  fn remove(&mut self, name: &str) {
+    if let Some(entry) = self.entries.remove(name) {
+      for dependency in &entry.dependencies {
+        if let Some(dependents) = self.dependents.get_mut(dependency) {
+          dependents.remove(name);
+
+          if dependents.is_empty() {
+            self.dependents.remove(dependency);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// The cached expansions of a [`MacroSet`], keyed by macro name.
+///
+/// Only expansions without arguments are cached, i.e. [`MacroSet::expand_var_macro`]
+/// and [`MacroSet::expand_fn_macro`]. Each entry is invalidated when one of the
+/// names looked up during its expansion is defined or undefined.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ExpansionCache(Entries);
+
+impl ExpansionCache {
+  /// Get the cached expansion of a macro.
+  pub fn get(&self, name: &str) -> Option<Vec<Token<'static>>> {
+    self.0.get(name)
+  }
+
+  /// Get the cached expansion of a variable-like macro for use inside of another
+  /// expansion, see [`Entries::get_nested`].
+  pub fn get_nested(
+    &self,
+    name: &str,
+    non_replaced_names: &HashSet<&str>,
+    dependencies: &mut Dependencies,
+  ) -> Option<Vec<Token<'static>>> {
+    self.0.get_nested(name, non_replaced_names, dependencies)
+  }
+
+  /// Remove all cached expansions depending on the given name.
+  pub fn invalidate(&mut self, name: &str) {
+    if let Some(dependents) = self.0.dependents.remove(name) {
+      for dependent in dependents {
+        self.0.remove(&dependent);
+      }
+    }
+  }
+
+  /// Remove all cached expansions.
+  pub fn clear(&mut self) {
+    self.0 = Entries::default();
+  }
+
+  #[cfg(test)]
+  fn contains(&self, name: &str) -> bool {
+    self.0.entries.contains_key(name)
+  }
+}
+
+impl MacroSet {
+  /// Expand all defined macros and cache the results.
+  ///
+  /// Expanding macros does not modify a macro set, so that it can be shared between
+  /// threads. Instead, cached expansions are only added by this method. Afterwards,
+  /// [`MacroSet::expand_var_macro`] and [`MacroSet::expand_fn_macro`] return the cached
+  /// expansions, and cached expansions of variable-like macros are reused when expanding
+  /// other macros, until any name they looked up is defined or undefined.
+  ///
+  /// Macros are expanded in dependency order where possible, so each shared macro is
+  /// only expanded once. Expansions using `__COUNTER__` are not cached.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_var_macro("ONE", ["1"])?;
+  /// macro_set.define_var_macro("TWO", ["ONE", "+", "ONE"])?;
+  /// macro_set.cache_expansions();
+  ///
+  /// assert_eq!(macro_set.expand_var_macro("TWO")?, macro_set.expand(&["1", "+", "1"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn cache_expansions(&mut self) {
+    let names = match self.dependency_graph().topological_order() {
+      Ok(names) => names.into_iter().map(|name| name.to_owned()).collect::<Vec<_>>(),
+      Err(_) => self.definition_names().into_iter().map(|name| name.to_owned()).collect(),
+    };
+
+    let mut memo = self.cache.0.clone();
+    for name in names {
+      if !memo.entries.contains_key(&name) {
+        let _ = self.expansion(&name, ExpansionState::local(&name, &mut memo));
+      }
+    }
+
+    self.cache.0 = memo;
+  }
+
+  /// Get the expansion of a macro without arguments, from the cache if possible.
+  ///
+  /// The shared cache is only read. The expansion is added to the local memo of
+  /// the given state if it has one.
+  pub(super) fn expansion(
+    &self,
+    name: &str,
+    mut state: ExpansionState<'_>,
+  ) -> Result<Vec<Token<'static>>, ExpansionError> {
+    let cached = match &state.memo {
+      Some(memo) => memo.get(name),
+      None => None,
+    };
+    if let Some(tokens) = cached.or_else(|| self.cache.get(name)) {
+      return Ok(tokens)
+    }
+
+    let tokens = if let Some((name, var_macro)) = self.var_macro(name) {
+      let body = traced(var_macro.tokens.clone(), &Trace::default());
+      self.expand_var_macro_body(HashSet::new(), name, &body, &mut state)
+    } else if let Some((name, fn_macro)) = self.fn_macro(name) {
+      let body = traced(fn_macro.tokens.clone(), &Trace::default());
+      self.expand_fn_macro_body(HashSet::new(), name, &fn_macro.arg_tokens, None, &body, &mut state)
+    } else {
+      return Err(ExpansionError::MacroNotFound)
+    };
+    let tokens = untraced(tokens.map_err(|err| self.locate(name, err))?);
+
+    if let Some(memo) = state.memo {
+      memo.insert(name, tokens.clone(), state.dependencies);
+    }
+
+    Ok(tokens)
+  }
+
+  /// Invalidate the cached expansions depending on the given macro name.
+  pub(super) fn invalidate(&mut self, name: &str) {
+    self.cache.invalidate(name);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    ast::{id, punct},
+    macro_token::*,
+  };
+
+  #[test]
+  fn cache_invalidation() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", ["B", "+", "C"]).unwrap();
+    macro_set.define_var_macro("B", ["1"]).unwrap();
+    macro_set.define_var_macro("D", ["2"]).unwrap();
+    macro_set.define_fn_macro("F", ["x"], ["x", "*", "B"]).unwrap();
+
+    // Expanding does not modify the set.
+    assert_eq!(macro_set.expand_var_macro("A"), macro_set.expand(&["1", "+", "C"]));
+    assert!(!macro_set.cache.contains("A"));
+
+    macro_set.cache_expansions();
+    assert_eq!(macro_set.expand_var_macro("A"), macro_set.expand(&["1", "+", "C"]));
+    assert!(macro_set.expand_fn_macro("F").is_ok());
+    assert_eq!(macro_set.expand_var_macro("D"), macro_set.expand(&["2"]));
+    assert!(macro_set.cache.contains("A") && macro_set.cache.contains("F") && macro_set.cache.contains("D"));
+
+    // Defining a name which was looked up but undefined invalidates the expansion.
+    macro_set.define_var_macro("C", ["3"]).unwrap();
+    assert!(!macro_set.cache.contains("A") && macro_set.cache.contains("F") && macro_set.cache.contains("D"));
+    assert_eq!(macro_set.expand_var_macro("A"), macro_set.expand(&["1", "+", "3"]));
+
+    macro_set.cache_expansions();
+    macro_set.undefine_var_macro("B");
+    assert!(!macro_set.cache.contains("A") && !macro_set.cache.contains("F") && macro_set.cache.contains("D"));
+    assert_eq!(macro_set.expand_var_macro("A"), macro_set.expand(&["B", "+", "3"]));
+
+    // Redefining a macro invalidates its own expansion.
+    macro_set.define_var_macro("D", ["4"]).unwrap();
+    assert_eq!(macro_set.expand_var_macro("D"), macro_set.expand(&["4"]));
+
+    macro_set.cache_expansions();
+    macro_set.push_macro("D");
+    macro_set.define_var_macro("D", ["5"]).unwrap();
+    assert_eq!(macro_set.expand_var_macro("D"), macro_set.expand(&["5"]));
+    macro_set.cache_expansions();
+    macro_set.pop_macro("D");
+    assert_eq!(macro_set.expand_var_macro("D"), macro_set.expand(&["4"]));
+  }
+
+  #[test]
+  fn cache_nested() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("B", ["C", "(", "A", ")"]).unwrap();
+    macro_set.define_var_macro("A", ["B", "+", "B"]).unwrap();
+    macro_set.define_fn_macro("C", ["x"], ["x"]).unwrap();
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    macro_set.define_var_macro("TWO", ["ONE", "+", "ONE"]).unwrap();
+    macro_set.define_fn_macro("STR", ["x"], ["#", "x"]).unwrap();
+    macro_set.define_var_macro("S", ["STR", "(", "__LINE__", ")"]).unwrap();
+    macro_set.define_var_macro("T", ["S"]).unwrap();
+    let uncached = macro_set.clone();
+    macro_set.cache_expansions();
+
+    // `A` is not replaced inside of its own expansion, so the cached expansion of `B` cannot be used.
+    assert_eq!(macro_set.expand_var_macro("B"), Ok(tokens![id!(B), punct!("+"), id!(B)]));
+    assert_eq!(macro_set.expand_var_macro("A"), Ok(tokens![id!(A), punct!("+"), id!(A)]));
+
+    assert_eq!(macro_set.expand_var_macro("ONE"), macro_set.expand(&["1"]));
+    assert_eq!(macro_set.expand_var_macro("TWO"), macro_set.expand(&["1", "+", "1"]));
+    assert!(macro_set.cache.0.dependents["ONE"].contains("TWO"));
+
+    // Stringification depends on the enclosing expansions.
+    assert_eq!(macro_set.expand_var_macro("S"), macro_set.expand(&["\"__LINE__\""]));
+    assert_eq!(macro_set.expand_var_macro("T"), uncached.expand_var_macro("T"));
+  }
+
+  #[test]
+  fn cache_counter() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("NEXT", ["__COUNTER__"]).unwrap();
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    macro_set.define_var_macro("NEXT_ONE", ["ONE", "+", "NEXT"]).unwrap();
+    macro_set.cache_expansions();
+
+    assert_eq!(macro_set.expand_var_macro("NEXT"), macro_set.expand(&["0"]));
+    assert_eq!(macro_set.expand_var_macro("NEXT"), macro_set.expand(&["0"]));
+    assert_eq!(macro_set.expand_var_macro("ONE"), macro_set.expand(&["1"]));
+    assert_eq!(macro_set.expand_var_macro("NEXT_ONE"), macro_set.expand(&["1", "+", "0"]));
+    assert!(
+      !macro_set.cache.contains("NEXT") && !macro_set.cache.contains("NEXT_ONE") && macro_set.cache.contains("ONE")
+    );
+  }
+
+  #[test]
+  fn cache_chain() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A0", ["0"]).unwrap();
+    for i in 1..=500 {
+      macro_set.define_var_macro(format!("A{i}"), [format!("A{}", i - 1)]).unwrap();
+    }
+
+    // Each expansion reuses the previous one.
+    macro_set.cache_expansions();
+    for i in 0..=500 {
+      assert_eq!(macro_set.expand_var_macro(&format!("A{i}")), macro_set.expand(&["0"]));
+    }
+    assert_eq!(macro_set.cache.0.dependents["A0"].len(), 501);
+
+    macro_set.define_var_macro("A0", ["1"]).unwrap();
+    assert!(macro_set.cache.0.entries.is_empty());
+    assert_eq!(macro_set.expand_var_macro("A500"), macro_set.expand(&["1"]));
+  }
+}
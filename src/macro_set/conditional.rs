@@ -0,0 +1,255 @@
+use super::{ExpansionError, MacroSet};
+
+/// State of a conditional directive, i.e. an `#if`/`#ifdef`/`#ifndef` and its
+/// corresponding `#elif`/`#else`/`#endif` directives.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Conditional {
+  /// Whether the group containing this conditional is active.
+  parent_active: bool,
+  /// Whether one of the groups of this conditional was already taken.
+  taken: bool,
+  /// Whether the current group is active.
+  active: bool,
+  /// Whether an `#else` directive was encountered.
+  has_else: bool,
+}
+
+impl Conditional {
+  fn new(parent_active: bool, active: bool) -> Self {
+    Self { parent_active, taken: active, active, has_else: false }
+  }
+}
+
+impl MacroSet {
+  /// Check whether the current conditional group is active.
+  ///
+  /// Macros are only defined or undefined while the current group is active.
+  pub fn is_active(&self) -> bool {
+    self.conditionals.last().map(|c| c.active).unwrap_or(true)
+  }
+
+  fn push_conditional<F>(&mut self, condition: F) -> Result<bool, ExpansionError>
+  where
+    F: FnOnce(&Self) -> Result<bool, ExpansionError>,
+  {
+    let parent_active = self.is_active();
+
+    // Conditions inside of inactive groups are not evaluated.
+    let result = if parent_active { condition(self) } else { Ok(false) };
+    let active = *result.as_ref().unwrap_or(&false);
+
+    self.conditionals.push(Conditional::new(parent_active, active));
+    result
+  }
+
+  fn next_conditional<F>(&mut self, condition: F) -> Result<bool, ExpansionError>
+  where
+    F: FnOnce(&Self) -> Result<bool, ExpansionError>,
+  {
+    let conditional = *self.conditionals.last().ok_or(ExpansionError::UnmatchedConditional)?;
+
+    if conditional.has_else {
+      return Err(ExpansionError::ConditionalAfterElse)
+    }
+
+    // Conditions are only evaluated if no previous group was taken.
+    let result = if conditional.parent_active && !conditional.taken { condition(self) } else { Ok(false) };
+    let active = *result.as_ref().unwrap_or(&false);
+
+    if let Some(conditional) = self.conditionals.last_mut() {
+      conditional.active = active;
+      conditional.taken |= active;
+    }
+
+    result
+  }
+
+  /// Enter an `#if` directive.
+  ///
+  /// The condition is evaluated using the macros currently defined in this set,
+  /// including the `defined X` and `defined(X)` operators.
+  ///
+  /// Returns whether the following group is active. If the condition cannot
+  /// be evaluated, the group is treated as inactive and an error is returned.
+  /// While inside of an inactive group, macros are neither defined nor undefined.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_var_macro("VERSION", &["3"])?;
+  ///
+  /// assert!(macro_set.if_directive(&["defined", "(", "VERSION", ")", "&&", "VERSION", ">=", "2"])?);
+  /// macro_set.define_var_macro("HAS_FEATURE", &["1"])?;
+  /// assert!(!macro_set.else_directive()?);
+  /// macro_set.define_var_macro("HAS_FEATURE", &["0"])?;
+  /// macro_set.endif_directive()?;
+  ///
+  /// assert_eq!(macro_set.expand(&["HAS_FEATURE"])?, macro_set.expand(&["1"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn if_directive<T>(&mut self, condition: &[T]) -> Result<bool, ExpansionError>
+  where
+    T: AsRef<str>,
+  {
+    self.push_conditional(|macro_set| macro_set.evaluate_condition(condition).map(|value| value.as_bool()))
+  }
+
+  /// Enter an `#ifdef` directive.
+  ///
+  /// Returns whether the following group is active.
+  pub fn ifdef_directive(&mut self, name: &str) -> bool {
+    self.push_conditional(|macro_set| Ok(macro_set.is_defined(name))).unwrap_or(false)
+  }
+
+  /// Enter an `#ifndef` directive.
+  ///
+  /// Returns whether the following group is active.
+  pub fn ifndef_directive(&mut self, name: &str) -> bool {
+    self.push_conditional(|macro_set| Ok(!macro_set.is_defined(name))).unwrap_or(false)
+  }
+
+  /// Enter an `#elif` directive.
+  ///
+  /// Returns whether the following group is active. The condition is only
+  /// evaluated if no previous group of the current conditional was active.
+  pub fn elif_directive<T>(&mut self, condition: &[T]) -> Result<bool, ExpansionError>
+  where
+    T: AsRef<str>,
+  {
+    self.next_conditional(|macro_set| macro_set.evaluate_condition(condition).map(|value| value.as_bool()))
+  }
+
+  /// Enter an `#else` directive.
+  ///
+  /// Returns whether the following group is active.
+  pub fn else_directive(&mut self) -> Result<bool, ExpansionError> {
+    let active = self.next_conditional(|_| Ok(true))?;
+
+    if let Some(conditional) = self.conditionals.last_mut() {
+      conditional.has_else = true;
+    }
+
+    Ok(active)
+  }
+
+  /// Leave the current conditional with an `#endif` directive.
+  ///
+  /// Returns whether the enclosing group is active.
+  pub fn endif_directive(&mut self) -> Result<bool, ExpansionError> {
+    self.conditionals.pop().ok_or(ExpansionError::UnmatchedConditional)?;
+    Ok(self.is_active())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ConditionError, Redefinition};
+
+  #[test]
+  fn ifdef_ifndef() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", ["1"]).unwrap();
+
+    assert!(macro_set.ifdef_directive("A"));
+    assert!(macro_set.ifndef_directive("B"));
+    macro_set.define_var_macro("B", ["2"]).unwrap();
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+
+    assert!(!macro_set.ifndef_directive("B"));
+    assert_eq!(macro_set.define_var_macro("C", ["3"]), Ok(Redefinition::Skipped));
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+
+    assert!(macro_set.is_defined("B"));
+    assert!(!macro_set.is_defined("C"));
+  }
+
+  #[test]
+  fn if_elif_else() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("VERSION", ["2"]).unwrap();
+
+    assert_eq!(macro_set.if_directive(&["VERSION", "==", "1"]), Ok(false));
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    assert_eq!(macro_set.elif_directive(&["VERSION", "==", "2"]), Ok(true));
+    macro_set.define_var_macro("TWO", ["2"]).unwrap();
+    assert_eq!(macro_set.elif_directive(&["VERSION", ">=", "2"]), Ok(false));
+    macro_set.define_var_macro("TWO_OR_MORE", ["2"]).unwrap();
+    assert_eq!(macro_set.else_directive(), Ok(false));
+    macro_set.define_var_macro("OTHER", ["0"]).unwrap();
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+
+    assert!(!macro_set.is_defined("ONE"));
+    assert!(macro_set.is_defined("TWO"));
+    assert!(!macro_set.is_defined("TWO_OR_MORE"));
+    assert!(!macro_set.is_defined("OTHER"));
+  }
+
+  #[test]
+  fn if_defined() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", [] as [&str; 0]).unwrap();
+    macro_set.define_fn_macro("F", ["x"], ["x"]).unwrap();
+
+    assert_eq!(macro_set.if_directive(&["defined", "A"]), Ok(true));
+    assert_eq!(macro_set.if_directive(&["defined", "(", "F", ")", "&&", "!", "defined", "B"]), Ok(true));
+    assert_eq!(macro_set.if_directive(&["defined", "(", "B", ")", "||", "defined", "C"]), Ok(false));
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+    assert_eq!(
+      macro_set.if_directive(&["defined"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidDefined))
+    );
+    assert!(!macro_set.is_active());
+  }
+
+  #[test]
+  fn if_expand() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("MAJOR", ["1"]).unwrap();
+    macro_set.define_var_macro("MINOR", ["12"]).unwrap();
+    macro_set
+      .define_fn_macro("VERSION", ["major", "minor"], ["(", "(", "major", ")", "*", "100", "+", "(", "minor", ")", ")"])
+      .unwrap();
+
+    assert_eq!(macro_set.if_directive(&["VERSION", "(", "MAJOR", ",", "MINOR", ")", ">", "111"]), Ok(true));
+    assert_eq!(macro_set.if_directive(&["UNDEFINED", "==", "0"]), Ok(true));
+    assert_eq!(macro_set.if_directive(&["MAJOR", "?", "MINOR", ":", "0"]), Ok(true));
+  }
+
+  #[test]
+  fn nested_inactive() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.if_directive(&["0"]), Ok(false));
+    // Conditions inside inactive groups are not evaluated.
+    assert_eq!(macro_set.if_directive(&["1", "/", "0"]), Ok(false));
+    assert_eq!(macro_set.else_directive(), Ok(false));
+    assert_eq!(macro_set.endif_directive(), Ok(false));
+    assert_eq!(macro_set.else_directive(), Ok(true));
+    assert_eq!(macro_set.endif_directive(), Ok(true));
+  }
+
+  #[test]
+  fn unbalanced() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.endif_directive(), Err(ExpansionError::UnmatchedConditional));
+    assert_eq!(macro_set.else_directive(), Err(ExpansionError::UnmatchedConditional));
+    assert_eq!(macro_set.elif_directive(&["1"]), Err(ExpansionError::UnmatchedConditional));
+
+    assert_eq!(macro_set.if_directive(&["1"]), Ok(true));
+    assert_eq!(macro_set.else_directive(), Ok(false));
+    assert_eq!(macro_set.else_directive(), Err(ExpansionError::ConditionalAfterElse));
+    assert_eq!(macro_set.elif_directive(&["1"]), Err(ExpansionError::ConditionalAfterElse));
+  }
+}
@@ -0,0 +1,322 @@
+use std::borrow::Cow;
+
+use super::{tokenize, tokenize_arg_names, ExpansionError, MacroSet, Token};
+use crate::{ast::Comment, SourceLocation};
+
+impl Token<'_> {
+  fn into_static(self) -> Token<'static> {
+    match self {
+      Self::NonReplacable(token) => Token::NonReplacable(Box::new(token.into_static())),
+      Self::MacroArg(index) => Token::MacroArg(index),
+      Self::VarArgs => Token::VarArgs,
+      Self::VarOpt => Token::VarOpt,
+      Self::Punctuation(p) => Token::Punctuation(p.to_static()),
+      Self::Identifier(id) => Token::Identifier(id.to_static()),
+      Self::IdentifierContinue(id_cont) => Token::IdentifierContinue(id_cont.into_static()),
+      Self::Literal(lit, s) => Token::Literal(lit.into_static(), Cow::Owned(s.into_owned())),
+      Self::Plain(s) => Token::Plain(Cow::Owned(s.into_owned())),
+      Self::Comment(comment) => Token::Comment(Comment { comment: Cow::Owned(comment.comment.into_owned()) }),
+      Self::Placemarker => Token::Placemarker,
+    }
+  }
+}
+
+/// Tokenize a macro body, ignoring whitespace.
+fn tokenize_body(arg_names: &[Token<'_>], body: &[String]) -> Result<Vec<Token<'static>>, ExpansionError> {
+  let body = body.iter().filter(|t| !t.trim().is_empty()).collect::<Vec<_>>();
+
+  tokenize(arg_names, &body)
+    .into_iter()
+    .map(|token| match token {
+      Token::Plain(_) => Err(ExpansionError::InvalidToken),
+      token => Ok(token.into_static()),
+    })
+    .collect()
+}
+
+/// A variable-like macro, tokenized when it is defined.
+#[derive(Debug, Clone)]
+pub(super) struct VarMacroDef {
+  /// The body as given when defining the macro.
+  pub body: Vec<String>,
+  /// The tokenized body.
+  pub tokens: Vec<Token<'static>>,
+}
+
+impl VarMacroDef {
+  pub fn new(body: Vec<String>) -> Result<Self, ExpansionError> {
+    let tokens = tokenize_body(&[], &body)?;
+    Ok(Self { body, tokens })
+  }
+
+  /// The public view of this definition.
+  pub fn definition<'s>(&'s self, name: &'s str, location: Option<&'s SourceLocation>) -> MacroDefinition<'s> {
+    MacroDefinition { name, args: None, body: &self.body, location }
+  }
+}
+
+/// A function-like macro, tokenized when it is defined.
+#[derive(Debug, Clone)]
+pub(super) struct FnMacroDef {
+  /// The parameters as given when defining the macro.
+  pub args: Vec<String>,
+  /// The body as given when defining the macro.
+  pub body: Vec<String>,
+  /// The tokenized parameters.
+  pub arg_tokens: Vec<Token<'static>>,
+  /// The tokenized body.
+  pub tokens: Vec<Token<'static>>,
+}
+
+impl FnMacroDef {
+  pub fn new(args: Vec<String>, body: Vec<String>, gnu_extensions: bool) -> Result<Self, ExpansionError> {
+    let (arg_tokens, var_args_name) = tokenize_arg_names(&args, gnu_extensions)?;
+    let mut tokens = tokenize_body(&arg_tokens, &body)?;
+
+    // A named variadic parameter is an alias for `__VA_ARGS__`.
+    if let Some(var_args_name) = var_args_name {
+      for token in tokens.iter_mut() {
+        if matches!(token, Token::Identifier(id) if id.as_str() == var_args_name) {
+          *token = Token::VarArgs;
+        }
+      }
+    }
+
+    let arg_tokens = arg_tokens.into_iter().map(|token| token.into_static()).collect();
+
+    Ok(Self { args, body, arg_tokens, tokens })
+  }
+
+  /// The public view of this definition.
+  pub fn definition<'s>(&'s self, name: &'s str, location: Option<&'s SourceLocation>) -> MacroDefinition<'s> {
+    MacroDefinition { name, args: Some(&self.args), body: &self.body, location }
+  }
+}
+
+/// The kind of a macro, see [`MacroDefinition::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MacroKind {
+  /// A variable-like macro, e.g. `#define VAR 1`.
+  Var,
+  /// A function-like macro, e.g. `#define FUNC(x) x`.
+  Fn,
+}
+
+/// A macro defined in a [`MacroSet`], see [`MacroSet::definitions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MacroDefinition<'s> {
+  name: &'s str,
+  args: Option<&'s [String]>,
+  body: &'s [String],
+  location: Option<&'s SourceLocation>,
+}
+
+impl<'s> MacroDefinition<'s> {
+  /// The name of the macro.
+  pub fn name(&self) -> &'s str {
+    self.name
+  }
+
+  /// Whether the macro is variable-like or function-like.
+  pub fn kind(&self) -> MacroKind {
+    if self.args.is_some() {
+      MacroKind::Fn
+    } else {
+      MacroKind::Var
+    }
+  }
+
+  /// Check whether the macro is function-like.
+  pub fn is_function_like(&self) -> bool {
+    self.kind() == MacroKind::Fn
+  }
+
+  /// The parameters of a function-like macro as given when defining it,
+  /// or `None` for a variable-like macro.
+  pub fn args(&self) -> Option<&'s [String]> {
+    self.args
+  }
+
+  /// The body of the macro as given when defining it.
+  pub fn body(&self) -> &'s [String] {
+    self.body
+  }
+
+  /// The location of the definition, if known.
+  pub fn location(&self) -> Option<&'s SourceLocation> {
+    self.location
+  }
+}
+
+impl MacroSet {
+  /// Get the definition of the macro with the given name.
+  ///
+  /// Built-in macros like `__COUNTER__` are not included.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{MacroKind, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_fn_macro("ADD", ["a", "b"], ["a", "+", "b"])?;
+  ///
+  /// let definition = macro_set.definition("ADD").unwrap();
+  /// assert_eq!(definition.kind(), MacroKind::Fn);
+  /// assert_eq!(definition.args(), Some(&["a".to_owned(), "b".to_owned()][..]));
+  /// assert_eq!(definition.body(), ["a", "+", "b"]);
+  /// assert!(macro_set.definition("SUB").is_none());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn definition(&self, name: &str) -> Option<MacroDefinition<'_>> {
+    if let Some((name, var_macro)) = self.var_macro(name) {
+      return Some(var_macro.definition(name, self.location(name)))
+    }
+
+    let (name, fn_macro) = self.fn_macro(name)?;
+    Some(fn_macro.definition(name, self.location(name)))
+  }
+
+  /// Iterate over all defined macros, in the order they were first defined.
+  ///
+  /// Redefining a macro keeps its position, while undefining it removes it,
+  /// so defining it again afterwards moves it to the end. For an overlay
+  /// (see [`MacroSet::overlay`]), the macros visible from its parent come first.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_from_source("#define B 2\n#define A(x) x\n#define C 3\n#undef C\n#define C 4\n#define B 5")?;
+  ///
+  /// assert_eq!(macro_set.definitions().map(|definition| definition.name()).collect::<Vec<_>>(), ["B", "A", "C"]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn definitions(&self) -> impl Iterator<Item = MacroDefinition<'_>> {
+    self.definition_names().into_iter().filter_map(|name| self.definition(name))
+  }
+
+  /// The names of all defined macros, in definition order.
+  pub(super) fn definition_names(&self) -> Vec<&str> {
+    let mut names = match &self.parent {
+      Some(parent) => parent.definition_names().into_iter().filter(|name| self.definition(name).is_some()).collect(),
+      None => vec![],
+    };
+
+    let mut local_names = self
+      .order
+      .iter()
+      .filter(|(name, _)| self.parent.as_ref().map(|parent| parent.definition(name).is_none()).unwrap_or(true))
+      .collect::<Vec<_>>();
+    local_names.sort_unstable_by_key(|(_, order)| **order);
+    names.extend(local_names.into_iter().map(|(name, _)| name.as_str()));
+
+    names
+  }
+
+  /// The number of defined macros.
+  pub fn len(&self) -> usize {
+    match self.parent {
+      Some(_) => self.definition_names().len(),
+      None => self.var_macros.len() + self.fn_macros.len(),
+    }
+  }
+
+  /// Check whether no macros are defined.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Record the definition order of a macro, keeping it if already defined.
+  pub(super) fn insert_order(&mut self, name: &str) {
+    if !self.order.contains_key(name) {
+      self.order.insert(name.to_owned(), self.next_order);
+      self.next_order += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::MacroSet;
+
+  #[test]
+  fn invalid_token() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.define_var_macro("AT", ["@"]), Err(ExpansionError::InvalidToken));
+    assert_eq!(macro_set.define_fn_macro("F", ["x"], ["x", "`"]), Err(ExpansionError::InvalidToken));
+    assert_eq!(macro_set.define_fn_macro("G", ["x", "x"], ["x"]), Err(ExpansionError::NonUniqueArgument("x".into())));
+    assert!(!macro_set.is_defined("AT") && !macro_set.is_defined("F") && !macro_set.is_defined("G"));
+
+    assert_eq!(
+      macro_set.define_from_source("#define A 1\n#define B $"),
+      Err(ExpansionError::InvalidToken.with_location(crate::SourceLocation::new(2, 9)).into())
+    );
+    assert!(macro_set.is_defined("A") && !macro_set.is_defined("B"));
+  }
+
+  #[test]
+  fn tokenize_once() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(true);
+
+    macro_set.define_fn_macro("F", ["a", "args..."], ["a", " ", "+", "args"]).unwrap();
+    assert_eq!(macro_set.fn_macros["F"].tokens, [Token::MacroArg(0), Token::from_str("+"), Token::VarArgs]);
+    assert_eq!(macro_set.fn_macros["F"].body, ["a", " ", "+", "args"]);
+
+    // The body is still valid after the original strings are dropped.
+    let body = vec![String::from("F"), String::from("("), String::from("1"), String::from(")")];
+    macro_set.define_var_macro("X", &body).unwrap();
+    drop(body);
+    assert_eq!(macro_set.expand(&["X"]), macro_set.expand(&["1", "+"]));
+  }
+
+  #[test]
+  fn definitions() {
+    let mut macro_set = MacroSet::new();
+    assert!(macro_set.is_empty());
+
+    macro_set.define_var_macro("A", ["1"]).unwrap();
+    macro_set.define_fn_macro("B", ["x", "..."], ["x", "__VA_ARGS__"]).unwrap();
+    macro_set.define_var_macro_with_location("C", ["A"], SourceLocation::new(3, 9)).unwrap();
+    assert_eq!(macro_set.len(), 3);
+
+    let definitions = macro_set.definitions().collect::<Vec<_>>();
+    assert_eq!(definitions.iter().map(|d| d.name()).collect::<Vec<_>>(), ["A", "B", "C"]);
+    assert_eq!(definitions[0].kind(), MacroKind::Var);
+    assert_eq!(definitions[0].args(), None);
+    assert_eq!(definitions[0].body(), ["1"]);
+    assert!(definitions[1].is_function_like());
+    assert_eq!(definitions[1].args().unwrap(), ["x", "..."]);
+    assert_eq!(definitions[1].body(), ["x", "__VA_ARGS__"]);
+    assert_eq!(definitions[2].location(), Some(&SourceLocation::new(3, 9)));
+
+    // Changing the kind of a macro keeps its position.
+    macro_set.define_fn_macro("A", ["y"], ["y"]).unwrap();
+    macro_set.undefine_var_macro("B");
+    macro_set.undefine_fn_macro("B");
+    macro_set.define_var_macro("B", ["2"]).unwrap();
+    let names = macro_set.definitions().map(|d| (d.name(), d.kind())).collect::<Vec<_>>();
+    assert_eq!(names, [("A", MacroKind::Fn), ("C", MacroKind::Var), ("B", MacroKind::Var)]);
+
+    // Popping a macro which was not defined when it was pushed removes it.
+    macro_set.push_macro("D");
+    macro_set.define_var_macro("D", ["4"]).unwrap();
+    macro_set.push_macro("A");
+    macro_set.undefine_fn_macro("A");
+    assert!(macro_set.pop_macro("A"));
+    assert!(macro_set.pop_macro("D"));
+    assert_eq!(macro_set.definitions().map(|d| d.name()).collect::<Vec<_>>(), ["C", "B", "A"]);
+    assert_eq!(macro_set.len(), 3);
+  }
+}
@@ -0,0 +1,247 @@
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  error::Error,
+  fmt,
+};
+
+use super::MacroSet;
+use crate::ast::Identifier;
+
+/// A cycle in a [`DependencyGraph`].
+///
+/// The first and the last name of the chain are the same, e.g. `A -> B -> A`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCycle {
+  chain: Vec<String>,
+}
+
+impl DependencyCycle {
+  /// The chain of macro names forming this cycle.
+  pub fn chain(&self) -> &[String] {
+    &self.chain
+  }
+}
+
+impl fmt::Display for DependencyCycle {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "recursive macro definition {}", self.chain.join(" -> "))
+  }
+}
+
+impl Error for DependencyCycle {}
+
+/// The graph of references between the macros in a [`MacroSet`].
+///
+/// A macro depends on another macro if its body contains the other macro's
+/// name, not counting function-like macro parameters. Self-references are
+/// ignored since they are never expanded. All iteration orders are
+/// deterministic.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use cmacro::MacroSet;
+///
+/// let mut macro_set = MacroSet::new();
+/// macro_set.define_var_macro("A", ["B", "+", "C"])?;
+/// macro_set.define_var_macro("B", ["C", "*", "2"])?;
+/// macro_set.define_var_macro("C", ["1"])?;
+///
+/// let graph = macro_set.dependency_graph();
+/// assert_eq!(graph.dependencies("A").collect::<Vec<_>>(), ["B", "C"]);
+/// assert_eq!(graph.topological_order()?, ["C", "B", "A"]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+  dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DependencyGraph {
+  /// Iterate over the names of all macros in the graph, in lexicographic order.
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.dependencies.keys().map(|name| name.as_str())
+  }
+
+  /// Iterate over the macros directly referenced by the given macro.
+  pub fn dependencies<'g>(&'g self, name: &str) -> impl Iterator<Item = &'g str> {
+    self.dependencies.get(name).into_iter().flatten().map(|name| name.as_str())
+  }
+
+  /// Iterate over the macros directly referencing the given macro.
+  pub fn dependents<'g>(&'g self, name: &'g str) -> impl Iterator<Item = &'g str> {
+    self
+      .dependencies
+      .iter()
+      .filter(move |(_, dependencies)| dependencies.contains(name))
+      .map(|(dependent, _)| dependent.as_str())
+  }
+
+  /// Get all macros directly or indirectly referenced by the given macro.
+  ///
+  /// The macro itself is only included if it is part of a cycle.
+  pub fn transitive_dependencies(&self, name: &str) -> BTreeSet<&str> {
+    let mut visited = BTreeSet::new();
+    let mut stack = self.dependencies(name).collect::<Vec<_>>();
+
+    while let Some(name) = stack.pop() {
+      if visited.insert(name) {
+        stack.extend(self.dependencies(name));
+      }
+    }
+
+    visited
+  }
+
+  /// Get all macros ordered so that each macro comes after its dependencies.
+  ///
+  /// Returns the first cycle found if there is none.
+  pub fn topological_order(&self) -> Result<Vec<&str>, DependencyCycle> {
+    let mut order = vec![];
+    let mut cycles = vec![];
+    self.visit_all(&mut order, &mut cycles, true);
+
+    match cycles.into_iter().next() {
+      Some(cycle) => Err(cycle),
+      None => Ok(order),
+    }
+  }
+
+  /// Get the cycles in the graph, at least one for each group of mutually recursive macros.
+  ///
+  /// Each cycle is reported once, starting with its lexicographically smallest name.
+  pub fn cycles(&self) -> Vec<DependencyCycle> {
+    let mut cycles = vec![];
+    self.visit_all(&mut vec![], &mut cycles, false);
+    cycles
+  }
+
+  fn visit_all<'g>(&'g self, order: &mut Vec<&'g str>, cycles: &mut Vec<DependencyCycle>, stop_at_cycle: bool) {
+    let mut visited = BTreeSet::new();
+
+    for name in self.names() {
+      self.visit(name, &mut visited, &mut vec![], order, cycles);
+
+      if stop_at_cycle && !cycles.is_empty() {
+        return
+      }
+    }
+  }
+
+  fn visit<'g>(
+    &'g self,
+    name: &'g str,
+    visited: &mut BTreeSet<&'g str>,
+    path: &mut Vec<&'g str>,
+    order: &mut Vec<&'g str>,
+    cycles: &mut Vec<DependencyCycle>,
+  ) {
+    if let Some(start) = path.iter().position(|&n| n == name) {
+      let mut chain = path[start..].to_vec();
+
+      // Rotate the chain to start with its smallest name, so each cycle is only reported once.
+      let min = chain.iter().enumerate().min_by_key(|(_, &n)| n).map(|(i, _)| i).unwrap_or(0);
+      chain.rotate_left(min);
+      chain.push(chain[0]);
+
+      let cycle = DependencyCycle { chain: chain.into_iter().map(|n| n.to_owned()).collect() };
+      if !cycles.contains(&cycle) {
+        cycles.push(cycle);
+      }
+
+      return
+    }
+
+    if visited.contains(name) {
+      return
+    }
+
+    path.push(name);
+    for dependency in self.dependencies(name) {
+      self.visit(dependency, visited, path, order, cycles);
+    }
+    path.pop();
+
+    visited.insert(name);
+    order.push(name);
+  }
+}
+
+impl MacroSet {
+  /// Compute the graph of references between all defined macros.
+  pub fn dependency_graph(&self) -> DependencyGraph {
+    let is_defined = |name: &str| self.definition(name).is_some();
+
+    let references = |name: &str, arg_names: &[String], body: &[String]| {
+      body
+        .iter()
+        .map(|t| t.as_str())
+        .filter(|&t| t != name && Identifier::try_from(t).is_ok() && is_defined(t))
+        .filter(|&t| !arg_names.iter().any(|arg| arg.strip_suffix("...").unwrap_or(arg) == t))
+        .map(|t| t.to_owned())
+        .collect::<BTreeSet<_>>()
+    };
+
+    let mut dependencies = BTreeMap::new();
+
+    for definition in self.definitions() {
+      let name = definition.name();
+      dependencies.insert(name.to_owned(), references(name, definition.args().unwrap_or(&[]), definition.body()));
+    }
+
+    DependencyGraph { dependencies }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dependencies() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(true);
+
+    macro_set.define_var_macro("A", ["F", "(", "B", ")"]).unwrap();
+    macro_set.define_var_macro("B", ["C", "+", "UNDEFINED"]).unwrap();
+    macro_set.define_var_macro("C", ["C"]).unwrap();
+    macro_set.define_fn_macro("F", ["C", "args..."], ["C", "+", "args", "+", "D"]).unwrap();
+    macro_set.define_var_macro("D", ["1"]).unwrap();
+
+    let graph = macro_set.dependency_graph();
+
+    assert_eq!(graph.names().collect::<Vec<_>>(), ["A", "B", "C", "D", "F"]);
+    assert_eq!(graph.dependencies("A").collect::<Vec<_>>(), ["B", "F"]);
+    assert_eq!(graph.dependencies("B").collect::<Vec<_>>(), ["C"]);
+    assert_eq!(graph.dependencies("C").count(), 0);
+    assert_eq!(graph.dependencies("F").collect::<Vec<_>>(), ["D"]);
+    assert_eq!(graph.dependents("C").collect::<Vec<_>>(), ["B"]);
+    assert_eq!(graph.transitive_dependencies("A").into_iter().collect::<Vec<_>>(), ["B", "C", "D", "F"]);
+    assert_eq!(graph.topological_order(), Ok(vec!["C", "B", "D", "F", "A"]));
+    assert!(graph.cycles().is_empty());
+  }
+
+  #[test]
+  fn cycles() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", ["B"]).unwrap();
+    macro_set.define_var_macro("B", ["C", "+", "D"]).unwrap();
+    macro_set.define_var_macro("C", ["A"]).unwrap();
+    macro_set.define_fn_macro("D", ["x"], ["E", "(", "x", ")"]).unwrap();
+    macro_set.define_fn_macro("E", ["x"], ["D", "(", "x", ")"]).unwrap();
+
+    let graph = macro_set.dependency_graph();
+
+    let cycle = graph.topological_order().unwrap_err();
+    assert_eq!(cycle.chain(), ["A", "B", "C", "A"]);
+    assert_eq!(cycle.to_string(), "recursive macro definition A -> B -> C -> A");
+
+    let cycles = graph.cycles().into_iter().map(|cycle| cycle.chain().join(" -> ")).collect::<Vec<_>>();
+    assert_eq!(cycles, ["A -> B -> C -> A", "D -> E -> D"]);
+
+    assert!(graph.transitive_dependencies("A").contains("A"));
+  }
+}
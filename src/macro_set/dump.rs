@@ -0,0 +1,150 @@
+use std::fmt;
+
+use super::{is_whitespace, MacroDefinition, MacroSet};
+use crate::tokenize;
+
+/// The order of definitions in [`MacroSet::dump`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionOrder {
+  /// In the order the macros were first defined, see [`MacroSet::definitions`].
+  Defined,
+  /// Sorted by name.
+  Name,
+}
+
+/// Check whether two adjacent tokens must be separated by a space to be read back as two tokens.
+fn needs_space(token: &str, next: &str) -> bool {
+  match tokenize(&format!("{token}{next}")) {
+    Ok(tokens) => tokens != [token, next],
+    Err(_) => true,
+  }
+}
+
+/// Write the tokens of a replacement list, separated by single spaces.
+///
+/// Comments are dropped and any amount of whitespace is written as a single space,
+/// whitespace before the first and after the last token is dropped. Adjacent tokens
+/// which would otherwise be read back as a different token are separated as well.
+fn write_body(f: &mut fmt::Formatter<'_>, body: &[String]) -> fmt::Result {
+  let mut previous: Option<&str> = None;
+  let mut space_before = false;
+
+  for token in body {
+    if is_whitespace(token) {
+      space_before = previous.is_some();
+      continue
+    }
+
+    let token = token.trim();
+
+    match previous {
+      Some(previous) if space_before || needs_space(previous, token) => write!(f, " {token}")?,
+      Some(_) => f.write_str(token)?,
+      None => write!(f, " {token}")?,
+    }
+
+    previous = Some(token);
+    space_before = false;
+  }
+
+  Ok(())
+}
+
+/// A definition is displayed as a canonical `#define` line without a trailing newline,
+/// e.g. `#define FUNC(a,b) a + b`.
+///
+/// Parameters are separated by commas without spaces. In the replacement list,
+/// comments are removed and whitespace is normalized to single spaces, so
+/// identical definitions (see [`Redefinition::Identical`](crate::Redefinition::Identical))
+/// are displayed identically.
+impl fmt::Display for MacroDefinition<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "#define {}", self.name())?;
+
+    if let Some(args) = self.args() {
+      write!(f, "({})", args.join(","))?;
+    }
+
+    write_body(f, self.body())
+  }
+}
+
+impl MacroSet {
+  /// Render all defined macros as `#define` lines, similar to `cc -dM -E`.
+  ///
+  /// Each definition is displayed on its own line, in the given order, with comments
+  /// removed and whitespace normalized (see [`MacroDefinition`]).
+  /// Built-in macros like `__COUNTER__` are not included.
+  ///
+  /// The output can be read back with [`MacroSet::define_from_source`].
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{DefinitionOrder, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_from_source("#define VAR /* one */ 1\n#define ADD( a , b ) a+ +b")?;
+  ///
+  /// assert_eq!(macro_set.dump(DefinitionOrder::Defined), "#define VAR 1\n#define ADD(a,b) a+ +b\n");
+  /// assert_eq!(macro_set.dump(DefinitionOrder::Name), "#define ADD(a,b) a+ +b\n#define VAR 1\n");
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn dump(&self, order: DefinitionOrder) -> String {
+    let mut definitions = self.definitions().collect::<Vec<_>>();
+
+    if order == DefinitionOrder::Name {
+      definitions.sort_unstable_by_key(|definition| definition.name());
+    }
+
+    definitions.into_iter().map(|definition| format!("{definition}\n")).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(true);
+
+    macro_set.define_var_macro("EMPTY", [" ", "/* empty */"]).unwrap();
+    macro_set.define_var_macro("SPACED", ["  ", "1", " \t ", "+", "/* c */", "2", " "]).unwrap();
+    macro_set.define_var_macro("PASTE", ["a", "+", "+", "-", ">", "/", "/", "1", ".", "L", "'a'"]).unwrap();
+    macro_set.define_fn_macro("F", ["x", "args..."], ["x", "#", "#", "args"]).unwrap();
+    macro_set.define_fn_macro("G", Vec::<String>::new(), ["(", ")"]).unwrap();
+
+    let lines = macro_set.definitions().map(|definition| definition.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      lines,
+      [
+        "#define EMPTY",
+        "#define SPACED 1 + 2",
+        "#define PASTE a+ +- >/ /1 .L 'a'",
+        "#define F(x,args...) x# #args",
+        "#define G() ()",
+      ]
+    );
+
+    // Identical definitions are displayed identically.
+    let definition = macro_set.definition("SPACED").unwrap().to_string();
+    macro_set.define_var_macro("SPACED", ["1", "\n", "+", " ", "2"]).unwrap();
+    assert_eq!(macro_set.definition("SPACED").unwrap().to_string(), definition);
+  }
+
+  #[test]
+  fn dump_round_trip() {
+    let mut macro_set = MacroSet::new();
+    macro_set.define_from_source("#define B(x) x##1\n#define A 1. e+1\n#define C\n").unwrap();
+    assert_eq!(macro_set.dump(DefinitionOrder::Name), "#define A 1. e+1\n#define B(x) x##1\n#define C\n");
+
+    let mut read_back = MacroSet::new();
+    read_back.define_from_source(&macro_set.dump(DefinitionOrder::Defined)).unwrap();
+    assert!(read_back.diff(&macro_set).is_empty());
+    assert_eq!(read_back.dump(DefinitionOrder::Defined), macro_set.dump(DefinitionOrder::Defined));
+  }
+}
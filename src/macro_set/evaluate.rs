@@ -0,0 +1,412 @@
+use std::{borrow::Cow, cmp::Ordering, collections::HashSet, error::Error, fmt};
+
+use nom::{combinator::all_consuming, sequence::delimited};
+
+use super::{
+  builtin::BUILTIN_MACROS, detokenize, feature::FEATURE_OPERATORS, tokenize, traced, untraced, ExpansionError,
+  ExpansionState, MacroSet, Token, Trace,
+};
+use crate::{
+  ast::{meta, BinaryExpr, BinaryOp, Expr, Lit, LitChar, LitInt, TernaryExpr, UnaryExpr, UnaryOp},
+  BuiltInType, MacroToken,
+};
+
+/// An error during evaluation of a preprocessor condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConditionError {
+  /// `defined` is not followed by an identifier or a parenthesized identifier.
+  InvalidDefined,
+  /// Condition is not a valid integer constant expression.
+  InvalidExpression,
+  /// Integer literal is too large for `uintmax_t`.
+  IntegerTooLarge,
+  /// Division or remainder by zero.
+  DivisionByZero,
+  /// `__has_include`, `__has_attribute`, `__has_builtin` or `__has_feature` is not
+  /// followed by a valid parenthesized operand.
+  InvalidFeatureQuery,
+}
+
+impl fmt::Display for ConditionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::InvalidDefined => "`defined` is not followed by an identifier".fmt(f),
+      Self::InvalidExpression => "condition is not a valid integer constant expression".fmt(f),
+      Self::IntegerTooLarge => "integer literal is too large".fmt(f),
+      Self::DivisionByZero => "division by zero".fmt(f),
+      Self::InvalidFeatureQuery => "feature query is not followed by a valid operand".fmt(f),
+    }
+  }
+}
+
+impl Error for ConditionError {}
+
+/// The value of a preprocessor condition.
+///
+/// All arithmetic in preprocessor conditions is done using either `intmax_t`
+/// or `uintmax_t`, following the usual arithmetic conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionValue {
+  /// A value of type `intmax_t`.
+  Signed(i64),
+  /// A value of type `uintmax_t`.
+  Unsigned(u64),
+}
+
+impl ConditionValue {
+  /// Check whether the value is non-zero.
+  pub fn as_bool(&self) -> bool {
+    match *self {
+      Self::Signed(n) => n != 0,
+      Self::Unsigned(n) => n != 0,
+    }
+  }
+
+  fn from_bool(b: bool) -> Self {
+    Self::Signed(b.into())
+  }
+
+  fn from_lit_int(lit: &LitInt) -> Result<Self, ConditionError> {
+    let unsigned =
+      matches!(lit.suffix, Some(BuiltInType::UInt | BuiltInType::ULong | BuiltInType::ULongLong | BuiltInType::SizeT));
+
+    if !unsigned {
+      if let Ok(n) = i64::try_from(lit.value) {
+        return Ok(Self::Signed(n))
+      }
+    }
+
+    // Literals which do not fit into `intmax_t` have type `uintmax_t`.
+    u64::try_from(lit.value).map(Self::Unsigned).map_err(|_| ConditionError::IntegerTooLarge)
+  }
+
+  fn from_lit_char(lit: &LitChar) -> Self {
+    match *lit {
+      // Plain `char` is treated as `signed char`.
+      LitChar::Ordinary(c) => Self::Signed((c as i8).into()),
+      LitChar::Utf8(c) => Self::Signed(c.into()),
+      LitChar::Utf16(c) => Self::Signed(c.into()),
+      LitChar::Utf32(c) | LitChar::Wide(c) => Self::Signed(c.into()),
+    }
+  }
+
+  fn to_unsigned(self) -> u64 {
+    match self {
+      Self::Signed(n) => n as u64,
+      Self::Unsigned(n) => n,
+    }
+  }
+
+  /// Convert both operands to their common type.
+  fn convert(self, other: Self) -> (Self, Self) {
+    match (self, other) {
+      (Self::Signed(_), Self::Signed(_)) => (self, other),
+      _ => (Self::Unsigned(self.to_unsigned()), Self::Unsigned(other.to_unsigned())),
+    }
+  }
+
+  fn compare(self, other: Self) -> Ordering {
+    match self.convert(other) {
+      (Self::Signed(lhs), Self::Signed(rhs)) => lhs.cmp(&rhs),
+      (lhs, rhs) => lhs.to_unsigned().cmp(&rhs.to_unsigned()),
+    }
+  }
+
+  fn shift(self, amount: Self, left: bool) -> Self {
+    // Shifting by a negative amount shifts in the opposite direction.
+    let (left, amount) = match amount {
+      Self::Signed(n) if n < 0 => (!left, n.unsigned_abs()),
+      amount => (left, amount.to_unsigned()),
+    };
+    let amount = u32::try_from(amount).unwrap_or(u32::MAX);
+
+    match (self, left) {
+      (Self::Signed(n), true) => Self::Signed(n.checked_shl(amount).unwrap_or(0)),
+      (Self::Signed(n), false) => Self::Signed(n.checked_shr(amount).unwrap_or(if n < 0 { -1 } else { 0 })),
+      (Self::Unsigned(n), true) => Self::Unsigned(n.checked_shl(amount).unwrap_or(0)),
+      (Self::Unsigned(n), false) => Self::Unsigned(n.checked_shr(amount).unwrap_or(0)),
+    }
+  }
+
+  fn arithmetic(self, op: BinaryOp, other: Self, evaluated: bool) -> Result<Self, ConditionError> {
+    macro_rules! arithmetic {
+      ($lhs:ident, $rhs:ident) => {
+        match op {
+          BinaryOp::Mul => $lhs.wrapping_mul($rhs),
+          BinaryOp::Add => $lhs.wrapping_add($rhs),
+          BinaryOp::Sub => $lhs.wrapping_sub($rhs),
+          BinaryOp::BitAnd => $lhs & $rhs,
+          BinaryOp::BitXor => $lhs ^ $rhs,
+          BinaryOp::BitOr => $lhs | $rhs,
+          // Division by zero is only an error if the operation is actually evaluated.
+          BinaryOp::Div | BinaryOp::Rem if $rhs == 0 => {
+            if evaluated {
+              return Err(ConditionError::DivisionByZero)
+            }
+
+            0
+          },
+          BinaryOp::Div => $lhs.wrapping_div($rhs),
+          BinaryOp::Rem => $lhs.wrapping_rem($rhs),
+          _ => return Err(ConditionError::InvalidExpression),
+        }
+      };
+    }
+
+    Ok(match self.convert(other) {
+      (Self::Signed(lhs), Self::Signed(rhs)) => Self::Signed(arithmetic!(lhs, rhs)),
+      (lhs, rhs) => {
+        let (lhs, rhs) = (lhs.to_unsigned(), rhs.to_unsigned());
+        Self::Unsigned(arithmetic!(lhs, rhs))
+      },
+    })
+  }
+}
+
+/// Evaluate a parsed preprocessor condition.
+///
+/// If `evaluated` is `false`, the expression is only checked for validity and
+/// its type is determined, e.g. for the unevaluated operand of `&&`.
+fn evaluate(expr: &Expr<'_>, evaluated: bool) -> Result<ConditionValue, ConditionError> {
+  Ok(match expr {
+    Expr::Literal(Lit::Int(lit)) => ConditionValue::from_lit_int(lit)?,
+    Expr::Literal(Lit::Char(lit)) => ConditionValue::from_lit_char(lit),
+    Expr::Unary(UnaryExpr { op, expr }) => {
+      let value = evaluate(expr, evaluated)?;
+
+      match (op, value) {
+        (UnaryOp::Plus, value) => value,
+        (UnaryOp::Minus, ConditionValue::Signed(n)) => ConditionValue::Signed(n.wrapping_neg()),
+        (UnaryOp::Minus, ConditionValue::Unsigned(n)) => ConditionValue::Unsigned(n.wrapping_neg()),
+        (UnaryOp::Not, value) => ConditionValue::from_bool(!value.as_bool()),
+        (UnaryOp::Comp, ConditionValue::Signed(n)) => ConditionValue::Signed(!n),
+        (UnaryOp::Comp, ConditionValue::Unsigned(n)) => ConditionValue::Unsigned(!n),
+        _ => return Err(ConditionError::InvalidExpression),
+      }
+    },
+    Expr::Binary(BinaryExpr { lhs, op: op @ (BinaryOp::And | BinaryOp::Or), rhs }) => {
+      let lhs = evaluate(lhs, evaluated)?.as_bool();
+
+      // The right-hand side is only evaluated if the left-hand side does not determine the result.
+      let short_circuit = if *op == BinaryOp::And { !lhs } else { lhs };
+      let rhs = evaluate(rhs, evaluated && !short_circuit)?.as_bool();
+
+      ConditionValue::from_bool(if *op == BinaryOp::And { lhs && rhs } else { lhs || rhs })
+    },
+    Expr::Binary(BinaryExpr { lhs, op, rhs }) => {
+      let lhs = evaluate(lhs, evaluated)?;
+      let rhs = evaluate(rhs, evaluated)?;
+
+      match op {
+        BinaryOp::Shl => lhs.shift(rhs, true),
+        BinaryOp::Shr => lhs.shift(rhs, false),
+        BinaryOp::Lt => ConditionValue::from_bool(lhs.compare(rhs).is_lt()),
+        BinaryOp::Lte => ConditionValue::from_bool(lhs.compare(rhs).is_le()),
+        BinaryOp::Gt => ConditionValue::from_bool(lhs.compare(rhs).is_gt()),
+        BinaryOp::Gte => ConditionValue::from_bool(lhs.compare(rhs).is_ge()),
+        BinaryOp::Eq => ConditionValue::from_bool(lhs.compare(rhs).is_eq()),
+        BinaryOp::Neq => ConditionValue::from_bool(lhs.compare(rhs).is_ne()),
+        op => lhs.arithmetic(*op, rhs, evaluated)?,
+      }
+    },
+    Expr::Ternary(TernaryExpr { condition, if_branch, else_branch }) => {
+      let condition = evaluate(condition, evaluated)?.as_bool();
+
+      // Only one branch is evaluated, but the result has the common type of both branches.
+      let if_branch = evaluate(if_branch, evaluated && condition)?;
+      let else_branch = evaluate(else_branch, evaluated && !condition)?;
+      let (if_branch, else_branch) = if_branch.convert(else_branch);
+
+      if condition {
+        if_branch
+      } else {
+        else_branch
+      }
+    },
+    _ => return Err(ConditionError::InvalidExpression),
+  })
+}
+
+impl MacroSet {
+  /// Check whether a macro with the given name is defined.
+  pub(super) fn is_defined(&self, name: &str) -> bool {
+    self.var_macro(name).is_some()
+      || self.fn_macro(name).is_some()
+      || BUILTIN_MACROS.contains(&name)
+      || FEATURE_OPERATORS.contains(&name)
+  }
+
+  /// Replace `defined X` and `defined(X)` with `1` or `0`.
+  fn replace_defined<'t>(&self, tokens: Vec<Token<'t>>) -> Result<Vec<Token<'t>>, ExpansionError> {
+    let mut it = tokens.into_iter().filter(|t| !matches!(t, Token::Comment(_)));
+    let mut tokens = vec![];
+
+    while let Some(token) = it.next() {
+      match token {
+        Token::Identifier(ref id) if id.as_str() == "defined" => {
+          let name = match it.next() {
+            Some(Token::Identifier(id)) => id,
+            Some(Token::Punctuation(p)) if p == "(" => match (it.next(), it.next()) {
+              (Some(Token::Identifier(id)), Some(Token::Punctuation(p))) if p == ")" => id,
+              _ => return Err(ExpansionError::InvalidCondition(ConditionError::InvalidDefined)),
+            },
+            _ => return Err(ExpansionError::InvalidCondition(ConditionError::InvalidDefined)),
+          };
+
+          let (value, token) = if self.is_defined(name.as_str()) { (1, "1") } else { (0, "0") };
+          tokens.push(Token::Literal(Lit::Int(LitInt { value, suffix: None }), Cow::Borrowed(token)));
+        },
+        token => tokens.push(token),
+      }
+    }
+
+    Ok(tokens)
+  }
+
+  /// Evaluate a preprocessor condition, i.e. the tokens following an `#if` or
+  /// `#elif` directive.
+  ///
+  /// The `defined X` and `defined(X)` operators are replaced before macros are
+  /// expanded. All identifiers remaining after expansion are replaced with `0`.
+  /// Operands of `&&`, `||` and `?:` which do not contribute to the result are
+  /// not evaluated, so e.g. `0 && 1 / 0` is not an error.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{ConditionValue, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_var_macro("VERSION", &["2"])?;
+  ///
+  /// assert_eq!(macro_set.evaluate_condition(&["VERSION", "*", "'a'"])?, ConditionValue::Signed(194));
+  /// assert_eq!(macro_set.evaluate_condition(&["-", "1", "+", "0u"])?, ConditionValue::Unsigned(u64::MAX));
+  /// assert_eq!(macro_set.evaluate_condition(&["defined", "UNKNOWN", "||", "UNKNOWN"])?, ConditionValue::Signed(0));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn evaluate_condition<T>(&self, condition: &[T]) -> Result<ConditionValue, ExpansionError>
+  where
+    T: AsRef<str>,
+  {
+    let tokens = traced(self.replace_defined(tokenize(&[], condition))?, &Trace::default());
+    let tokens = self.expand_var_macro_body(HashSet::new(), "", &tokens, &mut ExpansionState::default())?;
+
+    let tokens = detokenize(&[], untraced(tokens))?
+      .into_iter()
+      .map(|token| match token {
+        MacroToken::Identifier(_) => MacroToken::Lit(Lit::Int(LitInt { value: 0, suffix: None })),
+        token => token,
+      })
+      .collect::<Vec<_>>();
+
+    let (_, expr) =
+      all_consuming(delimited(meta, Expr::parse, meta))(&tokens).map_err(|_: nom::Err<nom::error::Error<_>>| {
+        ExpansionError::InvalidCondition(ConditionError::InvalidExpression)
+      })?;
+
+    evaluate(&expr, true).map_err(ExpansionError::InvalidCondition)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn evaluate_arithmetic() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.evaluate_condition(&["1", "+", "2", "*", "3"]), Ok(ConditionValue::Signed(7)));
+    assert_eq!(macro_set.evaluate_condition(&["(", "1", "+", "2", ")", "*", "3"]), Ok(ConditionValue::Signed(9)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "7", "/", "2"]), Ok(ConditionValue::Signed(-3)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "7", "%", "2"]), Ok(ConditionValue::Signed(-1)));
+    assert_eq!(macro_set.evaluate_condition(&["~", "0"]), Ok(ConditionValue::Signed(-1)));
+    assert_eq!(macro_set.evaluate_condition(&["1", "<<", "62"]), Ok(ConditionValue::Signed(1 << 62)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "16", ">>", "2"]), Ok(ConditionValue::Signed(-4)));
+    assert_eq!(macro_set.evaluate_condition(&["0x10", "|", "3", "^", "1"]), Ok(ConditionValue::Signed(0x12)));
+  }
+
+  #[test]
+  fn evaluate_unsigned() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.evaluate_condition(&["-", "1", "<", "0"]), Ok(ConditionValue::Signed(1)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "1", "<", "0u"]), Ok(ConditionValue::Signed(0)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "1", ">>", "63"]), Ok(ConditionValue::Signed(-1)));
+    assert_eq!(macro_set.evaluate_condition(&["-", "1u", ">>", "63"]), Ok(ConditionValue::Unsigned(1)));
+    assert_eq!(macro_set.evaluate_condition(&["0xFFFFFFFFFFFFFFFF"]), Ok(ConditionValue::Unsigned(0xFFFFFFFFFFFFFFFF)));
+    assert_eq!(macro_set.evaluate_condition(&["1", "?", "-", "1", ":", "0u"]), Ok(ConditionValue::Unsigned(u64::MAX)));
+    assert_eq!(
+      macro_set.evaluate_condition(&["0x10000000000000000"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::IntegerTooLarge))
+    );
+  }
+
+  #[test]
+  fn evaluate_char() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.evaluate_condition(&["'A'"]), Ok(ConditionValue::Signed(65)));
+    assert_eq!(macro_set.evaluate_condition(&[r"'\377'", "<", "0"]), Ok(ConditionValue::Signed(1)));
+    assert_eq!(macro_set.evaluate_condition(&["u8'a'", "==", "'a'"]), Ok(ConditionValue::Signed(1)));
+    assert_eq!(macro_set.evaluate_condition(&["U'🍌'"]), Ok(ConditionValue::Signed(0x1f34c)));
+  }
+
+  #[test]
+  fn evaluate_short_circuit() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.evaluate_condition(&["0", "&&", "1", "/", "0"]), Ok(ConditionValue::Signed(0)));
+    assert_eq!(macro_set.evaluate_condition(&["1", "||", "1", "%", "0"]), Ok(ConditionValue::Signed(1)));
+    assert_eq!(macro_set.evaluate_condition(&["1", "?", "2", ":", "1", "/", "0"]), Ok(ConditionValue::Signed(2)));
+    assert_eq!(
+      macro_set.evaluate_condition(&["1", "&&", "1", "/", "0"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::DivisionByZero))
+    );
+    assert_eq!(
+      macro_set.evaluate_condition(&["0", "?", "2", ":", "1", "%", "0"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::DivisionByZero))
+    );
+  }
+
+  #[test]
+  fn evaluate_identifiers() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", ["2"]).unwrap();
+    macro_set.define_fn_macro("F", ["x"], ["x", "+", "B"]).unwrap();
+
+    assert_eq!(macro_set.evaluate_condition(&["A", "+", "B"]), Ok(ConditionValue::Signed(2)));
+    assert_eq!(macro_set.evaluate_condition(&["F", "(", "A", ")"]), Ok(ConditionValue::Signed(2)));
+    assert_eq!(macro_set.evaluate_condition(&["defined", "(", "F", ")"]), Ok(ConditionValue::Signed(1)));
+  }
+
+  #[test]
+  fn evaluate_invalid() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(
+      macro_set.evaluate_condition(&["defined", "+"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidDefined))
+    );
+    assert_eq!(
+      macro_set.evaluate_condition(&["1", "+"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidExpression))
+    );
+    assert_eq!(
+      macro_set.evaluate_condition(&["1.0"]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidExpression))
+    );
+    assert_eq!(
+      macro_set.evaluate_condition(&["\"a\""]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidExpression))
+    );
+    assert_eq!(
+      macro_set.evaluate_condition(&[] as &[&str]),
+      Err(ExpansionError::InvalidCondition(ConditionError::InvalidExpression))
+    );
+  }
+}
@@ -0,0 +1,223 @@
+use std::{borrow::Cow, fmt, sync::Arc};
+
+use super::{trace::spelling, ConditionError, ExpansionError, MacroSet, Token, Traced};
+use crate::ast::{Lit, LitInt};
+
+/// Operators answered by a [`FeatureQuery`].
+pub(super) const FEATURE_OPERATORS: [&str; 4] = ["__has_include", "__has_attribute", "__has_builtin", "__has_feature"];
+
+/// Answers the `__has_include`, `__has_attribute`, `__has_builtin` and `__has_feature`
+/// operators, see [`MacroSet::set_feature_query`].
+///
+/// By default, every method returns `false`, i.e. all operators evaluate to `0`.
+/// This is also what the implementation for `()` does, which is used if no
+/// feature query is set.
+pub trait FeatureQuery {
+  /// Check whether a header exists, i.e. `__has_include("name")` if `quoted`
+  /// is `true`, or `__has_include(<name>)` otherwise.
+  fn has_include(&self, name: &str, quoted: bool) -> bool {
+    let _ = (name, quoted);
+    false
+  }
+
+  /// Check whether an attribute is supported, i.e. `__has_attribute(name)`.
+  ///
+  /// The name is normalized, i.e. `__packed__` is passed as `packed`.
+  fn has_attribute(&self, name: &str) -> bool {
+    let _ = name;
+    false
+  }
+
+  /// Check whether a builtin function is supported, i.e. `__has_builtin(name)`.
+  fn has_builtin(&self, name: &str) -> bool {
+    let _ = name;
+    false
+  }
+
+  /// Check whether a language feature is supported, i.e. `__has_feature(name)`.
+  fn has_feature(&self, name: &str) -> bool {
+    let _ = name;
+    false
+  }
+}
+
+impl FeatureQuery for () {}
+
+/// The feature query of a [`MacroSet`].
+#[derive(Clone)]
+pub(super) struct Features(Arc<dyn FeatureQuery + Send + Sync>);
+
+impl Default for Features {
+  fn default() -> Self {
+    Self(Arc::new(()))
+  }
+}
+
+impl fmt::Debug for Features {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Features").finish_non_exhaustive()
+  }
+}
+
+impl MacroSet {
+  /// Set the [`FeatureQuery`] used to evaluate `__has_include`, `__has_attribute`,
+  /// `__has_builtin` and `__has_feature`.
+  ///
+  /// The operand of these operators is not macro-expanded. They are replaced
+  /// with `1` or `0` both in conditions and when expanding macros, so e.g.
+  /// `#define HAS_STDINT __has_include(<stdint.h>)` expands to `1` or `0`.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{FeatureQuery, MacroSet};
+  ///
+  /// struct Gcc;
+  ///
+  /// impl FeatureQuery for Gcc {
+  ///   fn has_include(&self, name: &str, quoted: bool) -> bool {
+  ///     !quoted && name == "stdint.h"
+  ///   }
+  ///
+  ///   fn has_attribute(&self, name: &str) -> bool {
+  ///     matches!(name, "packed" | "aligned")
+  ///   }
+  /// }
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// assert!(!macro_set.if_directive(&["__has_include", "(", "<", "stdint", ".", "h", ">", ")"])?);
+  /// macro_set.endif_directive()?;
+  ///
+  /// macro_set.set_feature_query(Gcc);
+  /// assert!(macro_set.if_directive(&["__has_include", "(", "<", "stdint", ".", "h", ">", ")"])?);
+  /// macro_set.endif_directive()?;
+  /// assert!(macro_set.if_directive(&["__has_attribute", "(", "__packed__", ")"])?);
+  /// macro_set.endif_directive()?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_feature_query<Q>(&mut self, query: Q)
+  where
+    Q: FeatureQuery + Send + Sync + 'static,
+  {
+    self.features = Features(Arc::new(query));
+    self.cache.clear();
+  }
+
+  /// Evaluate a feature operator with the given arguments.
+  pub(super) fn evaluate_feature(
+    &self,
+    operator: &str,
+    args: &[Vec<Traced<'_>>],
+  ) -> Result<Token<'static>, ExpansionError> {
+    let operand = match args {
+      [operand] => operand.iter().filter(|t| !matches!(t.token, Token::Comment(_))).collect::<Vec<_>>(),
+      _ => vec![],
+    };
+    if operand.is_empty() {
+      return Err(ExpansionError::InvalidCondition(ConditionError::InvalidFeatureQuery))
+    }
+
+    let name = operand.iter().map(|t| spelling(&t.token)).collect::<String>();
+    let query = &self.features.0;
+
+    let value = match operator {
+      "__has_include" => {
+        if let Some(name) = name.strip_prefix('<').and_then(|name| name.strip_suffix('>')) {
+          query.has_include(name, false)
+        } else if let Some(name) = name.strip_prefix('"').and_then(|name| name.strip_suffix('"')) {
+          query.has_include(name, true)
+        } else {
+          return Err(ExpansionError::InvalidCondition(ConditionError::InvalidFeatureQuery))
+        }
+      },
+      "__has_attribute" => {
+        let name = name.strip_prefix("__").and_then(|name| name.strip_suffix("__")).unwrap_or(&name);
+        query.has_attribute(name)
+      },
+      "__has_builtin" => query.has_builtin(&name),
+      "__has_feature" => query.has_feature(&name),
+      _ => unreachable!(),
+    };
+
+    let (value, token) = if value { (1, "1") } else { (0, "0") };
+    Ok(Token::Literal(Lit::Int(LitInt { value, suffix: None }), Cow::Borrowed(token)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ConditionValue;
+
+  struct Query;
+
+  impl FeatureQuery for Query {
+    fn has_include(&self, name: &str, quoted: bool) -> bool {
+      if quoted {
+        name == "config.h"
+      } else {
+        name == "sys/types.h"
+      }
+    }
+
+    fn has_attribute(&self, name: &str) -> bool {
+      name == "packed" || name == "gnu::packed"
+    }
+
+    fn has_builtin(&self, name: &str) -> bool {
+      name == "__builtin_expect"
+    }
+
+    fn has_feature(&self, name: &str) -> bool {
+      name == "address_sanitizer"
+    }
+  }
+
+  fn evaluate(macro_set: &MacroSet, condition: &str) -> Result<ConditionValue, ExpansionError> {
+    macro_set.evaluate_condition(&crate::tokenize(condition).unwrap())
+  }
+
+  #[test]
+  fn feature_query_default() {
+    let macro_set = MacroSet::new();
+
+    assert_eq!(evaluate(&macro_set, "__has_include(<stdio.h>)"), Ok(ConditionValue::Signed(0)));
+    assert_eq!(evaluate(&macro_set, "__has_attribute(packed)"), Ok(ConditionValue::Signed(0)));
+    assert_eq!(evaluate(&macro_set, "__has_builtin(__builtin_expect)"), Ok(ConditionValue::Signed(0)));
+    assert_eq!(evaluate(&macro_set, "__has_feature(modules)"), Ok(ConditionValue::Signed(0)));
+    assert_eq!(evaluate(&macro_set, "defined(__has_include) && defined __has_feature"), Ok(ConditionValue::Signed(1)));
+  }
+
+  #[test]
+  fn feature_query() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_feature_query(Query);
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define types sys/types.h
+        #define HAS_CONFIG __has_include("config.h")
+        #if __has_include(<types>) || !__has_include(<sys/types.h>)
+        #  error
+        #elif __has_attribute(__packed__) && __has_attribute(gnu::packed)
+        #  define PACKED __attribute__((packed))
+        #endif
+        "#,
+      )
+      .unwrap();
+
+    assert!(macro_set.var_macros.contains_key("PACKED"));
+    assert_eq!(evaluate(&macro_set, "HAS_CONFIG"), Ok(ConditionValue::Signed(1)));
+    assert_eq!(evaluate(&macro_set, "__has_builtin(__builtin_expect)"), Ok(ConditionValue::Signed(1)));
+    assert_eq!(evaluate(&macro_set, "__has_feature(address_sanitizer)"), Ok(ConditionValue::Signed(1)));
+    assert_eq!(macro_set.expand(&["HAS_CONFIG"]), macro_set.expand(&["1"]));
+
+    let err = Err(ExpansionError::InvalidCondition(ConditionError::InvalidFeatureQuery));
+    assert_eq!(evaluate(&macro_set, "__has_include()"), err);
+    assert_eq!(evaluate(&macro_set, "__has_include(stdio.h)"), err);
+    assert_eq!(evaluate(&macro_set, "__has_builtin(a, b)"), err);
+  }
+}
@@ -0,0 +1,402 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  hash::BuildHasher,
+  path::{Component, Path, PathBuf},
+};
+
+use super::{
+  source::{SourceError, SourceFile},
+  MacroSet,
+};
+use crate::{
+  tokenizer::{tokenize_lines, SourceToken},
+  Lit, LitString, MacroToken,
+};
+
+/// Maximum nesting depth of included files.
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+/// A file lookup used to resolve `#include` directives.
+///
+/// This is implemented for [`FileSystem`] as well as for an in-memory
+/// `HashMap<PathBuf, String>` mapping paths to file contents.
+pub trait FileLookup {
+  /// Read the file at the given path, returning `None` if it does not exist.
+  fn read_file(&self, path: &Path) -> Option<String>;
+}
+
+/// A [`FileLookup`] which reads files from the file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+impl FileLookup for FileSystem {
+  fn read_file(&self, path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+  }
+}
+
+impl<S: BuildHasher> FileLookup for HashMap<PathBuf, String, S> {
+  fn read_file(&self, path: &Path) -> Option<String> {
+    self.get(path).cloned()
+  }
+}
+
+impl<L: FileLookup + ?Sized> FileLookup for &L {
+  fn read_file(&self, path: &Path) -> Option<String> {
+    (**self).read_file(path)
+  }
+}
+
+/// Normalize a path lexically, i.e. without accessing the file system.
+pub(super) fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+
+  for component in path.components() {
+    match component {
+      Component::CurDir => (),
+      Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+        normalized.pop();
+      },
+      component => normalized.push(component),
+    }
+  }
+
+  normalized
+}
+
+/// Include search paths and the state of already included files.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Includes {
+  /// Directories searched for `#include "..."`.
+  pub user_dirs: Vec<PathBuf>,
+  /// Directories searched for both `#include "..."` and `#include <...>`.
+  pub system_dirs: Vec<PathBuf>,
+  /// Files containing `#pragma once`.
+  pub once: HashSet<PathBuf>,
+  /// Include guard macros of already included files.
+  pub guards: HashMap<PathBuf, String>,
+}
+
+impl Includes {
+  /// Find an included file.
+  ///
+  /// `#include "..."` is searched relative to the directory of the current file first,
+  /// then in the user and system directories. `#include <...>` is only searched in the
+  /// system directories.
+  pub fn resolve<L>(
+    &self,
+    name: &str,
+    quoted: bool,
+    current_file: Option<&Path>,
+    lookup: &L,
+  ) -> Option<(PathBuf, String)>
+  where
+    L: FileLookup + ?Sized,
+  {
+    let current_dir = current_file.filter(|_| quoted).map(|file| file.parent().unwrap_or_else(|| Path::new("")));
+    let user_dirs = self.user_dirs.iter().filter(|_| quoted).map(|dir| dir.as_path());
+    let system_dirs = self.system_dirs.iter().map(|dir| dir.as_path());
+
+    current_dir.into_iter().chain(user_dirs).chain(system_dirs).find_map(|dir| {
+      let path = normalize_path(&dir.join(name));
+      lookup.read_file(&path).map(|contents| (path, contents))
+    })
+  }
+}
+
+/// Find the include guard of a file, i.e. the `X` in a file of the form
+///
+/// ```c
+/// #ifndef X
+/// ...
+/// #endif
+/// ```
+///
+/// where the `#endif` is the last line and matches the `#ifndef`. `#if !defined X`
+/// and `#if !defined(X)` are also recognized.
+pub(super) fn include_guard(lines: &[Vec<SourceToken>]) -> Option<String> {
+  let mut lines = lines
+    .iter()
+    .map(|line| line.iter().filter(|t| !t.is_comment()).map(|t| t.text.as_str()).collect::<Vec<_>>())
+    .filter(|line| !line.is_empty());
+
+  let guard = match lines.next()?.as_slice() {
+    ["#", "ifndef", name] | ["#", "if", "!", "defined", name] | ["#", "if", "!", "defined", "(", name, ")"] => {
+      name.to_string()
+    },
+    _ => return None,
+  };
+
+  let mut depth = 1;
+
+  for line in lines.by_ref() {
+    match line.as_slice() {
+      ["#", "if" | "ifdef" | "ifndef", ..] => depth += 1,
+      // The rest of the file is not skipped if the guard macro is defined.
+      ["#", "elif" | "else", ..] if depth == 1 => return None,
+      ["#", "endif", ..] => {
+        depth -= 1;
+
+        if depth == 0 {
+          break
+        }
+      },
+      _ => (),
+    }
+  }
+
+  // The `#endif` matching the guard must be the last line.
+  if depth == 0 && lines.next().is_none() {
+    Some(guard)
+  } else {
+    None
+  }
+}
+
+impl MacroSet {
+  /// Add a directory which is searched for `#include "..."` directives.
+  ///
+  /// User include directories are searched after the directory of the current file and
+  /// before system include directories.
+  pub fn add_user_include_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+    self.includes.user_dirs.push(dir.into())
+  }
+
+  /// Add a directory which is searched for both `#include "..."` and `#include <...>` directives.
+  pub fn add_system_include_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+    self.includes.system_dirs.push(dir.into())
+  }
+
+  /// Parse the header name of an `#include` directive.
+  fn header_name(&self, tokens: &[SourceToken]) -> Result<(String, bool), SourceError> {
+    let mut tokens = tokens.iter().filter(|t| !t.is_comment());
+
+    match tokens.clone().next() {
+      Some(t) if t.text.len() >= 2 && t.text.starts_with('"') && t.text.ends_with('"') => {
+        return Ok((t.text[1..(t.text.len() - 1)].to_owned(), true))
+      },
+      Some(t) if t.text == "<" => {
+        let mut name = String::new();
+
+        for t in tokens.by_ref().skip(1) {
+          if t.text == ">" {
+            return Ok((name, false))
+          }
+
+          if t.space_before && !name.is_empty() {
+            name.push(' ');
+          }
+          name.push_str(&t.text);
+        }
+
+        return Err(SourceError::InvalidInclude)
+      },
+      _ => (),
+    }
+
+    // The header name may also be given by a macro expanding to a string literal.
+    let tokens = tokens.map(|t| t.text.as_str()).collect::<Vec<_>>();
+    match self.expand(&tokens)?.as_slice() {
+      [MacroToken::Lit(Lit::String(LitString::Ordinary(name)))] => {
+        Ok((String::from_utf8(name.to_vec()).map_err(|_| SourceError::InvalidInclude)?, true))
+      },
+      _ => Err(SourceError::InvalidInclude),
+    }
+  }
+
+  /// Process an `#include` directive.
+  pub(super) fn include(&mut self, tokens: &[SourceToken], file: SourceFile<'_>) -> Result<(), SourceError> {
+    let (name, quoted) = self.header_name(tokens)?;
+
+    let (path, contents) =
+      self.includes.resolve(&name, quoted, Some(file.path), file.lookup).ok_or(SourceError::IncludeNotFound(name))?;
+
+    if self.includes.once.contains(&path) {
+      return Ok(())
+    }
+
+    if let Some(guard) = self.includes.guards.get(&path) {
+      if self.is_defined(guard) {
+        return Ok(())
+      }
+    }
+
+    if file.depth >= MAX_INCLUDE_DEPTH {
+      return Err(SourceError::IncludeDepthExceeded)
+    }
+
+    self.define_from_file_contents(&contents, SourceFile { path: &path, lookup: file.lookup, depth: file.depth + 1 })
+  }
+
+  fn define_from_file_contents(&mut self, contents: &str, file: SourceFile<'_>) -> Result<(), SourceError> {
+    let lines = tokenize_lines(contents)?;
+    self.define_from_lines(&lines, Some(file))?;
+
+    if let Some(guard) = include_guard(&lines) {
+      self.includes.guards.insert(file.path.to_owned(), guard);
+    }
+
+    Ok(())
+  }
+
+  /// Define and undefine macros using the C source file at the given path.
+  ///
+  /// This works like [`MacroSet::define_from_source`], but additionally follows
+  /// `#include` directives using the given [`FileLookup`] and the include directories
+  /// added with [`MacroSet::add_user_include_dir`] and [`MacroSet::add_system_include_dir`].
+  ///
+  /// Files containing `#pragma once` are only included once. Files protected by an
+  /// include guard are skipped if the guard macro is defined.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use std::{collections::HashMap, path::PathBuf};
+  ///
+  /// use cmacro::MacroSet;
+  ///
+  /// let files = HashMap::from([
+  ///   (PathBuf::from("main.h"), "#include \"config.h\"\n#include <lib.h>\n".to_owned()),
+  ///   (PathBuf::from("config.h"), "#pragma once\n#define LIB_VERSION 2\n".to_owned()),
+  ///   (PathBuf::from("include/lib.h"), "#if LIB_VERSION >= 2\n#define LIB_FEATURE 1\n#endif\n".to_owned()),
+  /// ]);
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.add_system_include_dir("include");
+  /// macro_set.define_from_file("main.h", &files)?;
+  ///
+  /// assert_eq!(macro_set.expand(&["LIB_FEATURE"])?, macro_set.expand(&["1"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn define_from_file<P, L>(&mut self, path: P, lookup: &L) -> Result<(), SourceError>
+  where
+    P: AsRef<Path>,
+    L: FileLookup,
+  {
+    let path = normalize_path(path.as_ref());
+    let contents = lookup.read_file(&path).ok_or_else(|| SourceError::IncludeNotFound(path.display().to_string()))?;
+
+    self.define_from_file_contents(&contents, SourceFile { path: &path, lookup, depth: 0 })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{tokenizer::tokenize_lines, SourceLocation};
+
+  fn files(files: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+    files.iter().map(|(path, contents)| (PathBuf::from(path), contents.to_string())).collect()
+  }
+
+  #[test]
+  fn normalize() {
+    assert_eq!(normalize_path(Path::new("./a/b/../c.h")), Path::new("a/c.h"));
+    assert_eq!(normalize_path(Path::new("../a/./c.h")), Path::new("../a/c.h"));
+    assert_eq!(normalize_path(Path::new("/usr/include/../include/stdio.h")), Path::new("/usr/include/stdio.h"));
+  }
+
+  #[test]
+  fn guard() {
+    let guard = |source| include_guard(&tokenize_lines(source).unwrap());
+
+    assert_eq!(guard("// Header.\n#ifndef A_H\n#define A_H\n#if X\n#endif\n#endif /* A_H */\n"), Some("A_H".into()));
+    assert_eq!(guard("#if !defined(A_H)\n#define A_H\n#endif"), Some("A_H".into()));
+    assert_eq!(guard("#ifndef A_H\n#define A_H\n#endif\nint x;"), None);
+    assert_eq!(guard("#ifndef A_H\n#define A_H\n#else\n#endif"), None);
+    assert_eq!(guard("int x;\n#ifndef A_H\n#define A_H\n#endif"), None);
+  }
+
+  #[test]
+  fn include_search_order() {
+    let files = files(&[
+      ("src/main.c", "#include \"a.h\"\n#include <b.h>\n#include \"c.h\"\n"),
+      ("src/a.h", "#define A \"src\""),
+      ("user/a.h", "#define A \"user\""),
+      ("user/b.h", "#define B \"user\""),
+      ("user/c.h", "#define C \"user\""),
+      ("system/b.h", "#define B \"system\""),
+      ("system/c.h", "#define C \"system\""),
+    ]);
+
+    let mut macro_set = MacroSet::new();
+    macro_set.add_user_include_dir("user");
+    macro_set.add_system_include_dir("system");
+    macro_set.define_from_file("src/main.c", &files).unwrap();
+
+    assert_eq!(macro_set.var_macros["A"].body, [r#""src""#]);
+    assert_eq!(macro_set.var_macros["B"].body, [r#""system""#]);
+    assert_eq!(macro_set.var_macros["C"].body, [r#""user""#]);
+  }
+
+  #[test]
+  fn include_once() {
+    let files = files(&[
+      ("main.c", "#include \"once.h\"\n#include \"guard.h\"\n#include \"dir/../once.h\"\n#include \"guard.h\"\n"),
+      ("once.h", "#pragma once\n#ifdef ONCE\n#define ONCE_TWICE\n#endif\n#define ONCE"),
+      ("guard.h", "#ifndef GUARD_H\n#define GUARD_H\n#ifdef GUARD\n#define GUARD_TWICE\n#endif\n#define GUARD\n#endif"),
+    ]);
+
+    let mut macro_set = MacroSet::new();
+    macro_set.define_from_file("main.c", &files).unwrap();
+
+    assert!(macro_set.is_defined("ONCE"));
+    assert!(!macro_set.is_defined("ONCE_TWICE"));
+    assert!(macro_set.is_defined("GUARD"));
+    assert!(!macro_set.is_defined("GUARD_TWICE"));
+    assert_eq!(macro_set.includes.guards[Path::new("guard.h")], "GUARD_H");
+  }
+
+  #[test]
+  fn include_macro() {
+    let files = files(&[("main.c", "#define HEADER \"a.h\"\n#include HEADER\n"), ("a.h", "#define A 1")]);
+
+    let mut macro_set = MacroSet::new();
+    macro_set.define_from_file("main.c", &files).unwrap();
+
+    assert!(macro_set.is_defined("A"));
+  }
+
+  #[test]
+  fn include_errors() {
+    let files = files(&[
+      ("missing.c", "#include \"missing.h\""),
+      ("invalid.c", "#include <a.h"),
+      ("recursive.c", "#include \"recursive.c\""),
+      ("unterminated.h", "#if 1"),
+      ("unterminated.c", "#include \"unterminated.h\"\n#endif"),
+      ("inactive.c", "#if 0\n#include \"missing.h\"\n#endif"),
+      ("invalid_define.h", "#if 1\n#define 1"),
+      ("invalid_define.c", "#if 1\n#include \"invalid_define.h\"\n#endif"),
+    ]);
+
+    let located = |file: &str, error| SourceError::Located {
+      location: SourceLocation::new(1, 1).with_file(file),
+      error: Box::new(error),
+    };
+
+    let mut macro_set = MacroSet::new();
+    assert_eq!(
+      macro_set.define_from_file("missing.c", &files),
+      Err(located("missing.c", SourceError::IncludeNotFound("missing.h".into())))
+    );
+    assert_eq!(macro_set.define_from_file("invalid.c", &files), Err(located("invalid.c", SourceError::InvalidInclude)));
+    assert_eq!(
+      macro_set.define_from_file("recursive.c", &files),
+      Err(located("recursive.c", SourceError::IncludeDepthExceeded))
+    );
+    assert_eq!(
+      macro_set.define_from_file("unterminated.c", &files),
+      Err(located("unterminated.h", SourceError::UnterminatedConditional))
+    );
+    assert!(macro_set.is_active());
+    assert_eq!(macro_set.define_from_file("inactive.c", &files), Ok(()));
+
+    // Conditionals entered before an error in an included file are left.
+    assert!(matches!(macro_set.define_from_file("invalid_define.c", &files), Err(SourceError::Parser(_))));
+    assert!(macro_set.conditionals.is_empty());
+  }
+}
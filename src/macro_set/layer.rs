@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use super::{redefinition::is_identical, FnMacroDef, MacroSet, VarMacroDef};
+
+/// The differences between two [`MacroSet`]s, see [`MacroSet::diff`].
+///
+/// All lists are in definition order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacroDiff {
+  added: Vec<String>,
+  changed: Vec<String>,
+  removed: Vec<String>,
+}
+
+impl MacroDiff {
+  /// The macros which are only defined in the newer set.
+  pub fn added(&self) -> &[String] {
+    &self.added
+  }
+
+  /// The macros which are defined differently in both sets.
+  ///
+  /// A macro is changed if it is not defined identically, see [`Redefinition`](crate::Redefinition).
+  pub fn changed(&self) -> &[String] {
+    &self.changed
+  }
+
+  /// The macros which are only defined in the older set.
+  pub fn removed(&self) -> &[String] {
+    &self.removed
+  }
+
+  /// Check whether both sets define the same macros.
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+  }
+}
+
+impl MacroSet {
+  /// Create an overlay on top of the given macro set.
+  ///
+  /// Looking up a macro falls through to the parent unless it is defined or
+  /// undefined in the overlay, while defining and undefining macros only
+  /// affects the overlay. Settings like GNU extensions, limits, the feature
+  /// query and include paths are copied from the parent.
+  ///
+  /// This allows processing many headers on top of a shared base without
+  /// cloning the base for each of them.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use std::sync::Arc;
+  ///
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut base = MacroSet::new();
+  /// base.define_var_macro("WIDTH", ["80"])?;
+  /// base.define_var_macro("HEIGHT", ["24"])?;
+  /// let base = Arc::new(base);
+  ///
+  /// let mut module = MacroSet::overlay(base.clone());
+  /// module.define_var_macro("AREA", ["WIDTH", "*", "HEIGHT"])?;
+  /// module.define_var_macro("WIDTH", ["132"])?;
+  /// module.undefine_var_macro("HEIGHT");
+  ///
+  /// assert_eq!(module.expand_var_macro("AREA")?, module.expand(&["132", "*", "HEIGHT"])?);
+  /// assert_eq!(base.expand_var_macro("WIDTH")?, base.expand(&["80"])?);
+  ///
+  /// let diff = module.diff(&base);
+  /// assert_eq!(diff.added(), ["AREA"]);
+  /// assert_eq!(diff.changed(), ["WIDTH"]);
+  /// assert_eq!(diff.removed(), ["HEIGHT"]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn overlay(parent: Arc<MacroSet>) -> Self {
+    Self {
+      includes: parent.includes.clone(),
+      gnu_extensions: parent.gnu_extensions,
+      strict_redefinitions: parent.strict_redefinitions,
+      counter: parent.counter,
+      timestamp: parent.timestamp,
+      features: parent.features.clone(),
+      limits: parent.limits,
+      parent: Some(parent),
+      ..Self::default()
+    }
+  }
+
+  /// The parent of an overlay, see [`MacroSet::overlay`].
+  pub fn parent(&self) -> Option<&Arc<MacroSet>> {
+    self.parent.as_ref()
+  }
+
+  /// Apply the definitions and undefinitions of the top layer of another set.
+  ///
+  /// For an overlay, only the macros defined or undefined in the overlay itself are
+  /// applied, so merging an overlay into a copy of its parent flattens it. Otherwise,
+  /// all macros of the other set are defined. Conditional directives are not considered.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use std::sync::Arc;
+  ///
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut base = MacroSet::new();
+  /// base.define_var_macro("A", ["1"])?;
+  /// base.define_var_macro("B", ["2"])?;
+  /// let base = Arc::new(base);
+  ///
+  /// let mut module = MacroSet::overlay(base.clone());
+  /// module.undefine_var_macro("A");
+  /// module.define_var_macro("C", ["3"])?;
+  ///
+  /// let mut flattened = MacroSet::clone(&base);
+  /// flattened.merge(&module);
+  /// assert!(flattened.diff(&module).is_empty());
+  /// assert_eq!(flattened.definitions().map(|definition| definition.name()).collect::<Vec<_>>(), ["B", "C"]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn merge(&mut self, layer: &MacroSet) {
+    for name in &layer.undefined {
+      self.var_macros.remove(name);
+      self.fn_macros.remove(name);
+      self.locations.remove(name);
+      self.order.remove(name);
+      self.hide(name);
+      self.invalidate(name);
+    }
+
+    let mut names = layer.order.iter().collect::<Vec<_>>();
+    names.sort_unstable_by_key(|(_, order)| **order);
+
+    for (name, _) in names {
+      self.var_macros.remove(name);
+      self.fn_macros.remove(name);
+      self.undefined.remove(name);
+      self.locations.remove(name);
+      self.invalidate(name);
+      self.insert_order(name);
+
+      if let Some(var_macro) = layer.var_macros.get(name) {
+        self.var_macros.insert(name.clone(), var_macro.clone());
+      } else if let Some(fn_macro) = layer.fn_macros.get(name) {
+        self.fn_macros.insert(name.clone(), fn_macro.clone());
+      }
+
+      if let Some(location) = layer.locations.get(name) {
+        self.locations.insert(name.clone(), location.clone());
+      }
+    }
+  }
+
+  /// Compare the macros defined in this set with the macros defined in an older set.
+  ///
+  /// Both sets may be overlays, in which case all macros visible in them are compared.
+  pub fn diff(&self, base: &MacroSet) -> MacroDiff {
+    let mut diff = MacroDiff::default();
+
+    for name in self.definition_names() {
+      match (self.definition(name), base.definition(name)) {
+        (_, None) => diff.added.push(name.to_owned()),
+        (Some(definition), Some(base_definition)) if !is_identical(&definition, &base_definition) => {
+          diff.changed.push(name.to_owned())
+        },
+        _ => (),
+      }
+    }
+
+    for name in base.definition_names() {
+      if self.definition(name).is_none() {
+        diff.removed.push(name.to_owned());
+      }
+    }
+
+    diff
+  }
+
+  /// Check whether a name is defined or undefined in the top layer.
+  pub(super) fn is_shadowed(&self, name: &str) -> bool {
+    self.var_macros.contains_key(name) || self.fn_macros.contains_key(name) || self.undefined.contains(name)
+  }
+
+  /// Hide the definition of a macro in the parent, if any.
+  pub(super) fn hide(&mut self, name: &str) {
+    if self.parent.as_ref().map(|parent| parent.definition(name).is_some()).unwrap_or(false) {
+      self.undefined.insert(name.to_owned());
+    }
+  }
+
+  /// Look up a variable-like macro, falling through to the parent.
+  pub(super) fn var_macro(&self, name: &str) -> Option<(&String, &VarMacroDef)> {
+    if let Some(var_macro) = self.var_macros.get_key_value(name) {
+      return Some(var_macro)
+    }
+
+    if self.is_shadowed(name) {
+      return None
+    }
+
+    self.parent.as_ref()?.var_macro(name)
+  }
+
+  /// Look up a function-like macro, falling through to the parent.
+  pub(super) fn fn_macro(&self, name: &str) -> Option<(&String, &FnMacroDef)> {
+    if let Some(fn_macro) = self.fn_macros.get_key_value(name) {
+      return Some(fn_macro)
+    }
+
+    if self.is_shadowed(name) {
+      return None
+    }
+
+    self.parent.as_ref()?.fn_macro(name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ExpansionError, MacroKind, Redefinition, SourceLocation};
+
+  #[test]
+  fn overlay() {
+    let mut base = MacroSet::new();
+    base.define_var_macro("A", ["1"]).unwrap();
+    base.define_fn_macro("F", ["x"], ["x", "+", "A"]).unwrap();
+    base.define_var_macro_with_location("B", ["2"], SourceLocation::new(3, 9)).unwrap();
+    let base = Arc::new(base);
+
+    let mut overlay = MacroSet::overlay(base.clone());
+    assert_eq!(overlay.expand(&["F", "(", "B", ")"]), base.expand(&["2", "+", "1"]));
+    assert_eq!(overlay.location("B"), Some(&SourceLocation::new(3, 9)));
+    assert_eq!(overlay.len(), 3);
+
+    // Redefining and undefining only affects the overlay.
+    assert!(matches!(overlay.define_var_macro("A", ["1"]), Ok(Redefinition::Identical(_))));
+    assert!(matches!(overlay.define_fn_macro("A", ["y"], ["y"]), Ok(Redefinition::KindChanged(_))));
+    assert!(overlay.undefine_var_macro("B"));
+    assert!(!overlay.undefine_var_macro("B"));
+    assert_eq!(overlay.expand(&["F", "(", "B", ")"]), MacroSet::new().expand(&["B", "+", "A"]));
+    assert_eq!(overlay.location("B"), None);
+    assert_eq!(overlay.expand_var_macro("B"), Err(ExpansionError::MacroNotFound));
+    assert_eq!(base.expand_var_macro("B"), base.expand(&["2"]));
+
+    let definitions = overlay.definitions().map(|d| (d.name(), d.kind())).collect::<Vec<_>>();
+    assert_eq!(definitions, [("A", MacroKind::Fn), ("F", MacroKind::Fn)]);
+
+    // Overlays can be stacked.
+    let mut nested = MacroSet::overlay(Arc::new(overlay));
+    nested.define_var_macro("B", ["3"]).unwrap();
+    assert!(nested.undefine_fn_macro("A"));
+    assert_eq!(nested.expand(&["F", "(", "B", ")"]), MacroSet::new().expand(&["3", "+", "A"]));
+    assert_eq!(nested.definitions().map(|d| d.name()).collect::<Vec<_>>(), ["F", "B"]);
+  }
+
+  #[test]
+  fn merge_and_diff() {
+    let mut base = MacroSet::new();
+    base.define_var_macro("A", ["1"]).unwrap();
+    base.define_var_macro("B", ["2"]).unwrap();
+    base.define_fn_macro("C", ["x"], ["x"]).unwrap();
+    let base = Arc::new(base);
+
+    let mut overlay = MacroSet::overlay(base.clone());
+    overlay.define_var_macro("D", ["4"]).unwrap();
+    overlay.define_var_macro("A", ["/* one */", "1"]).unwrap();
+    overlay.define_var_macro("C", ["x"]).unwrap();
+    overlay.undefine_var_macro("B");
+
+    let diff = overlay.diff(&base);
+    assert_eq!(diff.added(), ["D"]);
+    assert_eq!(diff.changed(), ["C"]);
+    assert_eq!(diff.removed(), ["B"]);
+    assert!(overlay.diff(&overlay).is_empty());
+
+    let mut flattened = MacroSet::clone(&base);
+    flattened.merge(&overlay);
+    assert!(flattened.diff(&overlay).is_empty());
+    assert_eq!(flattened.definitions().map(|d| d.name()).collect::<Vec<_>>(), ["A", "C", "D"]);
+    assert_eq!(flattened.expand(&["C"]), MacroSet::new().expand(&["x"]));
+  }
+}
@@ -0,0 +1,198 @@
+use super::{ExpansionError, MacroSet, Traced};
+
+/// Limits for expanding macros, see [`MacroSet::set_limits`].
+///
+/// By default, nothing is limited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+  max_depth: Option<usize>,
+  max_tokens: Option<usize>,
+  max_argument_tokens: Option<usize>,
+}
+
+impl Limits {
+  /// Create limits which do not limit anything.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Set the maximum nesting depth of macro expansions.
+  ///
+  /// This counts both the replacement lists which are being rescanned as well as
+  /// the function-like macro invocations whose arguments are being expanded, e.g.
+  /// expanding `X(X(X(1)))` requires a depth of 3.
+  pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+    self.max_depth = Some(max_depth);
+    self
+  }
+
+  /// Set the maximum number of tokens while expanding a macro.
+  ///
+  /// This includes both the tokens which are already expanded and the tokens
+  /// which still need to be rescanned.
+  pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+    self.max_tokens = Some(max_tokens);
+    self
+  }
+
+  /// Set the maximum number of tokens in an argument of a function-like macro,
+  /// both before and after the argument is expanded.
+  pub fn with_max_argument_tokens(mut self, max_argument_tokens: usize) -> Self {
+    self.max_argument_tokens = Some(max_argument_tokens);
+    self
+  }
+
+  /// Get the maximum nesting depth, if set.
+  pub fn max_depth(&self) -> Option<usize> {
+    self.max_depth
+  }
+
+  /// Get the maximum number of tokens, if set.
+  pub fn max_tokens(&self) -> Option<usize> {
+    self.max_tokens
+  }
+
+  /// Get the maximum number of tokens in an argument, if set.
+  pub fn max_argument_tokens(&self) -> Option<usize> {
+    self.max_argument_tokens
+  }
+}
+
+impl MacroSet {
+  /// Set the [`Limits`] for expanding macros.
+  ///
+  /// Exceeding a limit results in [`ExpansionError::DepthLimitExceeded`],
+  /// [`ExpansionError::TokenLimitExceeded`] or [`ExpansionError::ArgumentLimitExceeded`],
+  /// which contain the name of the macro being expanded when the limit was exceeded.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{ExpansionError, Limits, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_fn_macro("TWICE", ["x"], ["x", "x"])?;
+  /// macro_set.set_limits(Limits::new().with_max_tokens(40));
+  ///
+  /// assert_eq!(macro_set.expand(&["TWICE", "(", "TWICE", "(", "1", ")", ")"])?.len(), 4);
+  /// assert_eq!(
+  ///   macro_set.expand(&["TWICE", "(", "TWICE", "(", "TWICE", "(", "TWICE", "(", "(", "1", ")", ")", ")", ")", ")"]),
+  ///   Err(ExpansionError::TokenLimitExceeded { name: "TWICE".into(), limit: 40 }),
+  /// );
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_limits(&mut self, limits: Limits) {
+    self.limits = limits;
+    self.cache.clear();
+  }
+
+  /// Get the [`Limits`] for expanding macros.
+  pub fn limits(&self) -> Limits {
+    self.limits
+  }
+
+  /// Check the nesting depth before expanding the given macro.
+  pub(super) fn check_depth(&self, name: &str, depth: usize) -> Result<(), ExpansionError> {
+    match self.limits.max_depth {
+      Some(limit) if depth > limit => Err(ExpansionError::DepthLimitExceeded { name: name.to_owned(), limit }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Check the number of tokens after expanding the given macro.
+  pub(super) fn check_tokens(&self, name: &str, tokens: usize) -> Result<(), ExpansionError> {
+    match self.limits.max_tokens {
+      Some(limit) if tokens > limit => Err(ExpansionError::TokenLimitExceeded { name: name.to_owned(), limit }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Check the size of an argument of the given macro.
+  pub(super) fn check_argument(&self, name: &str, arg: &[Traced<'_>]) -> Result<(), ExpansionError> {
+    match self.limits.max_argument_tokens {
+      Some(limit) if arg.len() > limit => Err(ExpansionError::ArgumentLimitExceeded { name: name.to_owned(), limit }),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::SourceLocation;
+
+  #[test]
+  fn max_depth() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("X", ["x"], ["x"]).unwrap();
+    macro_set.define_var_macro("A", ["B"]).unwrap();
+    macro_set.define_var_macro("B", ["C"]).unwrap();
+    macro_set.define_var_macro("C", ["X", "(", "1", ")"]).unwrap();
+    macro_set.set_limits(Limits::new().with_max_depth(3));
+
+    assert_eq!(macro_set.expand(&["X", "(", "X", "(", "X", "(", "1", ")", ")", ")"]), macro_set.expand(&["1"]));
+    assert_eq!(
+      macro_set.expand(&["X", "(", "X", "(", "X", "(", "X", "(", "1", ")", ")", ")", ")"]),
+      Err(ExpansionError::DepthLimitExceeded { name: "X".into(), limit: 3 })
+    );
+    assert_eq!(macro_set.expand(&["B"]), macro_set.expand(&["1"]));
+    assert_eq!(macro_set.expand(&["A"]), Err(ExpansionError::DepthLimitExceeded { name: "X".into(), limit: 3 }));
+
+    // Deeply nested invocations are rejected before running out of stack space.
+    macro_set.set_limits(Limits::new().with_max_depth(100));
+    let mut body = ["X", "("].repeat(10_000);
+    body.push("1");
+    body.extend([")"].repeat(10_000));
+    assert_eq!(macro_set.expand(&body), Err(ExpansionError::DepthLimitExceeded { name: "X".into(), limit: 100 }));
+  }
+
+  #[test]
+  fn max_tokens() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define X0 x
+        #define X1 X0 X0
+        #define X2 X1 X1
+        #define X3 X2 X2
+        "#,
+      )
+      .unwrap();
+    macro_set.set_limits(Limits::new().with_max_tokens(4));
+
+    assert_eq!(macro_set.expand_var_macro("X2").map(|tokens| tokens.len()), Ok(4));
+    assert_eq!(
+      macro_set.expand_var_macro("X3"),
+      Err(ExpansionError::TokenLimitExceeded { name: "X1".into(), limit: 4 }.with_location(SourceLocation::new(3, 17)))
+    );
+  }
+
+  #[test]
+  fn max_argument_tokens() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("F", ["a", "b"], ["a", "b"]).unwrap();
+    macro_set.define_var_macro("LONG", ["1", "2", "3"]).unwrap();
+    macro_set.set_limits(Limits::new().with_max_argument_tokens(2));
+
+    assert_eq!(macro_set.expand(&["F", "(", "1", "2", ",", "3", ")"]).map(|tokens| tokens.len()), Ok(3));
+    assert_eq!(
+      macro_set.expand(&["F", "(", "1", "2", "3", ",", ")"]),
+      Err(ExpansionError::ArgumentLimitExceeded { name: "F".into(), limit: 2 })
+    );
+    assert_eq!(
+      macro_set.expand(&["F", "(", "LONG", ",", ")"]),
+      Err(ExpansionError::ArgumentLimitExceeded { name: "F".into(), limit: 2 })
+    );
+    assert_eq!(
+      macro_set.expand_fn_macro_call("F", &[vec!["1", "2", "3"], vec![]]),
+      Err(ExpansionError::ArgumentLimitExceeded { name: "F".into(), limit: 2 })
+    );
+  }
+}
@@ -5,13 +5,55 @@ use std::{
   fmt,
   iter::IntoIterator,
   mem,
+  sync::Arc,
 };
 
 use crate::{
   ast::{Comment, Identifier, IdentifierContinue, Lit, LitChar, LitString, MacroArg, Punctuation},
-  MacroToken,
+  location::impl_located_error,
+  MacroToken, SourceLocation,
 };
 
+mod batch;
+pub use batch::{Expansion, GenerateError, GeneratedMacro};
+mod builtin;
+mod cache;
+use cache::{Dependencies, Entries, ExpansionCache};
+mod limits;
+pub use limits::Limits;
+mod conditional;
+use conditional::Conditional;
+mod definition;
+use definition::{FnMacroDef, VarMacroDef};
+pub use definition::{MacroDefinition, MacroKind};
+mod dependencies;
+pub use dependencies::{DependencyCycle, DependencyGraph};
+mod dump;
+pub use dump::DefinitionOrder;
+mod evaluate;
+pub use evaluate::{ConditionError, ConditionValue};
+mod feature;
+pub use feature::FeatureQuery;
+use feature::{Features, FEATURE_OPERATORS};
+mod include;
+use include::Includes;
+pub use include::{FileLookup, FileSystem};
+mod layer;
+pub use layer::MacroDiff;
+mod pragma;
+use pragma::PushedMacro;
+mod predefined;
+pub use predefined::{Compiler, DataModel, Profile, Standard};
+mod redefinition;
+pub use redefinition::{PreviousDefinition, Redefinition};
+#[cfg(feature = "serde")]
+mod serialize;
+mod source;
+pub use source::SourceError;
+mod trace;
+use trace::Trace;
+pub use trace::{render_trace, ExpansionStep, TracedToken};
+
 #[cfg(test)]
 pub(crate) mod test_macros;
 
@@ -35,8 +77,8 @@ pub(crate) mod test_macros;
 ///
 /// let mut macro_set = MacroSet::new();
 ///
-/// macro_set.define_var_macro("PI", &["3.14"]);
-/// macro_set.define_fn_macro("TIMES_PI", &["n"], &["n", "*", "PI"]);
+/// macro_set.define_var_macro("PI", &["3.14"])?;
+/// macro_set.define_fn_macro("TIMES_PI", &["n"], &["n", "*", "PI"])?;
 ///
 /// let (args, body) = macro_set.expand_fn_macro("TIMES_PI")?;
 /// assert_eq!(args, vec![MacroToken::Identifier(Identifier::try_from("n")?)]);
@@ -50,12 +92,28 @@ pub(crate) mod test_macros;
 /// ```
 #[derive(Debug, Clone, Default)]
 pub struct MacroSet {
-  var_macros: HashMap<String, Vec<String>>,
-  fn_macros: HashMap<String, (Vec<String>, Vec<String>)>,
+  var_macros: HashMap<String, VarMacroDef>,
+  fn_macros: HashMap<String, FnMacroDef>,
+  conditionals: Vec<Conditional>,
+  includes: Includes,
+  pushed_macros: HashMap<String, Vec<PushedMacro>>,
+  locations: HashMap<String, SourceLocation>,
+  order: HashMap<String, usize>,
+  next_order: usize,
+  parent: Option<Arc<MacroSet>>,
+  undefined: HashSet<String>,
+  gnu_extensions: bool,
+  strict_redefinitions: bool,
+  counter: usize,
+  timestamp: u64,
+  features: Features,
+  cache: ExpansionCache,
+  limits: Limits,
 }
 
 /// An error during macro expansion.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExpansionError {
   /// Macro not found.
   MacroNotFound,
@@ -84,8 +142,48 @@ pub enum ExpansionError {
   InvalidConcat,
   /// Invalid token used in argument
   InvalidArgumentName,
+  /// Condition of an `#if` or `#elif` directive cannot be evaluated.
+  InvalidCondition(ConditionError),
+  /// `#elif`, `#else` or `#endif` without a matching `#if`.
+  UnmatchedConditional,
+  /// `#elif` or `#else` after `#else`.
+  ConditionalAfterElse,
+  /// `__VA_OPT__` used in non-variadic macro, nested or not followed by parenthesized tokens.
+  InvalidVarOpt,
+  /// Macro redefined incompatibly in strict mode, see [`MacroSet::set_strict_redefinitions`].
+  IncompatibleRedefinition(String),
+  /// Maximum nesting depth exceeded, see [`Limits::with_max_depth`].
+  DepthLimitExceeded {
+    /// The macro name.
+    name: String,
+    /// The maximum nesting depth.
+    limit: usize,
+  },
+  /// Maximum number of tokens exceeded, see [`Limits::with_max_tokens`].
+  TokenLimitExceeded {
+    /// The macro name.
+    name: String,
+    /// The maximum number of tokens.
+    limit: usize,
+  },
+  /// Maximum number of tokens in a macro argument exceeded, see [`Limits::with_max_argument_tokens`].
+  ArgumentLimitExceeded {
+    /// The macro name.
+    name: String,
+    /// The maximum number of tokens in an argument.
+    limit: usize,
+  },
+  /// An error at the given location.
+  Located {
+    /// The source location.
+    location: SourceLocation,
+    /// The error.
+    error: Box<Self>,
+  },
 }
 
+impl_located_error!(ExpansionError);
+
 impl fmt::Display for ExpansionError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
@@ -101,6 +199,21 @@ impl fmt::Display for ExpansionError {
       Self::StringifyNonArgument => "`#` is not followed by a macro argument".fmt(f),
       Self::InvalidConcat => "concatenation does not produce a valid pre-processing token".fmt(f),
       Self::InvalidArgumentName => "argument name is not an identifier".fmt(f),
+      Self::InvalidCondition(err) => write!(f, "invalid condition: {err}"),
+      Self::UnmatchedConditional => "`#elif`, `#else` or `#endif` without matching `#if`".fmt(f),
+      Self::ConditionalAfterElse => "`#elif` or `#else` after `#else`".fmt(f),
+      Self::InvalidVarOpt => "`__VA_OPT__` is not used in a variadic macro or not followed by `(...)`".fmt(f),
+      Self::IncompatibleRedefinition(name) => write!(f, "macro {name} is redefined incompatibly"),
+      Self::DepthLimitExceeded { name, limit } => {
+        write!(f, "expanding macro {name} exceeds the maximum nesting depth of {limit}")
+      },
+      Self::TokenLimitExceeded { name, limit } => {
+        write!(f, "expanding macro {name} exceeds the maximum of {limit} tokens")
+      },
+      Self::ArgumentLimitExceeded { name, limit } => {
+        write!(f, "argument of macro {name} exceeds the maximum of {limit} tokens")
+      },
+      Self::Located { location, error } => write!(f, "{location}: {error}"),
     }
   }
 }
@@ -116,7 +229,14 @@ fn is_whitespace(s: &str) -> bool {
   s.is_empty() || is_comment(s)
 }
 
-fn tokenize_arg_names<'s, 't, T>(arg_names: &'s [T]) -> Result<Vec<Token<'t>>, ExpansionError>
+/// Tokenize the parameters of a function-like macro.
+///
+/// If `gnu_extensions` is enabled, the last parameter may be a named variadic
+/// parameter, e.g. `args...`, whose name is returned along with the parameters.
+fn tokenize_arg_names<'s, 't, T>(
+  arg_names: &'s [T],
+  gnu_extensions: bool,
+) -> Result<(Vec<Token<'t>>, Option<&'t str>), ExpansionError>
 where
   's: 't,
   T: AsRef<str> + 't,
@@ -125,9 +245,28 @@ where
   let mut arg_names = vec![];
 
   let mut is_variadic = false;
+  let mut var_args_name = None;
 
   for token in it {
-    let token = Token::from_str(token.as_ref());
+    let token = token.as_ref();
+
+    let token = match token.strip_suffix("...") {
+      Some(name) if gnu_extensions && Identifier::try_from(name).is_ok() => {
+        if is_variadic {
+          return Err(ExpansionError::NonUniqueArgument("...".into()))
+        }
+
+        if arg_names.contains(&Token::from_str(name)) {
+          return Err(ExpansionError::NonUniqueArgument(name.to_owned()))
+        }
+
+        is_variadic = true;
+        var_args_name = Some(name);
+        arg_names.push(Token::Punctuation(Punctuation { punctuation: "..." }));
+        continue
+      },
+      _ => Token::from_str(token),
+    };
 
     match token {
       Token::Identifier(ref id) => {
@@ -153,7 +292,7 @@ where
     arg_names.push(token)
   }
 
-  Ok(arg_names)
+  Ok((arg_names, var_args_name))
 }
 
 fn tokenize<'t, T>(arg_names: &[Token<'t>], tokens: &'t [T]) -> Vec<Token<'t>>
@@ -190,6 +329,8 @@ impl<'t> Token<'t> {
   pub fn from_str(token: &'t str) -> Self {
     if token == "__VA_ARGS__" {
       Token::VarArgs
+    } else if token == "__VA_OPT__" {
+      Token::VarOpt
     } else if let Ok(identifier) = Identifier::try_from(token) {
       Self::Identifier(identifier)
     } else if let Ok(literal) = Lit::try_from(token) {
@@ -217,6 +358,7 @@ impl<'t> Token<'t> {
           Append("__VA_ARGS__")
         }
       },
+      Self::VarOpt => Append("__VA_OPT__"),
       Self::Identifier(id) => match id.id.as_ref() {
         "__LINE__" | "__FILE__" if nested => Keep,
         t => Append(t),
@@ -235,6 +377,7 @@ impl<'t> Token<'t> {
     Ok(Some(match self {
       Self::MacroArg(arg_index) => MacroToken::Arg(MacroArg { index: arg_index }),
       Self::VarArgs => MacroToken::Arg(MacroArg { index: arg_names.len() - 1 }),
+      Self::VarOpt => MacroToken::Identifier(Identifier { id: Cow::Borrowed("__VA_OPT__") }),
       Self::Identifier(id) => MacroToken::Identifier(id),
       Self::IdentifierContinue(id_cont) => MacroToken::IdentifierContinue(id_cont),
       Self::Literal(lit, _) => MacroToken::Lit(lit),
@@ -326,8 +469,8 @@ impl<'t> Token<'t> {
       },
       (Self::Punctuation(_lhs), _) => return Err(ExpansionError::InvalidConcat),
       (_, Self::Punctuation(_rhs)) => return Err(ExpansionError::InvalidConcat),
-      (Self::MacroArg(_) | Self::VarArgs | Self::Comment(_), _)
-      | (_, Self::MacroArg(_) | Self::VarArgs | Self::Comment(_)) => unreachable!(),
+      (Self::MacroArg(_) | Self::VarArgs | Self::VarOpt | Self::Comment(_), _)
+      | (_, Self::MacroArg(_) | Self::VarArgs | Self::VarOpt | Self::Comment(_)) => unreachable!(),
     };
 
     Ok(if let Ok(identifier) = Identifier::try_from(new_token.as_ref()) {
@@ -388,6 +531,14 @@ fn detokenize<'t>(arg_names: &[Token<'t>], tokens: Vec<Token<'t>>) -> Result<Vec
   tokens.into_iter().filter_map(|t| t.detokenize(arg_names).transpose()).collect()
 }
 
+fn traced<'t>(tokens: Vec<Token<'t>>, trace: &Trace) -> Vec<Traced<'t>> {
+  tokens.into_iter().map(|token| Traced { token, trace: trace.clone() }).collect()
+}
+
+fn untraced(tokens: Vec<Traced<'_>>) -> Vec<Token<'_>> {
+  tokens.into_iter().map(|t| t.token).collect()
+}
+
 enum CollectArgsError {
   /// Open parenthesis not found.
   MissingOpenParenthesis(char),
@@ -403,6 +554,8 @@ enum Token<'t> {
   MacroArg(usize),
   /// Variable macro arguments.
   VarArgs,
+  /// `__VA_OPT__`
+  VarOpt,
   /// Punctuation.
   Punctuation(Punctuation<'t>),
   /// An identifier.
@@ -419,113 +572,324 @@ enum Token<'t> {
   Placemarker,
 }
 
+/// A token along with the steps producing it.
+#[derive(Debug, Clone)]
+struct Traced<'t> {
+  token: Token<'t>,
+  trace: Trace,
+}
+
+/// State shared by all scans during an expansion.
+#[derive(Debug, Default)]
+struct ExpansionState<'m> {
+  /// The names looked up during the expansion.
+  dependencies: Dependencies,
+  /// The number of macro invocations enclosing the current scan.
+  depth: usize,
+  /// The local memo of expansions in addition to the shared cache, see [`MacroSet::expand_all`].
+  memo: Option<&'m mut Entries>,
+  /// The next value of `__COUNTER__`, starting at [`MacroSet::set_counter`] on first use.
+  counter: Option<usize>,
+}
+
+impl<'m> ExpansionState<'m> {
+  /// State for expanding the given macro.
+  fn new(name: &str) -> Self {
+    Self { dependencies: Dependencies::new(name), ..Self::default() }
+  }
+
+  /// State for expanding the given macro using a local memo.
+  fn local(name: &str, memo: &'m mut Entries) -> Self {
+    Self { memo: Some(memo), ..Self::new(name) }
+  }
+}
+
+impl<'t> Traced<'t> {
+  fn map(self, f: impl FnOnce(Token<'t>) -> Token<'t>) -> Self {
+    Self { token: f(self.token), trace: self.trace }
+  }
+
+  fn is_punctuation(&self, punctuation: &str) -> bool {
+    matches!(&self.token, Token::Punctuation(p) if p.as_str() == punctuation)
+  }
+}
+
 impl MacroSet {
   /// Create a new macro set.
   pub fn new() -> Self {
     Self::default()
   }
 
-  fn contains_var_args(body: &[Token<'_>]) -> bool {
-    body.iter().any(|t| *t == Token::VarArgs)
+  /// Enable or disable GNU extensions.
+  ///
+  /// With GNU extensions enabled, the last parameter of a function-like macro may be
+  /// a named variadic parameter, e.g. `args...`, which can be used instead of `__VA_ARGS__`.
+  /// Additionally, a comma followed by `##` and the variadic arguments is removed if
+  /// the variadic arguments are empty:
+  ///
+  /// ```c
+  /// #define LOG(fmt, ...) printf(fmt, ## __VA_ARGS__)
+  /// #define LOG2(fmt, args...) printf(fmt, ##args)
+  ///
+  /// LOG("hello")    // printf("hello")
+  /// LOG2("%d", 42)  // printf("%d", 42)
+  /// ```
+  pub fn set_gnu_extensions(&mut self, enabled: bool) {
+    self.gnu_extensions = enabled;
+    self.cache.clear();
+  }
+
+  /// Check whether GNU extensions are enabled.
+  pub fn gnu_extensions(&self) -> bool {
+    self.gnu_extensions
+  }
+
+  fn contains_var_args(body: &[Traced<'_>]) -> bool {
+    body.iter().any(|t| t.token == Token::VarArgs)
+  }
+
+  fn contains_var_opt(body: &[Traced<'_>]) -> bool {
+    body.iter().any(|t| t.token == Token::VarOpt)
+  }
+
+  /// Check whether an argument contains no tokens other than comments.
+  fn is_empty_arg(arg: &[Traced<'_>]) -> bool {
+    arg.iter().all(|t| matches!(t.token, Token::Comment(_) | Token::Placemarker))
   }
 
-  fn expand_macro_body<'s, 't>(
+  /// Rescan the given tokens, replacing macro invocations.
+  ///
+  /// Instead of expanding replacement lists recursively, they are pushed onto a
+  /// work-list, so that the remaining tokens are only scanned once. While the
+  /// tokens of a replacement list are being rescanned, the name of its macro is
+  /// not replaced again, i.e. it is marked as [`Token::NonReplacable`].
+  fn expand_macro_body<'s, 't, 'n>(
     &'s self,
-    non_replaced_names: HashSet<&str>,
-    body: &[Token<'t>],
-  ) -> Result<Vec<Token<'t>>, ExpansionError>
+    mut non_replaced_names: HashSet<&'n str>,
+    body: &[Traced<'t>],
+    state: &mut ExpansionState<'_>,
+  ) -> Result<Vec<Traced<'t>>, ExpansionError>
   where
-    's: 't,
+    's: 'n,
   {
     let mut tokens = vec![];
-    let mut it = body.iter().cloned().peekable();
+    // The remaining tokens in reverse order.
+    let mut input = body.iter().rev().cloned().collect::<Vec<_>>();
+    // The macros whose replacement lists are being rescanned, along with
+    // the length of `input` before the replacement list was pushed.
+    let mut contexts: Vec<(&'s str, usize)> = vec![];
+
+    while let Some(token) = input.pop() {
+      Self::leave_contexts(&mut contexts, &mut non_replaced_names, input.len());
+
+      match token.token {
+        Token::Identifier(ref id) if id.as_str() == "_Pragma" => {
+          tokens.push(token);
+
+          // The operand of `_Pragma` is not macro-expanded.
+          if input.last().map(|t| t.is_punctuation("(")).unwrap_or(false) {
+            let mut depth = 0;
+
+            while let Some(token) = input.pop() {
+              if token.is_punctuation("(") {
+                depth += 1;
+              } else if token.is_punctuation(")") {
+                depth -= 1;
+              }
 
-    while let Some(token) = it.next() {
-      match token {
+              tokens.push(token);
+
+              if depth == 0 {
+                break
+              }
+            }
+          }
+        },
         Token::Identifier(ref id) => {
           if non_replaced_names.contains(id.id.as_ref()) {
-            tokens.push(Token::NonReplacable(Box::new(token)));
-          } else {
-            // Treat as function-like macro call if immediately followed by `(`.
-            if it.peek() == Some(&Token::Punctuation(Punctuation { punctuation: "(" })) {
-              if let Some((arg_names, body)) = self.fn_macros.get(id.id.as_ref()) {
-                if let Ok(args) = self.collect_args(&mut it) {
-                  let arg_names = tokenize_arg_names(arg_names)?;
-                  let body = tokenize(&arg_names, body);
-                  let expanded_tokens = self.expand_fn_macro_body(
-                    non_replaced_names.clone(),
-                    id.id.as_ref(),
-                    &arg_names,
-                    Some(&args),
-                    &body,
-                  )?;
-                  tokens.extend(expanded_tokens);
-                  tokens.extend(it);
-                  return self.expand_macro_body(non_replaced_names, &tokens)
-                }
+            tokens.push(token.map(|t| Token::NonReplacable(Box::new(t))));
+            continue
+          }
+
+          state.dependencies.insert(id.as_str());
+
+          // Treat as function-like macro call if immediately followed by `(`.
+          if input.last().map(|t| t.is_punctuation("(")).unwrap_or(false) {
+            // Feature operators can be overridden by function-like macros.
+            if FEATURE_OPERATORS.contains(&id.as_str()) && self.fn_macro(id.as_str()).is_none() {
+              if let Some(args) = self.take_args(&mut input, &contexts) {
+                let value = self.evaluate_feature(id.as_str(), &args).map_err(|err| match contexts.last() {
+                  Some((name, _)) => self.locate(name, err),
+                  None => err,
+                })?;
+                tokens.push(token.map(|_| value));
+                continue
               }
             }
 
-            // If it's not a macro call, check if it is a variable-like macro.
-            if let Some(body) = self.var_macros.get(id.id.as_ref()) {
-              let body = tokenize(&[], body);
-              tokens.extend(self.expand_var_macro_body(non_replaced_names.clone(), id.id.as_ref(), &body)?);
-              tokens.extend(it);
-              return self.expand_macro_body(non_replaced_names, &tokens)
+            if let Some((name, fn_macro)) = self.fn_macro(id.id.as_ref()) {
+              if let Some(args) = self.take_args(&mut input, &contexts) {
+                // The arguments may extend past the end of the current replacement lists.
+                Self::leave_contexts(&mut contexts, &mut non_replaced_names, input.len());
+
+                // The arguments are expanded inside of this invocation.
+                let depth = contexts.len() + 1;
+                self.check_depth(name, state.depth + depth).map_err(|err| self.locate(name, err))?;
+
+                let body = traced(fn_macro.tokens.clone(), &token.trace.with(|| ExpansionStep::Macro(name.clone())));
+                state.depth += depth;
+                let replacement =
+                  self.fn_macro_replacement(&non_replaced_names, name, &fn_macro.arg_tokens, Some(&args), &body, state);
+                state.depth -= depth;
+                let replacement = replacement.map_err(|err| self.locate(name, err))?;
+
+                self
+                  .check_tokens(name, tokens.len() + input.len() + replacement.len())
+                  .map_err(|err| self.locate(name, err))?;
+                contexts.push((name, input.len()));
+                non_replaced_names.insert(name);
+                input.extend(replacement.into_iter().rev());
+                continue
+              }
             }
+          }
+
+          // If it's not a macro call, check if it is a variable-like macro.
+          if let Some((name, var_macro)) = self.var_macro(id.id.as_ref()) {
+            self.check_depth(name, state.depth + contexts.len() + 1).map_err(|err| self.locate(name, err))?;
+
+            // Cached expansions are rescanned along with the following tokens.
+            let cached = if token.trace.is_enabled() {
+              None
+            } else {
+              state
+                .memo
+                .as_ref()
+                .and_then(|memo| memo.get_nested(name, &non_replaced_names, &mut state.dependencies))
+                .or_else(|| self.cache.get_nested(name, &non_replaced_names, &mut state.dependencies))
+            };
+            let replacement = match cached {
+              Some(tokens) => traced(tokens, &token.trace),
+              None => {
+                let body = traced(var_macro.tokens.clone(), &token.trace.with(|| ExpansionStep::Macro(name.clone())));
+                Self::var_macro_replacement(&body).map_err(|err| self.locate(name, err))?
+              },
+            };
+
+            self
+              .check_tokens(name, tokens.len() + input.len() + replacement.len())
+              .map_err(|err| self.locate(name, err))?;
+            contexts.push((name, input.len()));
+            non_replaced_names.insert(name);
+            input.extend(replacement.into_iter().rev());
+            continue
+          }
 
+          if let Some(builtin) = self.expand_builtin(id.id.as_ref(), state) {
+            tokens.push(token.map(|_| builtin))
+          } else {
             tokens.push(token)
           }
         },
-        token => tokens.push(token),
+        _ => tokens.push(token),
       }
     }
 
     Ok(tokens)
   }
 
-  fn expand_var_macro_body<'s, 't, 'n>(
-    &'s self,
-    mut non_replaced_names: HashSet<&'n str>,
-    name: &'n str,
-    body: &[Token<'t>],
-  ) -> Result<Vec<Token<'t>>, ExpansionError>
-  where
-    's: 't,
-  {
-    // A variable-like macro shall not contain `__VA_ARGS__`.
+  /// Leave the replacement lists which end before the given position in the work-list.
+  fn leave_contexts(contexts: &mut Vec<(&str, usize)>, non_replaced_names: &mut HashSet<&str>, position: usize) {
+    while let Some(&(name, end)) = contexts.last() {
+      if end <= position {
+        break
+      }
+
+      contexts.pop();
+      non_replaced_names.remove(name);
+    }
+  }
+
+  /// Take the arguments of a function-like macro invocation from the work-list.
+  ///
+  /// Identifiers in the arguments which are part of the replacement list of a macro
+  /// in `contexts` are marked as [`Token::NonReplacable`] if they are the name of that
+  /// macro, even if the arguments extend past the end of the replacement list, since
+  /// they were encountered while the macro was being rescanned (C11 6.10.3.4p2).
+  fn take_args<'t>(&self, input: &mut Vec<Traced<'t>>, contexts: &[(&str, usize)]) -> Option<Vec<Vec<Traced<'t>>>> {
+    let mut it = input.iter().rev().cloned();
+    self.collect_args(&mut it).ok()?;
+    let remaining = it.len();
+
+    for (position, token) in input.iter_mut().enumerate().skip(remaining) {
+      if let Token::Identifier(ref id) = token.token {
+        if contexts.iter().any(|&(name, end)| end <= position && name == id.as_str()) {
+          token.token = Token::NonReplacable(Box::new(token.token.clone()));
+        }
+      }
+    }
+
+    let args = self.collect_args(&mut input.iter().rev().cloned()).ok()?;
+    input.truncate(remaining);
+
+    Some(args)
+  }
+
+  /// Get the replacement list of a variable-like macro, without rescanning it.
+  fn var_macro_replacement<'t>(body: &[Traced<'t>]) -> Result<Vec<Traced<'t>>, ExpansionError> {
+    // A variable-like macro shall not contain `__VA_ARGS__` or `__VA_OPT__`.
     if Self::contains_var_args(body) {
       return Err(ExpansionError::NonVariadicVarArgs)
     }
+    if Self::contains_var_opt(body) {
+      return Err(ExpansionError::InvalidVarOpt)
+    }
 
     let mut body = Self::expand_concat(body.to_vec())?;
     Self::remove_placemarkers(&mut body);
 
-    non_replaced_names.insert(name);
-
-    self.expand_macro_body(non_replaced_names, &body)
+    Ok(body)
   }
 
-  fn expand_fn_macro_body<'s, 't, 'n>(
+  fn expand_var_macro_body<'s, 't, 'n>(
     &'s self,
     mut non_replaced_names: HashSet<&'n str>,
     name: &'n str,
-    arg_names: &[Token<'t>],
-    args: Option<&[Vec<Token<'t>>]>,
-    body: &[Token<'t>],
-  ) -> Result<Vec<Token<'t>>, ExpansionError>
+    body: &[Traced<'t>],
+    state: &mut ExpansionState<'_>,
+  ) -> Result<Vec<Traced<'t>>, ExpansionError>
   where
-    's: 't,
+    's: 'n,
   {
+    let body = Self::var_macro_replacement(body)?;
+
+    non_replaced_names.insert(name);
+
+    self.expand_macro_body(non_replaced_names, &body, state)
+  }
+
+  /// Get the replacement list of a function-like macro, without rescanning it.
+  fn fn_macro_replacement<'t>(
+    &self,
+    non_replaced_names: &HashSet<&str>,
+    name: &str,
+    arg_names: &[Token<'t>],
+    args: Option<&[Vec<Traced<'t>>]>,
+    body: &[Traced<'t>],
+    state: &mut ExpansionState<'_>,
+  ) -> Result<Vec<Traced<'t>>, ExpansionError> {
     let arg_names: Vec<_> = arg_names.iter().filter(|t| !matches!(t, Token::Comment(_))).cloned().collect();
 
     let is_variadic = matches!(arg_names.last(), Some(Token::Punctuation(p)) if p == "...");
 
     if !is_variadic {
-      // A function-like macro shall only contain `__VA_ARGS__` if it uses ellipsis notation in the parameters.
+      // A function-like macro shall only contain `__VA_ARGS__` or `__VA_OPT__` if it uses ellipsis notation in the parameters.
       if Self::contains_var_args(body) {
         return Err(ExpansionError::NonVariadicVarArgs)
       }
+      if Self::contains_var_opt(body) {
+        return Err(ExpansionError::InvalidVarOpt)
+      }
 
       if let Some(args) = args {
         if arg_names.len() != args.len()
@@ -539,6 +903,15 @@ impl MacroSet {
           })
         }
       }
+    } else if let Some(args) = args {
+      // The variable arguments may be empty, but all named parameters require an argument.
+      if args.len() < arg_names.len() - 1 {
+        return Err(ExpansionError::FnMacroArgumentError {
+          name: name.to_owned(),
+          required: arg_names.len() - 1,
+          given: args.len(),
+        })
+      }
     }
 
     // Parameter names must be unique.
@@ -560,7 +933,11 @@ impl MacroSet {
     }
 
     let body = if let Some(args) = args {
-      self.expand_arguments(non_replaced_names.clone(), &arg_names, args, body)?
+      for arg in args {
+        self.check_argument(name, arg)?;
+      }
+
+      self.expand_arguments(non_replaced_names.clone(), name, &arg_names, args, body, state)?
     } else {
       body.to_vec()
     };
@@ -568,15 +945,31 @@ impl MacroSet {
     let mut body = Self::expand_concat(body)?;
     Self::remove_placemarkers(&mut body);
 
+    Ok(body)
+  }
+
+  fn expand_fn_macro_body<'s, 't, 'n>(
+    &'s self,
+    mut non_replaced_names: HashSet<&'n str>,
+    name: &'n str,
+    arg_names: &[Token<'t>],
+    args: Option<&[Vec<Traced<'t>>]>,
+    body: &[Traced<'t>],
+    state: &mut ExpansionState<'_>,
+  ) -> Result<Vec<Traced<'t>>, ExpansionError>
+  where
+    's: 'n,
+  {
+    let body = self.fn_macro_replacement(&non_replaced_names, name, arg_names, args, body, state)?;
+
     non_replaced_names.insert(name);
 
-    self.expand_macro_body(non_replaced_names, &body)
+    self.expand_macro_body(non_replaced_names, &body, state)
   }
 
-  fn collect_args<'s, 't, I>(&'s self, it: &mut I) -> Result<Vec<Vec<Token<'t>>>, CollectArgsError>
+  fn collect_args<'t, I>(&self, it: &mut I) -> Result<Vec<Vec<Traced<'t>>>, CollectArgsError>
   where
-    's: 't,
-    I: Iterator<Item = Token<'t>> + Clone,
+    I: Iterator<Item = Traced<'t>> + Clone,
   {
     let mut parentheses = vec![]; // Keep track of parenthesis pairs.
     let mut args = vec![];
@@ -585,143 +978,272 @@ impl MacroSet {
     let mut it2 = it.clone();
 
     match it2.next() {
-      Some(Token::Punctuation(p)) if p == "(" => (),
+      Some(token) if token.is_punctuation("(") => (),
       _ => return Err(CollectArgsError::MissingOpenParenthesis('(')),
     }
 
-    while let Some(token) = it2.next() {
-      match token {
-        Token::Punctuation(p) => {
-          let pop = |parentheses: &mut Vec<char>, open, close| match parentheses.pop() {
-            Some(p) => {
-              if p == open {
-                Ok(())
-              } else {
-                Err(CollectArgsError::UnclosedParenthesis(p))
-              }
-            },
-            None => Err(CollectArgsError::MissingOpenParenthesis(close)),
-          };
+    for token in it2.by_ref() {
+      if let Token::Punctuation(ref p) = token.token {
+        let pop = |parentheses: &mut Vec<char>, open, close| match parentheses.pop() {
+          Some(p) => {
+            if p == open {
+              Ok(())
+            } else {
+              Err(CollectArgsError::UnclosedParenthesis(p))
+            }
+          },
+          None => Err(CollectArgsError::MissingOpenParenthesis(close)),
+        };
+
+        match p.as_str() {
+          "(" => parentheses.push('('),
+          ")" => {
+            if parentheses.is_empty() {
+              args.push(mem::take(&mut current_arg));
+
+              *it = it2;
+              return Ok(args)
+            } else {
+              pop(&mut parentheses, '(', ')')?
+            }
+          },
+          "[" => parentheses.push('['),
+          "]" => pop(&mut parentheses, '[', ']')?,
+          "{" => parentheses.push('{'),
+          "}" => pop(&mut parentheses, '{', '}')?,
+          "," => {
+            if parentheses.is_empty() {
+              args.push(mem::take(&mut current_arg));
+              continue
+            }
+          },
+          _ => (),
+        }
+      }
 
-          match p.as_str() {
-            "(" => parentheses.push('('),
-            ")" => {
-              if parentheses.is_empty() {
-                args.push(mem::take(&mut current_arg));
+      current_arg.push(token);
+    }
 
-                *it = it2;
-                return Ok(args)
-              } else {
-                pop(&mut parentheses, '(', ')')?
-              }
-            },
-            "[" => parentheses.push('['),
-            "]" => pop(&mut parentheses, '[', ']')?,
-            "{" => parentheses.push('{'),
-            "}" => pop(&mut parentheses, '{', '}')?,
-            "," => {
-              if parentheses.is_empty() {
-                args.push(mem::take(&mut current_arg));
-                continue
-              }
-            },
-            _ => (),
-          }
+    Err(CollectArgsError::UnclosedParenthesis('('))
+  }
 
-          current_arg.push(Token::Punctuation(p));
-        },
-        token => current_arg.push(token),
-      }
+  /// Get the name of the parameter corresponding to the given argument token.
+  fn parameter_name(arg_names: &[Token<'_>], token: &Token<'_>) -> String {
+    match token {
+      Token::MacroArg(arg_index) => match &arg_names[*arg_index] {
+        Token::Identifier(id) => id.as_str().to_owned(),
+        _ => "__VA_ARGS__".to_owned(),
+      },
+      Token::VarOpt => "__VA_OPT__".to_owned(),
+      _ => "__VA_ARGS__".to_owned(),
     }
+  }
 
-    Err(CollectArgsError::UnclosedParenthesis('('))
+  /// Record that the given tokens were substituted for a parameter.
+  fn substitute<'t>(arg: Vec<Traced<'t>>, name: &str, parameter: &str) -> Vec<Traced<'t>> {
+    arg
+      .into_iter()
+      .map(|t| Traced {
+        trace: t.trace.with(|| ExpansionStep::Argument { name: name.to_owned(), parameter: parameter.to_owned() }),
+        token: t.token,
+      })
+      .collect()
   }
 
-  fn expand_arguments<'s, 't>(
-    &'s self,
+  /// Stringify the given tokens in place of the preceding `#`.
+  fn stringify_arg<'t>(
+    hash: Traced<'t>,
+    arg: Vec<Traced<'t>>,
+    nested: bool,
+    name: &str,
+    parameter: &str,
+  ) -> Vec<Traced<'t>> {
+    let trace = hash.trace.with(|| ExpansionStep::Stringify { name: name.to_owned(), parameter: parameter.to_owned() });
+    traced(stringify(untraced(arg), nested), &trace)
+  }
+
+  fn expand_arguments<'t>(
+    &self,
     non_replaced_names: HashSet<&str>,
+    name: &str,
     arg_names: &[Token<'t>],
-    args: &[Vec<Token<'t>>],
-    tokens: &[Token<'t>],
-  ) -> Result<Vec<Token<'t>>, ExpansionError>
-  where
-    's: 't,
-  {
+    args: &[Vec<Traced<'t>>],
+    tokens: &[Traced<'t>],
+    state: &mut ExpansionState<'_>,
+  ) -> Result<Vec<Traced<'t>>, ExpansionError> {
+    let nested = non_replaced_names.len() > 1;
+
     let mut it = tokens.iter().cloned().peekable();
-    let mut tokens = vec![];
+    let mut tokens: Vec<Traced<'t>> = vec![];
 
     while let Some(token) = it.next() {
-      match token {
-        Token::Punctuation(ref p) if p == "#" => match it.peek() {
-          Some(Token::MacroArg(_) | Token::VarArgs) => {
+      match token.token {
+        Token::Punctuation(ref p) if p == "#" => match it.peek().map(|t| &t.token) {
+          Some(Token::MacroArg(_) | Token::VarArgs | Token::VarOpt) => {
             tokens.push(token.clone());
           },
           _ => return Err(ExpansionError::StringifyNonArgument),
         },
-        Token::MacroArg(_) | Token::VarArgs => {
-          let arg = if let Token::MacroArg(arg_index) = token {
-            args[arg_index].clone()
+        Token::VarOpt => {
+          let content = Self::collect_var_opt_content(&mut it)?;
+
+          // The content is only used if the variable arguments expand to at least one token.
+          let var_args = self.expand_macro_body(
+            non_replaced_names.clone(),
+            &Self::var_args(arg_names, args, &token.trace),
+            state,
+          )?;
+          let content = if Self::is_empty_arg(&var_args) {
+            vec![]
           } else {
-            let mut var_args = vec![];
+            let content = self.expand_arguments(non_replaced_names.clone(), name, arg_names, args, &content, state)?;
+            Self::expand_concat(content)?
+          };
 
-            for (i, arg) in args[(arg_names.len() - 1)..].iter().enumerate() {
-              if i > 0 {
-                var_args.push(Token::Punctuation(Punctuation { punctuation: "," }));
-              }
-              var_args.extend(arg.clone());
-            }
+          if tokens.last().map(|t| t.is_punctuation("#")).unwrap_or(false) {
+            let hash = tokens.pop().unwrap();
+            state.dependencies.set_stringified();
+            tokens.extend(Self::stringify_arg(hash, content, nested, name, "__VA_OPT__"));
+          } else if content.is_empty() {
+            tokens.push(token.map(|_| Token::Placemarker));
+          } else {
+            tokens.extend(content);
+          }
+        },
+        Token::VarArgs if self.gnu_extensions && Self::is_comma_concat(&tokens) => {
+          let var_args = Self::var_args(arg_names, args, &token.trace);
+
+          // Remove the `##` and also the comma if the variadic arguments are empty.
+          while !tokens.pop().map(|t| t.is_punctuation("##")).unwrap_or(true) {}
+          while matches!(tokens.last(), Some(Traced { token: Token::Comment(_), .. })) {
+            tokens.pop();
+          }
 
-            var_args
+          if Self::is_empty_arg(&var_args) {
+            tokens.pop();
+          } else {
+            let var_args = self.expand_macro_body(non_replaced_names.clone(), &var_args, state)?;
+            self.check_argument(name, &var_args)?;
+            tokens.extend(Self::substitute(var_args, name, "__VA_ARGS__"));
+          }
+        },
+        Token::MacroArg(_) | Token::VarArgs => {
+          let arg = if let Token::MacroArg(arg_index) = token.token {
+            args[arg_index].clone()
+          } else {
+            Self::var_args(arg_names, args, &token.trace)
           };
+          let parameter = Self::parameter_name(arg_names, &token.token);
 
           match tokens.last() {
-            Some(Token::Punctuation(Punctuation { punctuation: "#" })) => {
-              tokens.pop();
-              tokens.extend(stringify(arg, non_replaced_names.len() > 1));
+            Some(t) if t.is_punctuation("#") => {
+              let hash = tokens.pop().unwrap();
+              state.dependencies.set_stringified();
+              tokens.extend(Self::stringify_arg(hash, arg, nested, name, &parameter));
             },
-            Some(Token::Punctuation(Punctuation { punctuation: "##" })) => {
-              let arg = self.expand_macro_body(non_replaced_names.clone(), &arg)?;
-
-              if arg.is_empty() {
-                tokens.push(Token::Placemarker);
+            Some(t) if t.is_punctuation("##") => {
+              if Self::is_empty_arg(&arg) {
+                tokens.push(token.map(|_| Token::Placemarker));
               } else {
-                tokens.extend(arg);
+                tokens.extend(Self::substitute(arg, name, &parameter));
               }
             },
-            _ if it.peek() == Some(&Token::Punctuation(Punctuation { punctuation: "##" })) => {
-              let arg = self.expand_macro_body(non_replaced_names.clone(), &arg)?;
-
-              if arg.is_empty() {
-                tokens.push(Token::Placemarker);
+            _ if it.peek().map(|t| t.is_punctuation("##")).unwrap_or(false) => {
+              if Self::is_empty_arg(&arg) {
+                tokens.push(token.map(|_| Token::Placemarker));
               } else {
-                tokens.extend(arg);
+                tokens.extend(Self::substitute(arg, name, &parameter));
               }
             },
-            _ => tokens.extend(self.expand_macro_body(non_replaced_names.clone(), &arg)?),
+            _ => {
+              let arg = self.expand_macro_body(non_replaced_names.clone(), &arg, state)?;
+              self.check_argument(name, &arg)?;
+              tokens.extend(Self::substitute(arg, name, &parameter))
+            },
           }
         },
-        token => tokens.push(token),
+        _ => tokens.push(token),
       }
     }
 
     Ok(tokens)
   }
 
-  fn expand_concat(tokens: Vec<Token<'_>>) -> Result<Vec<Token<'_>>, ExpansionError> {
+  /// Check whether the given tokens end with `, ##`, ignoring comments.
+  fn is_comma_concat(tokens: &[Traced<'_>]) -> bool {
+    let mut it = tokens.iter().rev().filter(|t| !matches!(t.token, Token::Comment(_)));
+
+    matches!(
+      (it.next(), it.next()),
+      (Some(concat), Some(comma)) if concat.is_punctuation("##") && comma.is_punctuation(",")
+    )
+  }
+
+  /// Join the variable arguments with commas.
+  ///
+  /// The commas are traced like the `__VA_ARGS__` token they are substituted for.
+  fn var_args<'t>(arg_names: &[Token<'t>], args: &[Vec<Traced<'t>>], trace: &Trace) -> Vec<Traced<'t>> {
+    let mut var_args = vec![];
+
+    for (i, arg) in args[(arg_names.len() - 1)..].iter().enumerate() {
+      if i > 0 {
+        var_args.push(Traced { token: Token::Punctuation(Punctuation { punctuation: "," }), trace: trace.clone() });
+      }
+      var_args.extend(arg.clone());
+    }
+
+    var_args
+  }
+
+  /// Collect the parenthesized content following `__VA_OPT__`.
+  fn collect_var_opt_content<'t, I>(it: &mut I) -> Result<Vec<Traced<'t>>, ExpansionError>
+  where
+    I: Iterator<Item = Traced<'t>>,
+  {
+    match it.find(|t| !matches!(t.token, Token::Comment(_))) {
+      Some(token) if token.is_punctuation("(") => (),
+      _ => return Err(ExpansionError::InvalidVarOpt),
+    }
+
+    let mut depth = 0;
+    let mut content = vec![];
+
+    for token in it {
+      match token.token {
+        Token::Punctuation(ref p) if p == "(" => depth += 1,
+        Token::Punctuation(ref p) if p == ")" => {
+          if depth == 0 {
+            return Ok(content)
+          }
+
+          depth -= 1;
+        },
+        // `__VA_OPT__` cannot be nested.
+        Token::VarOpt => return Err(ExpansionError::InvalidVarOpt),
+        _ => (),
+      }
+
+      content.push(token);
+    }
+
+    Err(ExpansionError::InvalidVarOpt)
+  }
+
+  fn expand_concat(tokens: Vec<Traced<'_>>) -> Result<Vec<Traced<'_>>, ExpansionError> {
     let mut it = tokens.into_iter().peekable();
-    let mut tokens = vec![];
+    let mut tokens: Vec<Traced<'_>> = vec![];
 
     while let Some(token) = it.next() {
-      match token {
+      match token.token {
         Token::Punctuation(Punctuation { punctuation: "##" })
-          if !matches!(tokens.last(), Some(&Token::MacroArg(_) | &Token::VarArgs))
-            && !matches!(it.peek(), Some(&Token::MacroArg(_) | &Token::VarArgs)) =>
+          if !matches!(tokens.last().map(|t| &t.token), Some(&Token::MacroArg(_) | &Token::VarArgs))
+            && !matches!(it.peek().map(|t| &t.token), Some(&Token::MacroArg(_) | &Token::VarArgs | &Token::VarOpt)) =>
         {
           macro_rules! until_no_whitespace {
             ($expr:expr, $error:ident) => {{
               loop {
                 match $expr {
-                  Some(Token::Comment(_)) => continue,
+                  Some(Traced { token: Token::Comment(_), .. }) => continue,
                   Some(token) => break token,
                   // Macros may not start or and with `##`.
                   None => return Err(ExpansionError::$error),
@@ -732,60 +1254,137 @@ impl MacroSet {
 
           // Ignore whitespace between the last non-whitespace token and this `##`.
           let lhs = until_no_whitespace!(tokens.pop(), ConcatBegin);
-          let rhs = if it.peek() == Some(&Token::Punctuation(Punctuation { punctuation: "##" })) {
+          let rhs = if it.peek().map(|t| t.is_punctuation("##")).unwrap_or(false) {
             // Treat consecutive `##` as one.
-            Token::Placemarker
+            token.map(|_| Token::Placemarker)
           } else {
             // Ignore whitespace between this `##` and the next non-whitespace token.
             until_no_whitespace!(it.next(), ConcatEnd)
           };
-          tokens.push(lhs.concat(rhs)?)
+
+          let trace = match (&lhs.token, &rhs.token) {
+            (Token::Placemarker, _) => rhs.trace,
+            (_, Token::Placemarker) => lhs.trace,
+            _ => lhs.trace.with(|| ExpansionStep::Concat),
+          };
+          tokens.push(Traced { token: lhs.token.concat(rhs.token)?, trace })
         },
-        token => tokens.push(token),
+        _ => tokens.push(token),
       }
     }
 
     Ok(tokens)
   }
 
-  fn remove_placemarkers(tokens: &mut Vec<Token<'_>>) {
-    tokens.retain(|t| *t != Token::Placemarker);
+  fn remove_placemarkers(tokens: &mut Vec<Traced<'_>>) {
+    tokens.retain(|t| t.token != Token::Placemarker);
   }
 
   /// Define a variable-like macro.
   ///
-  /// Returns true if the macro was redefined.
-  pub fn define_var_macro<N, B>(&mut self, name: N, body: B) -> bool
+  /// Returns whether and how the macro was redefined, see [`Redefinition`].
+  ///
+  /// The body is tokenized immediately, returning [`ExpansionError::InvalidToken`]
+  /// if it contains a token which is not a valid pre-processing token.
+  ///
+  /// The macro is not defined if the current conditional group is inactive, or if it
+  /// is redefined incompatibly in strict mode, see [`MacroSet::set_strict_redefinitions`].
+  pub fn define_var_macro<N, B>(&mut self, name: N, body: B) -> Result<Redefinition, ExpansionError>
   where
     N: AsRef<str>,
     B: IntoIterator,
     B::Item: AsRef<str>,
   {
+    if !self.is_active() {
+      return Ok(Redefinition::Skipped)
+    }
+
     let name = name.as_ref().to_owned();
-    let body = body.into_iter().map(|t| t.as_ref().to_owned()).collect::<Vec<_>>();
+    let var_macro = VarMacroDef::new(body.into_iter().map(|t| t.as_ref().to_owned()).collect())?;
 
-    let redefined = if let Some(old_body) = self.var_macros.remove(&name) {
-      let old_tokens = old_body.iter().filter(|t| !is_whitespace(t));
-      let new_tokens = body.iter().filter(|t| !is_whitespace(t));
+    let redefinition = self.redefinition(&var_macro.definition(&name, None));
+    if self.strict_redefinitions && redefinition.is_incompatible() {
+      return Err(ExpansionError::IncompatibleRedefinition(name))
+    }
 
-      !old_tokens.zip(new_tokens).all(|(t1, t2)| t1 == t2)
-    } else {
-      self.fn_macros.remove(&name).is_some()
-    };
+    self.fn_macros.remove(&name);
+    self.undefined.remove(&name);
+    self.locations.remove(&name);
+    self.invalidate(&name);
+    self.insert_order(&name);
+    self.var_macros.insert(name, var_macro);
 
-    self.var_macros.insert(name, body);
+    Ok(redefinition)
+  }
 
-    redefined
+  /// Define a variable-like macro like [`MacroSet::define_var_macro`], recording
+  /// the location of its definition.
+  ///
+  /// Errors when expanding the macro will contain this location. Tokens passed to a
+  /// set carry no positions, so this is the location of the definition of the macro
+  /// being expanded, not of a single token.
+  pub fn define_var_macro_with_location<N, B>(
+    &mut self,
+    name: N,
+    body: B,
+    location: SourceLocation,
+  ) -> Result<Redefinition, ExpansionError>
+  where
+    N: AsRef<str>,
+    B: IntoIterator,
+    B::Item: AsRef<str>,
+  {
+    if !self.is_active() {
+      return Ok(Redefinition::Skipped)
+    }
+
+    let name = name.as_ref();
+    let redefinition = self.define_var_macro(name, body).map_err(|err| err.with_location(location.clone()))?;
+    self.locations.insert(name.to_owned(), location);
+
+    Ok(redefinition)
+  }
+
+  /// The location of the definition of the macro with the given name, if known.
+  pub fn location(&self, name: &str) -> Option<&SourceLocation> {
+    if self.is_shadowed(name) {
+      self.locations.get(name)
+    } else {
+      self.parent.as_ref()?.location(name)
+    }
+  }
+
+  /// Add the location of the definition of the given macro to an error.
+  fn locate(&self, name: &str, err: ExpansionError) -> ExpansionError {
+    match self.location(name) {
+      Some(location) => err.with_location(location.clone()),
+      None => err,
+    }
   }
 
   /// Expand a variable-like macro.
+  ///
+  /// Expanding does not modify the set, so it can be shared between threads. Expansions
+  /// cached by [`MacroSet::cache_expansions`] are reused. The operand of a `_Pragma`
+  /// operator is never macro-expanded.
   pub fn expand_var_macro<'s, 't>(&'s self, name: &str) -> Result<Vec<MacroToken<'t>>, ExpansionError>
   where
     's: 't,
   {
-    let body = self.var_macros.get(name).ok_or(ExpansionError::MacroNotFound)?;
-    let body = tokenize(&[], body);
-    let tokens = self.expand_var_macro_body(HashSet::new(), name, &body)?;
+    self.expand_var_macro_with_state(name, ExpansionState::new(name))
+  }
+
+  fn expand_var_macro_with_state<'s, 't>(
+    &'s self,
+    name: &str,
+    state: ExpansionState<'_>,
+  ) -> Result<Vec<MacroToken<'t>>, ExpansionError>
+  where
+    's: 't,
+  {
+    let (name, _) = self.var_macro(name).ok_or(ExpansionError::MacroNotFound)?;
+    let tokens = self.expansion(name, state)?;
+
     detokenize(&[], tokens)
   }
 
@@ -793,13 +1392,35 @@ impl MacroSet {
   ///
   /// Returns true if the macro was undefined.
   pub fn undefine_var_macro(&mut self, name: &str) -> bool {
-    self.var_macros.remove(name).is_some()
+    if !self.is_active() {
+      return false
+    }
+
+    if self.var_macro(name).is_some() {
+      self.var_macros.remove(name);
+      self.locations.remove(name);
+      self.order.remove(name);
+      self.hide(name);
+      self.invalidate(name);
+      return true
+    }
+
+    false
   }
 
   /// Define a function-like macro.
   ///
-  /// Returns true if the macro was redefined.
-  pub fn define_fn_macro<N, A, B>(&mut self, name: N, args: A, body: B) -> bool
+  /// Returns whether and how the macro was redefined, see [`Redefinition`].
+  ///
+  /// The parameters and body are tokenized immediately, returning an error if a
+  /// parameter is invalid or the body contains a token which is not a valid
+  /// pre-processing token ([`ExpansionError::InvalidToken`]). Named variadic
+  /// parameters are only accepted if GNU extensions are enabled at this point,
+  /// see [`MacroSet::set_gnu_extensions`].
+  ///
+  /// The macro is not defined if the current conditional group is inactive, or if it
+  /// is redefined incompatibly in strict mode, see [`MacroSet::set_strict_redefinitions`].
+  pub fn define_fn_macro<N, A, B>(&mut self, name: N, args: A, body: B) -> Result<Redefinition, ExpansionError>
   where
     N: AsRef<str>,
     A: IntoIterator,
@@ -807,30 +1428,60 @@ impl MacroSet {
     B: IntoIterator,
     B::Item: AsRef<str>,
   {
+    if !self.is_active() {
+      return Ok(Redefinition::Skipped)
+    }
+
     let name = name.as_ref().to_owned();
     let args = args.into_iter().map(|a| a.as_ref().to_owned()).collect::<Vec<_>>();
     let body = body.into_iter().map(|a| a.as_ref().to_owned()).collect::<Vec<_>>();
+    let fn_macro = FnMacroDef::new(args, body, self.gnu_extensions)?;
 
-    let redefined = if let Some((old_args, old_body)) = self.fn_macros.remove(&name) {
-      let old_args = old_args.iter().filter(|t| !is_whitespace(t));
-      let new_args = args.iter().filter(|t| !is_whitespace(t));
-      let args_equal = old_args.zip(new_args).all(|(old_arg, arg)| old_arg == arg);
+    let redefinition = self.redefinition(&fn_macro.definition(&name, None));
+    if self.strict_redefinitions && redefinition.is_incompatible() {
+      return Err(ExpansionError::IncompatibleRedefinition(name))
+    }
 
-      let old_tokens = old_body.iter().filter(|t| !is_whitespace(t));
-      let new_tokens = body.iter().filter(|t| !is_whitespace(t));
-      let tokens_equal = old_tokens.zip(new_tokens).all(|(t1, t2)| t1 == t2);
+    self.var_macros.remove(&name);
+    self.undefined.remove(&name);
+    self.locations.remove(&name);
+    self.invalidate(&name);
+    self.insert_order(&name);
+    self.fn_macros.insert(name, fn_macro);
 
-      !(args_equal && tokens_equal)
-    } else {
-      self.var_macros.remove(&name).is_some()
-    };
+    Ok(redefinition)
+  }
+
+  /// Define a function-like macro like [`MacroSet::define_fn_macro`], recording
+  /// the location of its definition.
+  ///
+  /// Errors when expanding the macro will contain this location.
+  pub fn define_fn_macro_with_location<N, A, B>(
+    &mut self,
+    name: N,
+    args: A,
+    body: B,
+    location: SourceLocation,
+  ) -> Result<Redefinition, ExpansionError>
+  where
+    N: AsRef<str>,
+    A: IntoIterator,
+    A::Item: AsRef<str>,
+    B: IntoIterator,
+    B::Item: AsRef<str>,
+  {
+    if !self.is_active() {
+      return Ok(Redefinition::Skipped)
+    }
 
-    self.fn_macros.insert(name, (args, body));
+    let name = name.as_ref();
+    let redefinition = self.define_fn_macro(name, args, body).map_err(|err| err.with_location(location.clone()))?;
+    self.locations.insert(name.to_owned(), location);
 
-    redefined
+    Ok(redefinition)
   }
 
-  /// Expand a function-like macro.
+  /// Expand a function-like macro, like [`MacroSet::expand_var_macro`].
   pub fn expand_fn_macro<'s, 't>(
     &'s self,
     name: &str,
@@ -838,23 +1489,103 @@ impl MacroSet {
   where
     's: 't,
   {
-    let (arg_names, body) = self.fn_macros.get(name).ok_or(ExpansionError::MacroNotFound)?;
+    self.expand_fn_macro_with_state(name, ExpansionState::new(name))
+  }
+
+  fn expand_fn_macro_with_state<'s, 't>(
+    &'s self,
+    name: &str,
+    state: ExpansionState<'_>,
+  ) -> Result<(Vec<MacroToken<'t>>, Vec<MacroToken<'t>>), ExpansionError>
+  where
+    's: 't,
+  {
+    let (name, fn_macro) = self.fn_macro(name).ok_or(ExpansionError::MacroNotFound)?;
 
-    let arg_names = tokenize_arg_names(arg_names)?;
-    let body = tokenize(&arg_names, body);
-    let tokens = self.expand_fn_macro_body(HashSet::new(), name, &arg_names, None, &body)?;
+    let arg_names = &fn_macro.arg_tokens;
+    let tokens = self.expansion(name, state)?;
 
-    let tokens = detokenize(&arg_names, tokens)?;
-    let arg_names = detokenize(&[], arg_names)?;
+    let tokens = detokenize(arg_names, tokens)?;
+    let arg_names = detokenize(&[], arg_names.clone())?;
 
     Ok((arg_names, tokens))
   }
 
+  /// Expand an invocation of a function-like macro with the given arguments.
+  ///
+  /// Each argument is a list of tokens. The arguments are fully macro-expanded
+  /// before being substituted, unless they are stringified (`#`) or concatenated (`##`).
+  /// Afterwards, the result is rescanned like with [`MacroSet::expand`].
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{ExpansionError, MacroSet};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_fn_macro("MAX", ["a", "b"], ["(", "a", ")", ">", "(", "b", ")", "?", "(", "a", ")", ":", "(", "b", ")"])?;
+  /// macro_set.define_var_macro("THREE", ["3"])?;
+  ///
+  /// assert_eq!(
+  ///   macro_set.expand_fn_macro_call("MAX", &[vec!["a"], vec!["THREE"]])?,
+  ///   macro_set.expand(&["(", "a", ")", ">", "(", "3", ")", "?", "(", "a", ")", ":", "(", "3", ")"])?,
+  /// );
+  ///
+  /// assert_eq!(
+  ///   macro_set.expand_fn_macro_call("MAX", &[vec!["a"]]),
+  ///   Err(ExpansionError::FnMacroArgumentError { name: "MAX".into(), required: 2, given: 1 }),
+  /// );
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn expand_fn_macro_call<'s, 'a, 't, A, T>(
+    &'s self,
+    name: &str,
+    args: &'a [A],
+  ) -> Result<Vec<MacroToken<'t>>, ExpansionError>
+  where
+    's: 't,
+    'a: 't,
+    A: AsRef<[T]>,
+    T: AsRef<str> + 't,
+  {
+    let (name, fn_macro) = self.fn_macro(name).ok_or(ExpansionError::MacroNotFound)?;
+
+    let body = traced(fn_macro.tokens.clone(), &Trace::default());
+    let args = args.iter().map(|arg| traced(tokenize(&[], arg.as_ref()), &Trace::default())).collect::<Vec<_>>();
+    let tokens = self
+      .expand_fn_macro_body(
+        HashSet::new(),
+        name,
+        &fn_macro.arg_tokens,
+        Some(&args),
+        &body,
+        &mut ExpansionState::default(),
+      )
+      .map_err(|err| self.locate(name, err))?;
+
+    detokenize(&[], untraced(tokens))
+  }
+
   /// Undefine a function-like macro with the given name.
   ///
   /// Returns true if the macro was undefined.
   pub fn undefine_fn_macro(&mut self, name: &str) -> bool {
-    self.fn_macros.remove(name).is_some()
+    if !self.is_active() {
+      return false
+    }
+
+    if self.fn_macro(name).is_some() {
+      self.fn_macros.remove(name);
+      self.locations.remove(name);
+      self.order.remove(name);
+      self.hide(name);
+      self.invalidate(name);
+      return true
+    }
+
+    false
   }
 
   /// Expand a macro expression using the macros defined in the set.
@@ -864,9 +1595,9 @@ impl MacroSet {
     'b: 't,
     T: AsRef<str> + 't,
   {
-    let body = tokenize(&[], body);
-    let tokens: Vec<Token<'t>> = self.expand_var_macro_body(HashSet::new(), "", &body)?;
-    detokenize(&[], tokens)
+    let body = traced(tokenize(&[], body), &Trace::default());
+    let tokens = self.expand_var_macro_body(HashSet::new(), "", &body, &mut ExpansionState::default())?;
+    detokenize(&[], untraced(tokens))
   }
 }
 
@@ -875,6 +1606,7 @@ mod tests {
   use crate::{
     ast::{arg, comment, id, lit_float, lit_int, lit_string, punct},
     macro_token::*,
+    CodegenError, ParserError, VarMacro,
   };
 
   use super::*;
@@ -883,14 +1615,14 @@ mod tests {
   fn macro_set() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("VAR", ["A", "+", "B"]);
-    macro_set.define_var_macro("A", ["2"]);
-    macro_set.define_var_macro("B", ["3"]);
-    macro_set.define_fn_macro("PLUS", ["A", "B"], ["A", "+", "B"]);
-    macro_set.define_fn_macro("F1", ["A", "B"], ["A", "+", "VAR", "+", "B"]);
-    macro_set.define_var_macro("PLUS_VAR", ["PLUS", "(", "7", ",", "8", ")"]);
-    macro_set.define_var_macro("PLUS_PLUS_VAR", ["PLUS", "(", "PLUS", "(", "3", ",", "1", ")", ",", "8", ")"]);
-    macro_set.define_var_macro("PLUS_VAR_VAR", ["PLUS", "(", "7", ",", "VAR", ")"]);
+    macro_set.define_var_macro("VAR", ["A", "+", "B"]).unwrap();
+    macro_set.define_var_macro("A", ["2"]).unwrap();
+    macro_set.define_var_macro("B", ["3"]).unwrap();
+    macro_set.define_fn_macro("PLUS", ["A", "B"], ["A", "+", "B"]).unwrap();
+    macro_set.define_fn_macro("F1", ["A", "B"], ["A", "+", "VAR", "+", "B"]).unwrap();
+    macro_set.define_var_macro("PLUS_VAR", ["PLUS", "(", "7", ",", "8", ")"]).unwrap();
+    macro_set.define_var_macro("PLUS_PLUS_VAR", ["PLUS", "(", "PLUS", "(", "3", ",", "1", ")", ",", "8", ")"]).unwrap();
+    macro_set.define_var_macro("PLUS_VAR_VAR", ["PLUS", "(", "7", ",", "VAR", ")"]).unwrap();
 
     assert_eq!(macro_set.expand_var_macro("VAR"), Ok(tokens![lit_int!(2), punct!("+"), lit_int!(3)]));
     assert_eq!(
@@ -914,15 +1646,20 @@ mod tests {
   #[test]
   fn non_unique_arg_name() {
     let mut macro_set = MacroSet::new();
-    macro_set.define_fn_macro("X", ["x", "x"], ["x", "*", "x"]);
-    assert_eq!(macro_set.expand_fn_macro("X"), Err(ExpansionError::NonUniqueArgument("x".into())));
+    assert_eq!(
+      macro_set.define_fn_macro("X", ["x", "x"], ["x", "*", "x"]),
+      Err(ExpansionError::NonUniqueArgument("x".into()))
+    );
+    assert_eq!(macro_set.expand_fn_macro("X"), Err(ExpansionError::MacroNotFound));
   }
 
   #[test]
   fn parse_args_whitespace() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("TIMES", ["x", "/* X coordinate */", "y", "/* Y coordinate */"], ["x", "*", "y"]);
+    macro_set
+      .define_fn_macro("TIMES", ["x", "/* X coordinate */", "y", "/* Y coordinate */"], ["x", "*", "y"])
+      .unwrap();
     assert_eq!(
       macro_set.expand_fn_macro("TIMES"),
       Ok((
@@ -936,8 +1673,8 @@ mod tests {
   fn parse_concat_var_args() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("CONCAT_VAR_ARGS", ["..."], ["__VA_", "##", "ARGS__"]);
-    macro_set.define_var_macro("CALL_CONCAT_VAR_ARGS", ["CONCAT_VAR_ARGS", "(", ")"]);
+    macro_set.define_fn_macro("CONCAT_VAR_ARGS", ["..."], ["__VA_", "##", "ARGS__"]).unwrap();
+    macro_set.define_var_macro("CALL_CONCAT_VAR_ARGS", ["CONCAT_VAR_ARGS", "(", ")"]).unwrap();
     assert_eq!(macro_set.expand_fn_macro("CONCAT_VAR_ARGS"), Ok((tokens![punct!("...")], tokens![id!(__VA_ARGS__)])));
     assert_eq!(macro_set.expand_var_macro("CALL_CONCAT_VAR_ARGS"), Ok(tokens![id!(__VA_ARGS__)]));
   }
@@ -946,12 +1683,12 @@ mod tests {
   fn concat_begin_end() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT_BEGIN", ["##", "b"]);
-    macro_set.define_var_macro("CONCAT_END", ["a", "##"]);
-    macro_set.define_var_macro("CONCAT_BEGIN_END", ["##"]);
-    macro_set.define_var_macro("CONCAT_COMMENT_BEGIN", ["/* a */", "##", "b"]);
-    macro_set.define_var_macro("CONCAT_COMMENT_END", ["a", "##", "/* b */"]);
-    macro_set.define_var_macro("CONCAT_COMMENT_BEGIN_END", ["/* a */", "##", "/* b */"]);
+    macro_set.define_var_macro("CONCAT_BEGIN", ["##", "b"]).unwrap();
+    macro_set.define_var_macro("CONCAT_END", ["a", "##"]).unwrap();
+    macro_set.define_var_macro("CONCAT_BEGIN_END", ["##"]).unwrap();
+    macro_set.define_var_macro("CONCAT_COMMENT_BEGIN", ["/* a */", "##", "b"]).unwrap();
+    macro_set.define_var_macro("CONCAT_COMMENT_END", ["a", "##", "/* b */"]).unwrap();
+    macro_set.define_var_macro("CONCAT_COMMENT_BEGIN_END", ["/* a */", "##", "/* b */"]).unwrap();
 
     assert_eq!(macro_set.expand_var_macro("CONCAT_BEGIN"), Err(ExpansionError::ConcatBegin));
     assert_eq!(macro_set.expand_var_macro("CONCAT_END"), Err(ExpansionError::ConcatEnd));
@@ -966,9 +1703,9 @@ mod tests {
   fn parse_disjunct() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("THREE_PLUS", ["3", "+"]);
-    macro_set.define_var_macro("FOUR", ["4"]);
-    macro_set.define_var_macro("THREE_PLUS_FOUR", ["THREE_PLUS", "FOUR"]);
+    macro_set.define_var_macro("THREE_PLUS", ["3", "+"]).unwrap();
+    macro_set.define_var_macro("FOUR", ["4"]).unwrap();
+    macro_set.define_var_macro("THREE_PLUS_FOUR", ["THREE_PLUS", "FOUR"]).unwrap();
 
     assert_eq!(macro_set.expand_var_macro("THREE_PLUS_FOUR"), Ok(tokens![lit_int!(3), punct!("+"), lit_int!(4)]));
   }
@@ -977,8 +1714,8 @@ mod tests {
   fn parse_fn_no_args() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("FUNC", [] as [String; 0], ["123"]);
-    macro_set.define_var_macro("ONE_TWO_THREE", ["FUNC", "(", ")"]);
+    macro_set.define_fn_macro("FUNC", [] as [String; 0], ["123"]).unwrap();
+    macro_set.define_var_macro("ONE_TWO_THREE", ["FUNC", "(", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("ONE_TWO_THREE"), Ok(tokens![lit_int!(123)]));
   }
 
@@ -986,9 +1723,9 @@ mod tests {
   fn parse_disjunct_fn() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("FUNC1", ["a", "b"], ["a", "+", "b"]);
-    macro_set.define_var_macro("FUNC1_PARTIAL", ["FUNC1", "(", "1", ","]);
-    macro_set.define_fn_macro("FUNC2", [] as [String; 0], ["FUNC1_PARTIAL", "2", ")"]);
+    macro_set.define_fn_macro("FUNC1", ["a", "b"], ["a", "+", "b"]).unwrap();
+    macro_set.define_var_macro("FUNC1_PARTIAL", ["FUNC1", "(", "1", ","]).unwrap();
+    macro_set.define_fn_macro("FUNC2", [] as [String; 0], ["FUNC1_PARTIAL", "2", ")"]).unwrap();
 
     assert_eq!(macro_set.expand_fn_macro("FUNC2"), Ok((tokens![], tokens![lit_int!(1), punct!("+"), lit_int!(2)])));
   }
@@ -997,9 +1734,9 @@ mod tests {
   fn parse_disjunct_fn_call() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("BAR", ["a", "b"], ["a", "+", "b"]);
-    macro_set.define_fn_macro("FOO", [] as [String; 0], ["BAR"]);
-    macro_set.define_var_macro("APLUSB", ["FOO", "(", ")", "(", "3", ",", "1", ")"]);
+    macro_set.define_fn_macro("BAR", ["a", "b"], ["a", "+", "b"]).unwrap();
+    macro_set.define_fn_macro("FOO", [] as [String; 0], ["BAR"]).unwrap();
+    macro_set.define_var_macro("APLUSB", ["FOO", "(", ")", "(", "3", ",", "1", ")"]).unwrap();
 
     assert_eq!(macro_set.expand_var_macro("APLUSB"), Ok(tokens![lit_int!(3), punct!("+"), lit_int!(1)]));
   }
@@ -1008,9 +1745,9 @@ mod tests {
   fn parse_recursive() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("FUNC1", ["arg"], ["FUNC2", "(", "arg", ")"]);
-    macro_set.define_fn_macro("FUNC2", ["arg"], ["FUNC1", "(", "arg", ")"]);
-    macro_set.define_var_macro("VAR1", ["1", "+", "VAR1"]);
+    macro_set.define_fn_macro("FUNC1", ["arg"], ["FUNC2", "(", "arg", ")"]).unwrap();
+    macro_set.define_fn_macro("FUNC2", ["arg"], ["FUNC1", "(", "arg", ")"]).unwrap();
+    macro_set.define_var_macro("VAR1", ["1", "+", "VAR1"]).unwrap();
     assert_eq!(
       macro_set.expand_fn_macro("FUNC1"),
       Ok((tokens![id!(arg)], tokens![id!(FUNC1), punct!("("), arg!(0), punct!(")")]))
@@ -1026,8 +1763,8 @@ mod tests {
   fn parse_stringify() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("s", ["377"]);
-    macro_set.define_fn_macro("STRINGIFY", ["s"], ["#", "s"]);
+    macro_set.define_var_macro("s", ["377"]).unwrap();
+    macro_set.define_fn_macro("STRINGIFY", ["s"], ["#", "s"]).unwrap();
     assert_eq!(macro_set.expand_fn_macro("STRINGIFY"), Ok((tokens![id!(s)], tokens![punct!("#"), arg!(0)])));
   }
 
@@ -1035,9 +1772,9 @@ mod tests {
   fn parse_stringify_nested() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("STRINGIFY", ["s"], ["#", "s"]);
-    macro_set.define_var_macro("s", ["STRINGIFY", "(", "asdf", ")"]);
-    macro_set.define_var_macro("e", ["STRINGIFY", "(", "a", "+", "b", ")"]);
+    macro_set.define_fn_macro("STRINGIFY", ["s"], ["#", "s"]).unwrap();
+    macro_set.define_var_macro("s", ["STRINGIFY", "(", "asdf", ")"]).unwrap();
+    macro_set.define_var_macro("e", ["STRINGIFY", "(", "a", "+", "b", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("s"), Ok(tokens![lit_string!("asdf")]));
     assert_eq!(macro_set.expand_var_macro("e"), Ok(tokens![lit_string!("a + b")]));
   }
@@ -1046,10 +1783,10 @@ mod tests {
   fn parse_stringify_double_nested() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("STRINGIFY1", ["s"], ["#", "s"]);
-    macro_set.define_fn_macro("STRINGIFY2", ["s"], ["STRINGIFY1", "(", "s", ")"]);
-    macro_set.define_var_macro("LINE_STRING1", ["STRINGIFY1", "(", "__LINE__", ")"]);
-    macro_set.define_var_macro("LINE_STRING2", ["STRINGIFY2", "(", "__LINE__", ")"]);
+    macro_set.define_fn_macro("STRINGIFY1", ["s"], ["#", "s"]).unwrap();
+    macro_set.define_fn_macro("STRINGIFY2", ["s"], ["STRINGIFY1", "(", "s", ")"]).unwrap();
+    macro_set.define_var_macro("LINE_STRING1", ["STRINGIFY1", "(", "__LINE__", ")"]).unwrap();
+    macro_set.define_var_macro("LINE_STRING2", ["STRINGIFY2", "(", "__LINE__", ")"]).unwrap();
     assert_eq!(macro_set.expand_fn_macro("STRINGIFY1"), Ok((tokens![id!(s)], tokens![punct!("#"), arg!(0)])));
     assert_eq!(macro_set.expand_fn_macro("STRINGIFY2"), Ok((tokens![id!(s)], tokens![punct!("#"), arg!(0)])));
     assert_eq!(macro_set.expand_var_macro("LINE_STRING1"), Ok(tokens![lit_string!("__LINE__")]));
@@ -1060,10 +1797,10 @@ mod tests {
   fn parse_stringify_var_args() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("STRINGIFY", ["..."], ["#", "__VA_ARGS__"]);
-    macro_set.define_var_macro("ZERO", ["STRINGIFY", "(", ")"]);
-    macro_set.define_var_macro("ONE", ["STRINGIFY", "(", "asdf", ")"]);
-    macro_set.define_var_macro("TWO", ["STRINGIFY", "(", "a", ",", "b", ")"]);
+    macro_set.define_fn_macro("STRINGIFY", ["..."], ["#", "__VA_ARGS__"]).unwrap();
+    macro_set.define_var_macro("ZERO", ["STRINGIFY", "(", ")"]).unwrap();
+    macro_set.define_var_macro("ONE", ["STRINGIFY", "(", "asdf", ")"]).unwrap();
+    macro_set.define_var_macro("TWO", ["STRINGIFY", "(", "a", ",", "b", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("ZERO"), Ok(tokens![lit_string!("")]));
     assert_eq!(macro_set.expand_var_macro("ONE"), Ok(tokens![lit_string!("asdf")]));
     assert_eq!(macro_set.expand_var_macro("TWO"), Ok(tokens![lit_string!("a, b")]));
@@ -1073,8 +1810,8 @@ mod tests {
   fn parse_wrong_arity() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("func", ["x"], ["func", "(", "x", ",", "3", ")"]);
-    macro_set.define_fn_macro("wrapper_func", ["x"], ["func", "(", "x", ",", "3", ")"]);
+    macro_set.define_fn_macro("func", ["x"], ["func", "(", "x", ",", "3", ")"]).unwrap();
+    macro_set.define_fn_macro("wrapper_func", ["x"], ["func", "(", "x", ",", "3", ")"]).unwrap();
 
     assert_eq!(
       macro_set.expand_fn_macro("wrapper_func"),
@@ -1086,9 +1823,9 @@ mod tests {
   fn parse_concat() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("A", ["1"]);
-    macro_set.define_var_macro("B", ["2"]);
-    macro_set.define_var_macro("CONCAT", ["A", "##", "B"]);
+    macro_set.define_var_macro("A", ["1"]).unwrap();
+    macro_set.define_var_macro("B", ["2"]).unwrap();
+    macro_set.define_var_macro("CONCAT", ["A", "##", "B"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT"), Ok(tokens![id!(AB)]));
   }
 
@@ -1096,9 +1833,9 @@ mod tests {
   fn parse_concat_comment() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT_COMMENT1", ["A", "/* 1 */", "##", "B"]);
-    macro_set.define_var_macro("CONCAT_COMMENT2", ["A", "##", "/* 2 */", "B"]);
-    macro_set.define_var_macro("CONCAT_COMMENT3", ["A", "/* 1 */", "##", "/* 2 */", "B"]);
+    macro_set.define_var_macro("CONCAT_COMMENT1", ["A", "/* 1 */", "##", "B"]).unwrap();
+    macro_set.define_var_macro("CONCAT_COMMENT2", ["A", "##", "/* 2 */", "B"]).unwrap();
+    macro_set.define_var_macro("CONCAT_COMMENT3", ["A", "/* 1 */", "##", "/* 2 */", "B"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT_COMMENT1"), Ok(tokens![id!(AB)]));
     assert_eq!(macro_set.expand_var_macro("CONCAT_COMMENT2"), Ok(tokens![id!(AB)]));
     assert_eq!(macro_set.expand_var_macro("CONCAT_COMMENT3"), Ok(tokens![id!(AB)]));
@@ -1108,11 +1845,11 @@ mod tests {
   fn parse_concat_string() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("A", ["1"]);
-    macro_set.define_var_macro("B", ["2"]);
-    macro_set.define_var_macro("C", ["\", world!\""]);
-    macro_set.define_var_macro("AB", ["\"Hello\""]);
-    macro_set.define_fn_macro("CONCAT_STRING", ["A", "B"], ["A", "##", "B", "C"]);
+    macro_set.define_var_macro("A", ["1"]).unwrap();
+    macro_set.define_var_macro("B", ["2"]).unwrap();
+    macro_set.define_var_macro("C", ["\", world!\""]).unwrap();
+    macro_set.define_var_macro("AB", ["\"Hello\""]).unwrap();
+    macro_set.define_fn_macro("CONCAT_STRING", ["A", "B"], ["A", "##", "B", "C"]).unwrap();
     assert_eq!(
       macro_set.expand_fn_macro("CONCAT_STRING"),
       Ok((tokens![id!(A), id!(B)], tokens![arg!(0), punct!("##"), arg!(1), lit_string!(", world!")]))
@@ -1123,14 +1860,14 @@ mod tests {
   fn parse_concat_string_prefix() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("A", ["u8", "##", "\"abc\""]);
-    macro_set.define_var_macro("B", ["u8", "\"abc\""]);
-    macro_set.define_fn_macro("PREFIX", ["prefix"], ["prefix", "##", "\"abc\""]);
-    macro_set.define_var_macro("C", ["PREFIX", "(", "u8", ")"]);
-    macro_set.define_fn_macro("PREFIX_STRINGIFY", ["prefix"], ["prefix", "##", "#", "prefix"]);
-    macro_set.define_var_macro("D", ["PREFIX_STRINGIFY", "(", "u8", ")"]);
-    macro_set.define_fn_macro("PREFIX_HASH", ["prefix"], ["prefix", "##", "#"]);
-    macro_set.define_var_macro("E", ["PREFIX_HASH", "(", "u8", ")"]);
+    macro_set.define_var_macro("A", ["u8", "##", "\"abc\""]).unwrap();
+    macro_set.define_var_macro("B", ["u8", "\"abc\""]).unwrap();
+    macro_set.define_fn_macro("PREFIX", ["prefix"], ["prefix", "##", "\"abc\""]).unwrap();
+    macro_set.define_var_macro("C", ["PREFIX", "(", "u8", ")"]).unwrap();
+    macro_set.define_fn_macro("PREFIX_STRINGIFY", ["prefix"], ["prefix", "##", "#", "prefix"]).unwrap();
+    macro_set.define_var_macro("D", ["PREFIX_STRINGIFY", "(", "u8", ")"]).unwrap();
+    macro_set.define_fn_macro("PREFIX_HASH", ["prefix"], ["prefix", "##", "#"]).unwrap();
+    macro_set.define_var_macro("E", ["PREFIX_HASH", "(", "u8", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("A"), Ok(tokens![lit_string!(u8 "abc")]));
     assert_eq!(macro_set.expand_var_macro("B"), Ok(tokens![id!(u8), lit_string!("abc")]));
     assert_eq!(macro_set.expand_var_macro("C"), Ok(tokens![lit_string!(u8 "abc")]));
@@ -1142,7 +1879,7 @@ mod tests {
   fn parse_concat_ident_int() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT", ["FUNC", "##", "123"]);
+    macro_set.define_var_macro("CONCAT", ["FUNC", "##", "123"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT"), Ok(tokens![id!(FUNC123)]));
   }
 
@@ -1150,7 +1887,7 @@ mod tests {
   fn parse_concat_ident_float() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT", ["FUNC", "##", "123e4"]);
+    macro_set.define_var_macro("CONCAT", ["FUNC", "##", "123e4"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT"), Ok(tokens![id!(FUNC123e4)]));
   }
 
@@ -1158,7 +1895,7 @@ mod tests {
   fn parse_concat_dot_float() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT", [".", "##", "123e4"]);
+    macro_set.define_var_macro("CONCAT", [".", "##", "123e4"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT"), Ok(tokens![lit_float!(0.123e4)]));
   }
 
@@ -1166,7 +1903,7 @@ mod tests {
   fn parse_concat_dot_int() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("CONCAT", [".", "##", "01234"]);
+    macro_set.define_var_macro("CONCAT", [".", "##", "01234"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("CONCAT"), Ok(tokens![lit_float!(0.01234)]));
   }
 
@@ -1174,8 +1911,8 @@ mod tests {
   fn parse_concat_empty() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("CONCAT", ["a", "b"], ["a", "##", "b"]);
-    macro_set.define_var_macro("EMPTY", ["CONCAT", "(", ",", ")"]);
+    macro_set.define_fn_macro("CONCAT", ["a", "b"], ["a", "##", "b"]).unwrap();
+    macro_set.define_var_macro("EMPTY", ["CONCAT", "(", ",", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("EMPTY"), Ok(tokens![]));
   }
 
@@ -1183,11 +1920,11 @@ mod tests {
   fn parse_c_std_6_10_3_3_example() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("hash_hash", ["#", "##", "#"]);
-    macro_set.define_fn_macro("mkstr", ["a"], ["#", "a"]);
-    macro_set.define_fn_macro("in_between", ["a"], ["mkstr", "(", "a", ")"]);
-    macro_set.define_fn_macro("join", ["c", "d"], ["in_between", "(", "c", "hash_hash", "d", ")"]);
-    macro_set.define_var_macro("join_x_y", ["join", "(", "x", ",", "y", ")"]);
+    macro_set.define_var_macro("hash_hash", ["#", "##", "#"]).unwrap();
+    macro_set.define_fn_macro("mkstr", ["a"], ["#", "a"]).unwrap();
+    macro_set.define_fn_macro("in_between", ["a"], ["mkstr", "(", "a", ")"]).unwrap();
+    macro_set.define_fn_macro("join", ["c", "d"], ["in_between", "(", "c", "hash_hash", "d", ")"]).unwrap();
+    macro_set.define_var_macro("join_x_y", ["join", "(", "x", ",", "y", ")"]).unwrap();
     assert_eq!(macro_set.expand_var_macro("join_x_y"), Ok(tokens![lit_string!("x ## y")]));
   }
 
@@ -1195,19 +1932,19 @@ mod tests {
   fn parse_c_std_6_10_3_5_example_3() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("x", ["3"]);
-    macro_set.define_fn_macro("f", ["a"], ["f", "(", "x", "*", "(", "a", ")", ")"]);
-    macro_set.define_var_macro("x", ["2"]);
-    macro_set.define_var_macro("g", ["f"]);
-    macro_set.define_var_macro("z", ["z", "[", "0", "]"]);
-    macro_set.define_var_macro("h", ["g", "(", "~"]);
-    macro_set.define_fn_macro("m", ["a"], ["a", "(", "w", ")"]);
-    macro_set.define_var_macro("w", ["0", ",", "1"]);
-    macro_set.define_fn_macro("t", ["a"], ["a"]);
-    macro_set.define_fn_macro("p", [] as [String; 0], ["int"]);
-    macro_set.define_fn_macro("q", ["x"], ["x"]);
-    macro_set.define_fn_macro("r", ["x", "y"], ["x", "##", "y"]);
-    macro_set.define_fn_macro("str", ["x"], ["#", "x"]);
+    macro_set.define_var_macro("x", ["3"]).unwrap();
+    macro_set.define_fn_macro("f", ["a"], ["f", "(", "x", "*", "(", "a", ")", ")"]).unwrap();
+    macro_set.define_var_macro("x", ["2"]).unwrap();
+    macro_set.define_var_macro("g", ["f"]).unwrap();
+    macro_set.define_var_macro("z", ["z", "[", "0", "]"]).unwrap();
+    macro_set.define_var_macro("h", ["g", "(", "~"]).unwrap();
+    macro_set.define_fn_macro("m", ["a"], ["a", "(", "w", ")"]).unwrap();
+    macro_set.define_var_macro("w", ["0", ",", "1"]).unwrap();
+    macro_set.define_fn_macro("t", ["a"], ["a"]).unwrap();
+    macro_set.define_fn_macro("p", [] as [String; 0], ["int"]).unwrap();
+    macro_set.define_fn_macro("q", ["x"], ["x"]).unwrap();
+    macro_set.define_fn_macro("r", ["x", "y"], ["x", "##", "y"]).unwrap();
+    macro_set.define_fn_macro("str", ["x"], ["#", "x"]).unwrap();
 
     assert_eq!(
       macro_set.expand(&[
@@ -1368,37 +2105,39 @@ mod tests {
   fn parse_c_std_6_10_3_5_example_4() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("str", ["s"], ["#", "s"]);
-    macro_set.define_fn_macro("xstr", ["s"], ["str", "(", "s", ")"]);
-    macro_set.define_fn_macro(
-      "debug",
-      ["s", "t"],
-      [
-        "printf",
-        "(",
-        "\"x\"",
-        "#",
-        "s",
-        "\"= %d, x\"",
-        "#",
-        "t",
-        "\"= %s\"",
-        ",",
-        "x",
-        "##",
-        "s",
-        ",",
-        "x",
-        "##",
-        "t",
-        ")",
-      ],
-    );
-    macro_set.define_fn_macro("INCFILE", ["n"], ["vers", "##", "n"]);
-    macro_set.define_fn_macro("glue", ["a", "b"], ["a", "##", "b"]);
-    macro_set.define_fn_macro("xglue", ["a", "b"], ["glue", "(", "a", ",", "b", ")"]);
-    macro_set.define_var_macro("HIGHLOW", ["\"hello\""]);
-    macro_set.define_var_macro("LOW", ["LOW", "\", world\""]);
+    macro_set.define_fn_macro("str", ["s"], ["#", "s"]).unwrap();
+    macro_set.define_fn_macro("xstr", ["s"], ["str", "(", "s", ")"]).unwrap();
+    macro_set
+      .define_fn_macro(
+        "debug",
+        ["s", "t"],
+        [
+          "printf",
+          "(",
+          "\"x\"",
+          "#",
+          "s",
+          "\"= %d, x\"",
+          "#",
+          "t",
+          "\"= %s\"",
+          ",",
+          "x",
+          "##",
+          "s",
+          ",",
+          "x",
+          "##",
+          "t",
+          ")",
+        ],
+      )
+      .unwrap();
+    macro_set.define_fn_macro("INCFILE", ["n"], ["vers", "##", "n"]).unwrap();
+    macro_set.define_fn_macro("glue", ["a", "b"], ["a", "##", "b"]).unwrap();
+    macro_set.define_fn_macro("xglue", ["a", "b"], ["glue", "(", "a", ",", "b", ")"]).unwrap();
+    macro_set.define_var_macro("HIGHLOW", ["\"hello\""]).unwrap();
+    macro_set.define_var_macro("LOW", ["LOW", "\", world\""]).unwrap();
 
     assert_eq!(
       macro_set.expand(&["debug", "(", "1", ",", "2", ")", ";"]),
@@ -1467,7 +2206,7 @@ mod tests {
   fn parse_c_std_6_10_3_5_example_5() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("t", ["x", "y", "z"], ["x", "##", "y", "##", "z"]);
+    macro_set.define_fn_macro("t", ["x", "y", "z"], ["x", "##", "y", "##", "z"]).unwrap();
     assert_eq!(
       macro_set.expand(&[
         "int", "j", "[", "]", "=", "{", //
@@ -1512,36 +2251,55 @@ mod tests {
   fn parse_c_std_6_10_3_5_example_6() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("OBJ_LIKE", ["/* whie space */", "(", "1", "-", "1", ")", "/* other */"]);
-    assert!(!macro_set.define_var_macro("OBJ_LIKE", ["(", "1", "-", "1", ")"]));
+    macro_set.define_var_macro("OBJ_LIKE", ["/* whie space */", "(", "1", "-", "1", ")", "/* other */"]).unwrap();
+    assert!(matches!(
+      macro_set.define_var_macro("OBJ_LIKE", ["(", "1", "-", "1", ")"]),
+      Ok(Redefinition::Identical(_))
+    ));
 
-    assert!(!macro_set.define_fn_macro("FUNC_LIKE", ["a"], ["(", "a", ")"]));
-    assert!(!macro_set.define_fn_macro(
-      "FUNC_LIKE",
-      ["a"],
-      ["(", "/* note the white space */", "a", "/* other stuff on this line \n */", ")"]
+    assert_eq!(macro_set.define_fn_macro("FUNC_LIKE", ["a"], ["(", " ", "a", " ", ")"]), Ok(Redefinition::New));
+    assert!(matches!(
+      macro_set.define_fn_macro(
+        "FUNC_LIKE",
+        ["a"],
+        ["(", "/* note the white space */", "a", "/* other stuff on this line \n */", ")"]
+      ),
+      Ok(Redefinition::Identical(_))
     ));
 
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_var_macro("OBJ_LIKE", ["(", "0", ")"]);
-    assert!(macro_set.define_var_macro("OBJ_LIKE", ["(", "1", "-", "1", ")"]));
+    macro_set.define_var_macro("OBJ_LIKE", ["(", "1", "-", "1", ")"]).unwrap();
+    assert!(matches!(macro_set.define_var_macro("OBJ_LIKE", ["(", "0", ")"]), Ok(Redefinition::Incompatible(_))));
+
+    macro_set.define_var_macro("OBJ_LIKE", ["(", "1", "-", "1", ")"]).unwrap();
+    assert!(matches!(
+      macro_set.define_var_macro("OBJ_LIKE", ["(", "1", " ", "-", " ", "1", ")"]),
+      Ok(Redefinition::Incompatible(_))
+    ));
+
+    let func_like = ["(", " ", "a", " ", ")"];
+    macro_set.define_fn_macro("FUNC_LIKE", ["a"], func_like).unwrap();
+    assert!(matches!(macro_set.define_fn_macro("FUNC_LIKE", ["b"], func_like), Ok(Redefinition::Incompatible(_))));
 
-    macro_set.define_fn_macro("FUNC_LIKE", ["b"], ["(", "a", ")"]);
-    assert!(macro_set.define_fn_macro("FUNC_LIKE", ["b"], ["(", "b", ")"]));
+    macro_set.define_fn_macro("FUNC_LIKE", ["a"], func_like).unwrap();
+    let redefinition = macro_set.define_fn_macro("FUNC_LIKE", ["b"], ["(", " ", "b", " ", ")"]);
+    assert!(matches!(redefinition, Ok(Redefinition::Incompatible(_))));
   }
 
   #[test]
   fn parse_c_std_6_10_3_5_example_7() {
     let mut macro_set = MacroSet::new();
 
-    macro_set.define_fn_macro("debug", ["..."], ["fprintf", "(", "stderr", ",", "__VA_ARGS__", ")"]);
-    macro_set.define_fn_macro("showlist", ["..."], ["puts", "(", "#", "__VA_ARGS__", ")"]);
-    macro_set.define_fn_macro(
-      "report",
-      ["test", "..."],
-      ["(", "(", "test", ")", "?", "puts", "(", "#", "test", ")", ":", "printf", "(", "__VA_ARGS__", ")", ")"],
-    );
+    macro_set.define_fn_macro("debug", ["..."], ["fprintf", "(", "stderr", ",", "__VA_ARGS__", ")"]).unwrap();
+    macro_set.define_fn_macro("showlist", ["..."], ["puts", "(", "#", "__VA_ARGS__", ")"]).unwrap();
+    macro_set
+      .define_fn_macro(
+        "report",
+        ["test", "..."],
+        ["(", "(", "test", ")", "?", "puts", "(", "#", "test", ")", ":", "printf", "(", "__VA_ARGS__", ")", ")"],
+      )
+      .unwrap();
 
     assert_eq!(
       macro_set.expand(&["debug", "(", "\"Flag\"", ")", ";"]),
@@ -1593,4 +2351,507 @@ mod tests {
       ])
     );
   }
+
+  #[test]
+  fn parse_c_std_6_10_5_1_example() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define F(...) f(0 __VA_OPT__(,) __VA_ARGS__)
+        #define G(X, ...) f(0, X __VA_OPT__(,) __VA_ARGS__)
+        #define SDEF(sname, ...) S sname __VA_OPT__(= { __VA_ARGS__ })
+        #define EMP
+        #define H2(X, Y, ...) __VA_OPT__(X ## Y,) __VA_ARGS__
+        #define H3(X, ...) #__VA_OPT__(X##X X##X)
+        #define H4(X, ...) __VA_OPT__(a X ## X) ## b
+        #define H5A(...) __VA_OPT__()/**/__VA_OPT__()
+        #define H5B(X) a ## X ## b
+        #define H5C(X) H5B(X)
+        "#,
+      )
+      .unwrap();
+
+    let assert_expands_to = |source, expected| {
+      let (source, expected) = (crate::tokenize(source).unwrap(), crate::tokenize(expected).unwrap());
+      assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+    };
+
+    assert_expands_to("F(a, b, c)", "f(0, a, b, c)");
+    assert_expands_to("F()", "f(0)");
+    assert_expands_to("F(EMP)", "f(0)");
+    assert_expands_to("G(a, b, c)", "f(0, a, b, c)");
+    assert_expands_to("G(a, )", "f(0, a)");
+    assert_expands_to("G(a)", "f(0, a)");
+    assert_expands_to("SDEF(foo);", "S foo;");
+    assert_expands_to("SDEF(bar, 1, 2);", "S bar = { 1, 2 };");
+    assert_expands_to("H2(a, b, c, d)", "ab, c, d");
+    assert_expands_to("H3(, 0)", r#""""#);
+    assert_expands_to("H4(, 1)", "a b");
+    assert_expands_to("H5C(H5A())", "ab");
+  }
+
+  #[test]
+  fn parse_c_std_6_10_3_4_example() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_from_source("#define f(a) a*g\n#define g(a) f(a)").unwrap();
+
+    // Either `2*9*g` or `2*f(9)` is allowed, the argument of `g` extends past the end of `f`.
+    let (source, expected) = (crate::tokenize("f(2)(9)").unwrap(), crate::tokenize("2*9*g").unwrap());
+    assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+  }
+
+  #[test]
+  fn rescan_painted_tokens() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define ID(x) x
+        #define REC ID(REC)
+        #define FOO BAR
+        #define BAR FOO
+        #define SELF(x) SELF x
+        #define OBJ(x) x OBJ
+        #define OPEN ID(OPEN
+        #define F(x) g
+        #define g(x) F
+        #define A A B C
+        #define B B C A
+        #define C C A B
+        "#,
+      )
+      .unwrap();
+
+    let assert_expands_to = |source, expected| {
+      let (source, expected) = (crate::tokenize(source).unwrap(), crate::tokenize(expected).unwrap());
+      assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+    };
+
+    // A name which is not replaced is never replaced again, even in the arguments of another macro.
+    assert_expands_to("REC", "REC");
+    assert_expands_to("ID(REC) ID(ID(REC))", "REC REC");
+    assert_expands_to("FOO BAR ID(FOO) ID(BAR)", "FOO BAR FOO BAR");
+    assert_expands_to("SELF(SELF(1))", "SELF SELF 1");
+    assert_expands_to("A", "A B C A B A C A B C A");
+
+    // The name of a function-like macro is replaced if its arguments follow its replacement list.
+    assert_expands_to("OBJ(1)(2) ID(OBJ(1))(2)", "1 OBJ(2) 1 OBJ(2)");
+    assert_expands_to("F(1)(2)(3)", "g");
+    assert_expands_to("ID(ID)(1)", "ID(1)");
+
+    // Tokens of a replacement list are not replaced even if the arguments extend past it.
+    assert_expands_to("OPEN)", "OPEN");
+  }
+
+  #[test]
+  fn parse_metaprogramming_defer() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define EMPTY()
+        #define DEFER(id) id EMPTY()
+        #define OBSTRUCT(...) __VA_ARGS__ DEFER(EMPTY)()
+        #define EXPAND(...) __VA_ARGS__
+        #define EVAL(...) EVAL1(EVAL1(EVAL1(__VA_ARGS__)))
+        #define EVAL1(...) EVAL2(EVAL2(EVAL2(__VA_ARGS__)))
+        #define EVAL2(...) __VA_ARGS__
+        #define A() 123
+
+        #define CAT(a, ...) PRIMITIVE_CAT(a, __VA_ARGS__)
+        #define PRIMITIVE_CAT(a, ...) a ## __VA_ARGS__
+        #define IIF(c) PRIMITIVE_CAT(IIF_, c)
+        #define IIF_0(t, ...) __VA_ARGS__
+        #define IIF_1(t, ...) t
+        #define COMPL(b) PRIMITIVE_CAT(COMPL_, b)
+        #define COMPL_0 1
+        #define COMPL_1 0
+
+        #define CHECK_N(x, n, ...) n
+        #define CHECK(...) CHECK_N(__VA_ARGS__, 0,)
+        #define PROBE(x) x, 1,
+        #define IS_PAREN(x) CHECK(IS_PAREN_PROBE x)
+        #define IS_PAREN_PROBE(...) PROBE(~)
+        #define NOT(x) CHECK(PRIMITIVE_CAT(NOT_, x))
+        #define NOT_0 PROBE(~)
+        #define BOOL(x) COMPL(NOT(x))
+        #define IF(c) IIF(BOOL(c))
+        #define EAT(...)
+        #define WHEN(c) IF(c)(EXPAND, EAT)
+
+        #define DEC(x) PRIMITIVE_CAT(DEC_, x)
+        #define DEC_1 0
+        #define DEC_2 1
+        #define DEC_3 2
+        #define REPEAT(count, macro, ...) \
+          WHEN(count) \
+          ( \
+            OBSTRUCT(REPEAT_INDIRECT) () (DEC(count), macro, __VA_ARGS__) \
+            OBSTRUCT(macro) (DEC(count), __VA_ARGS__) \
+          )
+        #define REPEAT_INDIRECT() REPEAT
+        #define M(i, x) x ## i
+        "#,
+      )
+      .unwrap();
+
+    let assert_expands_to = |source, expected| {
+      let (source, expected) = (crate::tokenize(source).unwrap(), crate::tokenize(expected).unwrap());
+      assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+    };
+
+    // Deferred expressions need another scan to be expanded.
+    assert_expands_to("A DEFER(A)()", "A A ()");
+    assert_expands_to("EXPAND(A DEFER(A)())", "A 123");
+    assert_expands_to("OBSTRUCT(A)()", "A EMPTY() ()");
+    assert_expands_to("EXPAND(OBSTRUCT(A)())", "A ()");
+    assert_expands_to("EXPAND(EXPAND(OBSTRUCT(A)()))", "123");
+
+    assert_expands_to("IIF(1)(a, b) IIF(0)(a, b) COMPL(0)", "a b 1");
+    assert_expands_to("IS_PAREN(()) IS_PAREN(xxx)", "1 0");
+    assert_expands_to("NOT(0) NOT(1) BOOL(0) BOOL(7)", "1 0 0 1");
+    assert_expands_to("IF(3)(a, b) IF(0)(a, b) WHEN(0)(a) WHEN(1)(a)", "a b a");
+
+    // Recursion through an indirect macro which is only expanded by the next scan.
+    assert_expands_to("REPEAT(3, M, x)", "REPEAT_INDIRECT () (2, M, x) M (2, x)");
+    assert_expands_to("EVAL(REPEAT(3, M, x))", "x0 x1 x2");
+  }
+
+  #[test]
+  fn parse_metaprogramming_argument_counting() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define PP_NARG(...) PP_NARG_(__VA_ARGS__, PP_RSEQ_N())
+        #define PP_NARG_(...) PP_ARG_N(__VA_ARGS__)
+        #define PP_ARG_N(_1, _2, _3, _4, _5, N, ...) N
+        #define PP_RSEQ_N() 5, 4, 3, 2, 1, 0
+
+        #define _ARG5(_0, _1, _2, _3, _4, ...) _4
+        #define HAS_COMMA(...) _ARG5(__VA_ARGS__, 1, 1, 1, 0)
+        #define _TRIGGER_PARENTHESIS_(...) ,
+        #define ISEMPTY(...) \
+          _ISEMPTY( \
+            HAS_COMMA(__VA_ARGS__), \
+            HAS_COMMA(_TRIGGER_PARENTHESIS_ __VA_ARGS__), \
+            HAS_COMMA(__VA_ARGS__ (/*empty*/)), \
+            HAS_COMMA(_TRIGGER_PARENTHESIS_ __VA_ARGS__ (/*empty*/)) \
+          )
+        #define PASTE5(_0, _1, _2, _3, _4) _0 ## _1 ## _2 ## _3 ## _4
+        #define _ISEMPTY(_0, _1, _2, _3) HAS_COMMA(PASTE5(_IS_EMPTY_CASE_, _0, _1, _2, _3))
+        #define _IS_EMPTY_CASE_0001 ,
+
+        #define PARENS ()
+        #define EXPAND(...) EXPAND2(EXPAND2(EXPAND2(__VA_ARGS__)))
+        #define EXPAND2(...) EXPAND1(EXPAND1(EXPAND1(__VA_ARGS__)))
+        #define EXPAND1(...) __VA_ARGS__
+        #define FOR_EACH(macro, ...) __VA_OPT__(EXPAND(FOR_EACH_HELPER(macro, __VA_ARGS__)))
+        #define FOR_EACH_HELPER(macro, a1, ...) macro(a1) __VA_OPT__(FOR_EACH_AGAIN PARENS (macro, __VA_ARGS__))
+        #define FOR_EACH_AGAIN() FOR_EACH_HELPER
+        #define NEG(x) -x
+        "#,
+      )
+      .unwrap();
+
+    let assert_expands_to = |source, expected| {
+      let (source, expected) = (crate::tokenize(source).unwrap(), crate::tokenize(expected).unwrap());
+      assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+    };
+
+    assert_expands_to("PP_NARG(a) PP_NARG(a, b, c) PP_NARG(a, (b, c), d, e, f)", "1 3 5");
+
+    assert_expands_to("ISEMPTY()", "1");
+    assert_expands_to("ISEMPTY(a) ISEMPTY(a, b) ISEMPTY(()) ISEMPTY(HAS_COMMA)", "0 0 0 0");
+
+    assert_expands_to("FOR_EACH(NEG)", "");
+    assert_expands_to("FOR_EACH(NEG, 1, 2, 3)", "-1 -2 -3");
+  }
+
+  #[test]
+  fn var_opt_invalid() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define VAR __VA_OPT__(,)
+        #define NON_VARIADIC(x) x __VA_OPT__(,)
+        #define NO_PARENS(...) __VA_OPT__ ,
+        #define UNBALANCED(...) __VA_OPT__((,)
+        #define NESTED(...) __VA_OPT__(__VA_OPT__(,))
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(
+      macro_set.expand(&["VAR"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(2, 17)))
+    );
+    assert_eq!(
+      macro_set.expand(&["NON_VARIADIC", "(", "1", ")"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(3, 17)))
+    );
+    assert_eq!(
+      macro_set.expand(&["NO_PARENS", "(", "1", ")"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(4, 17)))
+    );
+    assert_eq!(
+      macro_set.expand(&["UNBALANCED", "(", "1", ")"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(5, 17)))
+    );
+    assert_eq!(
+      macro_set.expand(&["NESTED", "(", "1", ")"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(6, 17)))
+    );
+  }
+
+  #[test]
+  fn gnu_comma_concat() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(true);
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define LOG(fmt, ...) printf(fmt, ## __VA_ARGS__)
+        #define LOG2(fmt, args...) printf(fmt , /* comment */ ##args)
+        #define STR(args...) #args
+        #define ID(x) x
+        "#,
+      )
+      .unwrap();
+
+    let assert_expands_to = |source, expected| {
+      let (source, expected) = (crate::tokenize(source).unwrap(), crate::tokenize(expected).unwrap());
+      assert_eq!(macro_set.expand(&source), MacroSet::new().expand(&expected));
+    };
+
+    assert_expands_to(r#"LOG("hello")"#, r#"printf("hello")"#);
+    assert_expands_to(r#"LOG("hello", )"#, r#"printf("hello")"#);
+    assert_expands_to(r#"LOG("%d %d", 1, ID(2))"#, r#"printf("%d %d", 1, 2)"#);
+    assert_expands_to(r#"LOG2("hello")"#, r#"printf("hello")"#);
+    assert_expands_to(r#"LOG2("%d", 42)"#, r#"printf("%d", 42)"#);
+    assert_expands_to("STR(a, b)", r#""a, b""#);
+  }
+
+  #[test]
+  fn gnu_extensions_disabled() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("LOG", ["fmt", "..."], ["printf", "(", "fmt", ",", "##", "__VA_ARGS__", ")"]).unwrap();
+
+    assert_eq!(
+      macro_set.expand(&["LOG", "(", "\"hello\"", ")"]),
+      Ok(tokens![id!(printf), punct!("("), lit_string!("hello"), punct!(","), punct!(")")])
+    );
+    assert_eq!(macro_set.expand(&["LOG", "(", "\"%d\"", ",", "1", ")"]), Err(ExpansionError::InvalidConcat));
+    assert_eq!(
+      macro_set.define_fn_macro("LOG2", ["fmt", "args..."], ["printf", "(", "fmt", ",", "##", "args", ")"]),
+      Err(ExpansionError::InvalidArgumentName)
+    );
+  }
+
+  #[test]
+  fn pragma_operator() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define STR "once"
+        #define DO_PRAGMA(x) _Pragma(#x)
+        #define PUSH DO_PRAGMA(GCC diagnostic push)
+        #define RAW _Pragma(STR)
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(
+      macro_set.expand(&["PUSH"]),
+      Ok(tokens![id!(_Pragma), punct!("("), lit_string!("GCC diagnostic push"), punct!(")")])
+    );
+    assert_eq!(macro_set.expand(&["RAW"]), Ok(tokens![id!(_Pragma), punct!("("), id!(STR), punct!(")")]));
+  }
+
+  #[test]
+  fn fn_macro_call() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define STR(x) #x
+        #define XSTR(x) STR(x)
+        #define CAT(a, b) a ## b
+        #define ONE 1
+        #define VAR(x, ...) f(x __VA_OPT__(,) __VA_ARGS__)
+        #define NONE() 0
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(macro_set.expand_fn_macro_call("STR", &[["ONE"]]), Ok(tokens![lit_string!("ONE")]));
+    assert_eq!(macro_set.expand_fn_macro_call("XSTR", &[["ONE"]]), Ok(tokens![lit_string!("1")]));
+    assert_eq!(macro_set.expand_fn_macro_call("CAT", &[["ONE"], ["ONE"]]), Ok(tokens![id!(ONEONE)]));
+    assert_eq!(
+      macro_set.expand_fn_macro_call("VAR", &[vec!["ONE"], vec!["2"], vec!["a", "+", "b"]]),
+      macro_set.expand(&["f", "(", "1", ",", "2", ",", "a", "+", "b", ")"])
+    );
+    assert_eq!(macro_set.expand_fn_macro_call("VAR", &[["ONE"]]), macro_set.expand(&["f", "(", "1", ")"]));
+    assert_eq!(macro_set.expand_fn_macro_call::<[&str; 0], &str>("NONE", &[]), Ok(tokens![lit_int!(0)]));
+
+    assert_eq!(
+      macro_set.expand_fn_macro_call("CAT", &[["a"]]),
+      Err(
+        ExpansionError::FnMacroArgumentError { name: "CAT".into(), required: 2, given: 1 }
+          .with_location(SourceLocation::new(4, 17))
+      )
+    );
+    assert_eq!(
+      macro_set.expand_fn_macro_call::<[&str; 0], &str>("VAR", &[]).unwrap_err().without_location(),
+      &ExpansionError::FnMacroArgumentError { name: "VAR".into(), required: 1, given: 0 }
+    );
+    assert_eq!(macro_set.expand_fn_macro_call("ONE", &[["1"]]), Err(ExpansionError::MacroNotFound));
+  }
+
+  #[test]
+  fn locations() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define OK 1
+        #define STMT do { f(); } while (0)
+        #define INVALID ) (
+        #define OUTER INNER
+        #define INNER __VA_OPT__()
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(macro_set.location("OK"), Some(&SourceLocation::new(2, 17)));
+    assert_eq!(
+      macro_set.expand(&["OUTER"]),
+      Err(ExpansionError::InvalidVarOpt.with_location(SourceLocation::new(6, 17)))
+    );
+    assert_eq!(
+      macro_set.expand(&["OUTER"]).unwrap_err().to_string(),
+      "6:17: `__VA_OPT__` is not used in a variadic macro or not followed by `(...)`"
+    );
+
+    let location = macro_set.location("INVALID").unwrap().clone();
+    let body = macro_set.expand_var_macro("INVALID").unwrap();
+    let err = VarMacro::parse_with_location("INVALID", &body, location).unwrap_err();
+    assert_eq!(err.location(), Some(&SourceLocation::new(4, 17)));
+    assert_eq!(err.without_location(), &ParserError::InvalidMacroBody);
+
+    let location = macro_set.location("STMT").unwrap().clone();
+    let body = macro_set.expand_var_macro("STMT").unwrap();
+    let mut var_macro = VarMacro::parse_with_location("STMT", &body, location).unwrap();
+    assert_eq!(
+      var_macro.generate(()).unwrap_err(),
+      CodegenError::NonExpressionVarMacro.with_location(SourceLocation::new(3, 17))
+    );
+
+    macro_set.undefine_var_macro("OK");
+    assert_eq!(macro_set.location("OK"), None);
+    macro_set.define_var_macro("STMT", ["2"]).unwrap();
+    assert_eq!(macro_set.location("STMT"), None);
+  }
+
+  #[test]
+  fn rescan_following_tokens_only() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("f", ["x"], ["[", "x", "]"]).unwrap();
+    macro_set.define_var_macro("P", ["(", "1", ")"]).unwrap();
+    macro_set.define_var_macro("EMPTY", Vec::<&str>::new()).unwrap();
+    macro_set.define_fn_macro("DEFER", ["id"], ["id", "EMPTY"]).unwrap();
+    macro_set.define_var_macro("g", ["f"]).unwrap();
+
+    // Tokens preceding an expansion are not rescanned.
+    assert_eq!(macro_set.expand(&["f", "P"]), Ok(tokens![id!(f), punct!("("), lit_int!(1), punct!(")")]));
+    assert_eq!(
+      macro_set.expand(&["DEFER", "(", "f", ")", "(", "2", ")"]),
+      Ok(tokens![id!(f), punct!("("), lit_int!(2), punct!(")")])
+    );
+
+    // Arguments may extend past the end of a replacement list.
+    assert_eq!(macro_set.expand(&["g", "(", "2", ")"]), Ok(tokens![punct!("["), lit_int!(2), punct!("]")]));
+  }
+
+  #[test]
+  fn expand_long_body() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    macro_set.define_fn_macro("ADD", ["a", "b"], ["a", "+", "b"]).unwrap();
+
+    let body = ["ADD", "(", "ONE", ",", "ONE", ")", ";"].repeat(5000);
+    let tokens = macro_set.expand(&body).unwrap();
+    assert_eq!(tokens.len(), 4 * 5000);
+    assert_eq!(tokens[..4], tokens![lit_int!(1), punct!("+"), lit_int!(1), punct!(";")]);
+  }
+
+  #[test]
+  fn expand_large_replacement() {
+    let mut macro_set = MacroSet::new();
+
+    // `X14` expands to 2^14 tokens.
+    macro_set.define_var_macro("X0", ["x"]).unwrap();
+    for i in 1..=14 {
+      let prev = format!("X{}", i - 1);
+      macro_set.define_var_macro(format!("X{i}"), [&prev, &prev]).unwrap();
+    }
+
+    let tokens = macro_set.expand(&["X14"]).unwrap();
+    assert_eq!(tokens.len(), 1 << 14);
+    assert!(tokens.iter().all(|t| *t == MacroToken::Identifier(Identifier::try_from("x").unwrap())));
+
+    // A chain of thousands of nested expansions.
+    for i in 1..=5000 {
+      macro_set
+        .define_fn_macro(format!("F{i}"), ["a"], [format!("F{}", i - 1), "(".into(), "a".into(), ")".into()])
+        .unwrap();
+    }
+    macro_set.define_fn_macro("F0", ["a"], ["a"]).unwrap();
+
+    assert_eq!(macro_set.expand(&["F5000", "(", "X0", ")"]), Ok(tokens![id!(x)]));
+  }
+
+  #[test]
+  fn expand_linear_time() {
+    use std::time::{Duration, Instant};
+
+    let mut macro_set = MacroSet::new();
+    macro_set.define_var_macro("ONE", ["1"]).unwrap();
+    macro_set.define_fn_macro("NEG", ["a"], ["-", "a"]).unwrap();
+
+    let expand = |n: usize| -> Duration {
+      let body = ["NEG", "(", "ONE", ")", "+"].repeat(n);
+
+      (0..3)
+        .map(|_| {
+          let start = Instant::now();
+          assert_eq!(macro_set.expand(&body).unwrap().len(), 3 * n);
+          start.elapsed()
+        })
+        .min()
+        .unwrap()
+    };
+
+    // Expanding 8 times as many tokens should take about 8 times as long,
+    // whereas restarting after each expansion took about 64 times as long.
+    let small = expand(1000);
+    let large = expand(8000);
+    assert!(large < small * 24, "expanding 8000 invocations took {large:?}, 1000 took {small:?}");
+  }
 }
@@ -0,0 +1,152 @@
+use super::{FnMacroDef, MacroSet, VarMacroDef};
+use crate::SourceLocation;
+
+/// A saved macro definition.
+#[derive(Debug, Clone)]
+pub(super) enum Definition {
+  Var(VarMacroDef),
+  Fn(FnMacroDef),
+}
+
+/// A saved macro definition and its location, or `None` if the macro was not defined.
+pub(super) type PushedMacro = Option<(Definition, Option<SourceLocation>)>;
+
+impl MacroSet {
+  /// Save the current definition of a macro, like `#pragma push_macro("NAME")`.
+  ///
+  /// If the macro is currently not defined, popping it will undefine it again.
+  ///
+  /// The definition is not saved if the current conditional group is inactive.
+  pub fn push_macro<N>(&mut self, name: N)
+  where
+    N: AsRef<str>,
+  {
+    if !self.is_active() {
+      return
+    }
+
+    let name = name.as_ref();
+
+    let definition = if let Some((_, var_macro)) = self.var_macro(name) {
+      Some(Definition::Var(var_macro.clone()))
+    } else {
+      self.fn_macro(name).map(|(_, fn_macro)| Definition::Fn(fn_macro.clone()))
+    };
+    let pushed_macro = definition.map(|definition| (definition, self.location(name).cloned()));
+
+    self.pushed_macros.entry(name.to_owned()).or_default().push(pushed_macro);
+  }
+
+  /// Restore the last saved definition of a macro, like `#pragma pop_macro("NAME")`.
+  ///
+  /// Returns false if no definition was saved using [`MacroSet::push_macro`],
+  /// in which case the current definition is kept.
+  ///
+  /// The definition is not restored if the current conditional group is inactive.
+  pub fn pop_macro<N>(&mut self, name: N) -> bool
+  where
+    N: AsRef<str>,
+  {
+    if !self.is_active() {
+      return false
+    }
+
+    let name = name.as_ref();
+
+    let definition = match self.pushed_macros.get_mut(name).and_then(|stack| stack.pop()) {
+      Some(definition) => definition,
+      None => return false,
+    };
+
+    if self.pushed_macros.get(name).map(|stack| stack.is_empty()).unwrap_or(false) {
+      self.pushed_macros.remove(name);
+    }
+
+    self.var_macros.remove(name);
+    self.fn_macros.remove(name);
+    self.locations.remove(name);
+    self.invalidate(name);
+
+    if let Some((definition, location)) = definition {
+      self.undefined.remove(name);
+      self.insert_order(name);
+
+      match definition {
+        Definition::Var(var_macro) => {
+          self.var_macros.insert(name.to_owned(), var_macro);
+        },
+        Definition::Fn(fn_macro) => {
+          self.fn_macros.insert(name.to_owned(), fn_macro);
+        },
+      }
+
+      if let Some(location) = location {
+        self.locations.insert(name.to_owned(), location);
+      }
+    } else {
+      self.order.remove(name);
+      self.hide(name);
+    }
+
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn push_pop_macro() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("X", ["1"]).unwrap();
+    macro_set.push_macro("X");
+    macro_set.define_fn_macro("X", ["a"], ["a"]).unwrap();
+    macro_set.push_macro("X");
+    macro_set.undefine_fn_macro("X");
+
+    assert!(macro_set.pop_macro("X"));
+    assert_eq!(macro_set.fn_macros["X"].args, ["a"]);
+    assert_eq!(macro_set.fn_macros["X"].body, ["a"]);
+    assert!(!macro_set.var_macros.contains_key("X"));
+
+    assert!(macro_set.pop_macro("X"));
+    assert_eq!(macro_set.var_macros["X"].body, ["1"]);
+    assert!(!macro_set.fn_macros.contains_key("X"));
+
+    assert!(!macro_set.pop_macro("X"));
+    assert_eq!(macro_set.var_macros["X"].body, ["1"]);
+  }
+
+  #[test]
+  fn push_pop_undefined_macro() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.push_macro("Y");
+    macro_set.define_var_macro("Y", ["2"]).unwrap();
+
+    assert!(macro_set.pop_macro("Y"));
+    assert!(!macro_set.var_macros.contains_key("Y"));
+  }
+
+  #[test]
+  fn push_pop_macro_from_source() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #define WIDTH 80
+        #pragma push_macro("WIDTH")
+        #undef WIDTH
+        #define WIDTH 120
+        #define SAVED_WIDTH WIDTH
+        #pragma pop_macro("WIDTH")
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(macro_set.var_macros["WIDTH"].body, ["80"]);
+  }
+}
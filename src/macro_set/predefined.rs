@@ -0,0 +1,596 @@
+use super::MacroSet;
+
+/// A compiler whose predefined macros can be emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compiler {
+  /// GCC
+  Gcc,
+  /// Clang
+  Clang,
+  /// Microsoft Visual C++
+  Msvc,
+}
+
+/// A C language standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Standard {
+  /// C89/C90
+  C89,
+  /// C99
+  C99,
+  /// C11
+  C11,
+  /// C17
+  C17,
+  /// C23
+  C23,
+}
+
+impl Standard {
+  /// The value of `__STDC_VERSION__` for this standard, if it is defined.
+  pub const fn version(&self) -> Option<&'static str> {
+    match self {
+      Self::C89 => None,
+      Self::C99 => Some("199901L"),
+      Self::C11 => Some("201112L"),
+      Self::C17 => Some("201710L"),
+      Self::C23 => Some("202311L"),
+    }
+  }
+}
+
+/// A data model, i.e. the sizes of `int`, `long` and pointers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataModel {
+  /// 32-bit `int`, `long` and pointers, e.g. 32-bit Linux and Windows or the x32 ABI.
+  Ilp32,
+  /// 32-bit `int` and `long`, 64-bit pointers, e.g. 64-bit Windows.
+  Llp64,
+  /// 32-bit `int`, 64-bit `long` and pointers, e.g. 64-bit Linux and macOS.
+  Lp64,
+}
+
+impl DataModel {
+  const fn sizeof_long(&self) -> usize {
+    match self {
+      Self::Ilp32 | Self::Llp64 => 4,
+      Self::Lp64 => 8,
+    }
+  }
+
+  const fn sizeof_pointer(&self) -> usize {
+    match self {
+      Self::Ilp32 => 4,
+      Self::Llp64 | Self::Lp64 => 8,
+    }
+  }
+}
+
+/// A profile of predefined macros for a compiler and target.
+///
+/// # Example
+///
+/// ```
+/// use cmacro::{DataModel, Profile};
+///
+/// let profile = Profile::gcc("x86_64-unknown-linux-gnu");
+/// assert_eq!(profile.data_model(), DataModel::Lp64);
+///
+/// let profile = Profile::msvc("x86_64-pc-windows-msvc");
+/// assert_eq!(profile.data_model(), DataModel::Llp64);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+  compiler: Compiler,
+  target: String,
+  data_model: DataModel,
+  standard: Option<Standard>,
+  hosted: bool,
+}
+
+impl Profile {
+  /// Create a profile for the given compiler and target triple.
+  ///
+  /// The data model is derived from the target triple.
+  pub fn new(compiler: Compiler, target: &str) -> Self {
+    let pointer_width = if target.starts_with("x86_64")
+      || target.starts_with("aarch64")
+      || target.starts_with("arm64")
+      || target.starts_with("riscv64")
+      || target.starts_with("powerpc64")
+      || target.starts_with("s390x")
+      || target.starts_with("mips64")
+      || target.starts_with("sparc64")
+      || target.starts_with("wasm64")
+    {
+      64
+    } else {
+      32
+    };
+
+    let data_model = match pointer_width {
+      // The x32 ABI uses 32-bit pointers on x86-64.
+      64 if target.ends_with("gnux32") => DataModel::Ilp32,
+      64 if target.contains("windows") => DataModel::Llp64,
+      64 => DataModel::Lp64,
+      _ => DataModel::Ilp32,
+    };
+
+    Self { compiler, target: target.to_owned(), data_model, standard: None, hosted: true }
+  }
+
+  /// Create a profile for GCC and the given target triple.
+  pub fn gcc(target: &str) -> Self {
+    Self::new(Compiler::Gcc, target)
+  }
+
+  /// Create a profile for Clang and the given target triple.
+  pub fn clang(target: &str) -> Self {
+    Self::new(Compiler::Clang, target)
+  }
+
+  /// Create a profile for MSVC and the given target triple.
+  pub fn msvc(target: &str) -> Self {
+    Self::new(Compiler::Msvc, target)
+  }
+
+  /// Override the data model derived from the target triple.
+  pub fn with_data_model(mut self, data_model: DataModel) -> Self {
+    self.data_model = data_model;
+    self
+  }
+
+  /// Set the C language standard.
+  ///
+  /// By default, GCC and Clang use C17 and MSVC does not define `__STDC_VERSION__`.
+  pub fn with_standard(mut self, standard: Standard) -> Self {
+    self.standard = Some(standard);
+    self
+  }
+
+  /// Set whether the implementation is hosted, i.e. `__STDC_HOSTED__`.
+  ///
+  /// Defaults to `true`.
+  pub fn with_hosted(mut self, hosted: bool) -> Self {
+    self.hosted = hosted;
+    self
+  }
+
+  /// Get the compiler.
+  pub fn compiler(&self) -> Compiler {
+    self.compiler
+  }
+
+  /// Get the target triple.
+  pub fn target(&self) -> &str {
+    &self.target
+  }
+
+  /// Get the data model.
+  pub fn data_model(&self) -> DataModel {
+    self.data_model
+  }
+
+  /// Get the C language standard, if set.
+  pub fn standard(&self) -> Option<Standard> {
+    self.standard
+  }
+
+  /// Get whether the implementation is hosted.
+  pub fn hosted(&self) -> bool {
+    self.hosted
+  }
+
+  fn arch(&self) -> &str {
+    self.target.split('-').next().unwrap_or_default()
+  }
+
+  fn is_windows(&self) -> bool {
+    self.target.contains("windows")
+  }
+
+  fn is_little_endian(&self) -> bool {
+    let arch = self.arch();
+    !(matches!(arch, "powerpc" | "powerpc64" | "s390x" | "sparc" | "sparc64" | "mips" | "mips64")
+      || arch.ends_with("eb")
+      || arch.ends_with("_be"))
+  }
+
+  fn is_char_unsigned(&self) -> bool {
+    let arch = self.arch();
+    self.target.contains("linux")
+      && (arch.starts_with("aarch64") || arch.starts_with("arm") || arch.starts_with("thumb"))
+  }
+
+  fn sizeof_long_double(&self) -> usize {
+    match self.arch() {
+      _ if self.is_windows() && !self.target.ends_with("gnu") => 8,
+      "i386" | "i486" | "i586" | "i686" if self.target.contains("android") => 8,
+      "i386" | "i486" | "i586" | "i686" => 12,
+      "aarch64" | "arm64" if self.target.contains("apple") || self.target.contains("darwin") => 8,
+      "mips" | "mipsel" => 8,
+      arch if arch.starts_with("arm") || arch.starts_with("thumb") => 8,
+      _ => 16,
+    }
+  }
+
+  /// Get the predefined macros of this profile as pairs of names and bodies.
+  fn macros(&self) -> Vec<(&'static str, String)> {
+    let mut macros = vec![];
+    let mut define = |name, value: &str| macros.push((name, value.to_owned()));
+
+    let arch = self.arch();
+    let target = self.target.as_str();
+    let is_64_bit = self.data_model.sizeof_pointer() == 8;
+
+    define("__STDC_HOSTED__", if self.hosted { "1" } else { "0" });
+
+    if self.compiler == Compiler::Msvc {
+      // MSVC only defines `__STDC_VERSION__` with `/std:c11` or later.
+      if let Some(version) = self.standard.filter(|standard| *standard >= Standard::C11).and_then(|s| s.version()) {
+        define("__STDC_VERSION__", version);
+      }
+
+      define("_MSC_VER", "1930");
+      define("_MSC_FULL_VER", "193030705");
+      define("_MSC_EXTENSIONS", "1");
+      define("_WIN32", "1");
+      if is_64_bit {
+        define("_WIN64", "1");
+      }
+
+      match arch {
+        "x86_64" => {
+          define("_M_X64", "100");
+          define("_M_AMD64", "100");
+        },
+        "i386" | "i486" | "i586" | "i686" => define("_M_IX86", "600"),
+        "aarch64" | "arm64" => define("_M_ARM64", "1"),
+        "arm" | "armv7" | "thumbv7a" => define("_M_ARM", "7"),
+        _ => (),
+      }
+
+      return macros
+    }
+
+    define("__STDC__", "1");
+    if let Some(version) = self.standard.unwrap_or(Standard::C17).version() {
+      define("__STDC_VERSION__", version);
+    }
+
+    match self.compiler {
+      Compiler::Gcc => {
+        define("__GNUC__", "12");
+        define("__GNUC_MINOR__", "2");
+        define("__GNUC_PATCHLEVEL__", "0");
+        define("__VERSION__", "\"12.2.0\"");
+      },
+      Compiler::Clang => {
+        define("__clang__", "1");
+        define("__clang_major__", "16");
+        define("__clang_minor__", "0");
+        define("__clang_patchlevel__", "0");
+        define("__clang_version__", "\"16.0.0\"");
+        define("__GNUC__", "4");
+        define("__GNUC_MINOR__", "2");
+        define("__GNUC_PATCHLEVEL__", "1");
+        define("__VERSION__", "\"Clang 16.0.0\"");
+      },
+      Compiler::Msvc => unreachable!(),
+    }
+
+    // Data model.
+    match self.data_model {
+      DataModel::Lp64 => {
+        define("_LP64", "1");
+        define("__LP64__", "1");
+      },
+      DataModel::Ilp32 => {
+        define("_ILP32", "1");
+        define("__ILP32__", "1");
+      },
+      DataModel::Llp64 => (),
+    }
+
+    let sizeof_long = self.data_model.sizeof_long();
+    let sizeof_pointer = self.data_model.sizeof_pointer();
+    let sizeof_wchar = if self.is_windows() { 2 } else { 4 };
+
+    define("__CHAR_BIT__", "8");
+    if self.is_char_unsigned() {
+      define("__CHAR_UNSIGNED__", "1");
+    }
+    define("__SIZEOF_SHORT__", "2");
+    define("__SIZEOF_INT__", "4");
+    define("__SIZEOF_LONG__", &sizeof_long.to_string());
+    define("__SIZEOF_LONG_LONG__", "8");
+    define("__SIZEOF_FLOAT__", "4");
+    define("__SIZEOF_DOUBLE__", "8");
+    define("__SIZEOF_LONG_DOUBLE__", &self.sizeof_long_double().to_string());
+    define("__SIZEOF_POINTER__", &sizeof_pointer.to_string());
+    define("__SIZEOF_SIZE_T__", &sizeof_pointer.to_string());
+    define("__SIZEOF_PTRDIFF_T__", &sizeof_pointer.to_string());
+    define("__SIZEOF_WCHAR_T__", &sizeof_wchar.to_string());
+
+    define("__SCHAR_MAX__", "0x7f");
+    define("__SHRT_MAX__", "0x7fff");
+    define("__INT_MAX__", "0x7fffffff");
+    define("__LONG_MAX__", if sizeof_long == 8 { "0x7fffffffffffffffL" } else { "0x7fffffffL" });
+    define("__LONG_LONG_MAX__", "0x7fffffffffffffffLL");
+    define("__INTMAX_MAX__", if sizeof_long == 8 { "0x7fffffffffffffffL" } else { "0x7fffffffffffffffLL" });
+    define("__UINTMAX_MAX__", if sizeof_long == 8 { "0xffffffffffffffffUL" } else { "0xffffffffffffffffULL" });
+
+    // Types.
+    let (size_type, ptrdiff_type) = match self.data_model {
+      DataModel::Lp64 => ("long unsigned int", "long int"),
+      DataModel::Llp64 => ("long long unsigned int", "long long int"),
+      DataModel::Ilp32 => ("unsigned int", "int"),
+    };
+    let (intmax_type, uintmax_type) =
+      if sizeof_long == 8 { ("long int", "long unsigned int") } else { ("long long int", "long long unsigned int") };
+
+    define("__SIZE_TYPE__", size_type);
+    define("__PTRDIFF_TYPE__", ptrdiff_type);
+    define("__INTPTR_TYPE__", ptrdiff_type);
+    define("__UINTPTR_TYPE__", size_type);
+    define("__INTMAX_TYPE__", intmax_type);
+    define("__UINTMAX_TYPE__", uintmax_type);
+    define("__WCHAR_TYPE__", if self.is_windows() { "unsigned short" } else { "int" });
+    define(
+      "__SIZE_MAX__",
+      match sizeof_pointer {
+        // `long` is too small for pointers on LLP64 targets.
+        8 if sizeof_long < sizeof_pointer => "0xffffffffffffffffULL",
+        8 => "0xffffffffffffffffUL",
+        _ => "0xffffffffU",
+      },
+    );
+
+    // Byte order.
+    define("__ORDER_LITTLE_ENDIAN__", "1234");
+    define("__ORDER_BIG_ENDIAN__", "4321");
+    define("__ORDER_PDP_ENDIAN__", "3412");
+    define("__BYTE_ORDER__", if self.is_little_endian() { "__ORDER_LITTLE_ENDIAN__" } else { "__ORDER_BIG_ENDIAN__" });
+
+    // Architecture.
+    match arch {
+      "x86_64" => {
+        define("__x86_64__", "1");
+        define("__x86_64", "1");
+        define("__amd64__", "1");
+        define("__amd64", "1");
+      },
+      "i386" | "i486" | "i586" | "i686" => {
+        define("__i386__", "1");
+        define("__i386", "1");
+      },
+      "aarch64" | "aarch64_be" | "arm64" => define("__aarch64__", "1"),
+      arch if arch.starts_with("arm") || arch.starts_with("thumb") => define("__arm__", "1"),
+      "riscv32" | "riscv64" => {
+        define("__riscv", "1");
+        define("__riscv_xlen", &(sizeof_pointer * 8).to_string());
+      },
+      "powerpc" => define("__powerpc__", "1"),
+      "powerpc64" | "powerpc64le" => {
+        define("__powerpc__", "1");
+        define("__powerpc64__", "1");
+        define("__PPC64__", "1");
+      },
+      "wasm32" | "wasm64" => {
+        define("__wasm__", "1");
+        define(if is_64_bit { "__wasm64__" } else { "__wasm32__" }, "1");
+      },
+      _ => (),
+    }
+
+    // Operating system.
+    if target.contains("linux") {
+      define("__linux__", "1");
+      define("__linux", "1");
+      define("__gnu_linux__", "1");
+    }
+    if target.contains("android") {
+      define("__ANDROID__", "1");
+    }
+    if target.contains("apple") || target.contains("darwin") {
+      define("__APPLE__", "1");
+      define("__MACH__", "1");
+    }
+    if target.contains("freebsd") {
+      define("__FreeBSD__", "13");
+    }
+    if self.is_windows() {
+      define("_WIN32", "1");
+      if is_64_bit {
+        define("_WIN64", "1");
+      }
+
+      if target.ends_with("gnu") {
+        define("__MINGW32__", "1");
+        if is_64_bit {
+          define("__MINGW64__", "1");
+        }
+      }
+    } else {
+      define("__unix__", "1");
+      define("__unix", "1");
+
+      if !(target.contains("apple") || target.contains("darwin")) {
+        define("__ELF__", "1");
+      }
+    }
+
+    macros
+  }
+}
+
+impl MacroSet {
+  /// Create a new macro set containing the predefined macros of the given profile.
+  ///
+  /// GNU extensions (see [`MacroSet::set_gnu_extensions`]) are enabled for GCC and Clang.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{MacroSet, Profile};
+  ///
+  /// let mut macro_set = MacroSet::with_predefined(Profile::clang("aarch64-apple-darwin"));
+  ///
+  /// assert!(macro_set.if_directive(&["defined", "__APPLE__", "&&", "__SIZEOF_LONG__", "==", "8"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn with_predefined(profile: Profile) -> Self {
+    let mut macro_set = Self::new();
+    macro_set.set_gnu_extensions(profile.compiler != Compiler::Msvc);
+
+    for (name, body) in profile.macros() {
+      // Predefined macro bodies are always valid.
+      macro_set.define_var_macro(name, crate::tokenize(&body).unwrap()).unwrap();
+    }
+
+    macro_set
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ConditionValue;
+
+  fn evaluate(macro_set: &MacroSet, condition: &str) -> ConditionValue {
+    macro_set.evaluate_condition(&crate::tokenize(condition).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn data_model() {
+    assert_eq!(Profile::gcc("x86_64-unknown-linux-gnu").data_model(), DataModel::Lp64);
+    assert_eq!(Profile::gcc("i686-unknown-linux-gnu").data_model(), DataModel::Ilp32);
+    assert_eq!(Profile::gcc("x86_64-pc-windows-gnu").data_model(), DataModel::Llp64);
+    assert_eq!(Profile::clang("aarch64-apple-darwin").data_model(), DataModel::Lp64);
+    assert_eq!(Profile::msvc("i686-pc-windows-msvc").data_model(), DataModel::Ilp32);
+    assert_eq!(Profile::gcc("x86_64-unknown-linux-gnux32").data_model(), DataModel::Ilp32);
+    assert_eq!(
+      Profile::gcc("x86_64-unknown-linux-gnux32").with_data_model(DataModel::Lp64).data_model(),
+      DataModel::Lp64
+    );
+  }
+
+  #[test]
+  fn gcc_linux() {
+    let macro_set = MacroSet::with_predefined(Profile::gcc("x86_64-unknown-linux-gnu"));
+
+    assert!(macro_set.gnu_extensions());
+    assert_eq!(evaluate(&macro_set, "__GNUC__ >= 12 && !defined(__clang__)"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__x86_64__ && __linux__ && __unix__ && _LP64"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG__ * __CHAR_BIT__"), ConditionValue::Signed(64));
+    assert_eq!(evaluate(&macro_set, "__LONG_MAX__ == __INTMAX_MAX__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__STDC_VERSION__"), ConditionValue::Signed(201710));
+    assert_eq!(evaluate(&macro_set, "defined _WIN32"), ConditionValue::Signed(0));
+    assert_eq!(macro_set.expand(&["__SIZE_MAX__"]), macro_set.expand(&["0xffffffffffffffffUL"]));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffUL"]));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(16));
+    assert_eq!(evaluate(&macro_set, "defined(__CHAR_UNSIGNED__)"), ConditionValue::Signed(0));
+
+    let macro_set = MacroSet::with_predefined(Profile::gcc("i686-unknown-linux-gnu"));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG__ == 4 && __SIZEOF_POINTER__ == 4"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(12));
+    assert_eq!(macro_set.expand(&["__SIZE_MAX__"]), macro_set.expand(&["0xffffffffU"]));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffULL"]));
+  }
+
+  #[test]
+  fn gcc_x32() {
+    let macro_set = MacroSet::with_predefined(Profile::gcc("x86_64-unknown-linux-gnux32"));
+
+    assert_eq!(evaluate(&macro_set, "__x86_64__ && __ILP32__ && !defined(_LP64)"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG__ == 4 && __SIZEOF_POINTER__ == 4"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(16));
+    assert_eq!(macro_set.expand(&["__SIZE_MAX__"]), macro_set.expand(&["0xffffffffU"]));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffULL"]));
+  }
+
+  #[test]
+  fn gcc_arm_linux() {
+    let macro_set = MacroSet::with_predefined(Profile::gcc("aarch64-unknown-linux-gnu"));
+
+    assert_eq!(evaluate(&macro_set, "__aarch64__ && __CHAR_UNSIGNED__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(16));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffUL"]));
+
+    let macro_set = MacroSet::with_predefined(Profile::gcc("armv7-unknown-linux-gnueabihf"));
+    assert_eq!(evaluate(&macro_set, "__arm__ && __CHAR_UNSIGNED__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(8));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffULL"]));
+  }
+
+  #[test]
+  fn clang_apple() {
+    let macro_set = MacroSet::with_predefined(Profile::clang("aarch64-apple-darwin"));
+
+    assert_eq!(evaluate(&macro_set, "__aarch64__ && __APPLE__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "defined(__CHAR_UNSIGNED__)"), ConditionValue::Signed(0));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(8));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffUL"]));
+  }
+
+  #[test]
+  fn clang_windows() {
+    let macro_set = MacroSet::with_predefined(Profile::clang("x86_64-pc-windows-gnu"));
+
+    assert_eq!(evaluate(&macro_set, "__clang__ && _WIN32 && _WIN64 && __MINGW64__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG__ == 4 && __SIZEOF_POINTER__ == 8"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_WCHAR_T__"), ConditionValue::Signed(2));
+    assert_eq!(macro_set.expand(&["__SIZE_MAX__"]), macro_set.expand(&["0xffffffffffffffffULL"]));
+    assert_eq!(macro_set.expand(&["__UINTMAX_MAX__"]), macro_set.expand(&["0xffffffffffffffffULL"]));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(16));
+    assert_eq!(
+      evaluate(&macro_set, "defined(__unix__) || defined(_LP64) || defined(__CHAR_UNSIGNED__)"),
+      ConditionValue::Signed(0)
+    );
+
+    let macro_set = MacroSet::with_predefined(Profile::clang("x86_64-pc-windows-msvc"));
+    assert_eq!(evaluate(&macro_set, "__SIZEOF_LONG_DOUBLE__"), ConditionValue::Signed(8));
+  }
+
+  #[test]
+  fn msvc() {
+    let macro_set = MacroSet::with_predefined(Profile::msvc("i686-pc-windows-msvc"));
+
+    assert!(!macro_set.gnu_extensions());
+    assert_eq!(evaluate(&macro_set, "_MSC_VER >= 1900 && _WIN32 && _M_IX86"), ConditionValue::Signed(1));
+    assert_eq!(
+      evaluate(&macro_set, "defined(_WIN64) || defined(__GNUC__) || defined(__STDC__) || defined(__STDC_VERSION__)"),
+      ConditionValue::Signed(0)
+    );
+
+    let macro_set = MacroSet::with_predefined(Profile::msvc("x86_64-pc-windows-msvc").with_standard(Standard::C11));
+    assert_eq!(evaluate(&macro_set, "__STDC_VERSION__"), ConditionValue::Signed(201112));
+  }
+
+  #[test]
+  fn standard() {
+    let profile = Profile::gcc("x86_64-unknown-linux-gnu").with_standard(Standard::C99).with_hosted(false);
+    let macro_set = MacroSet::with_predefined(profile);
+    assert_eq!(evaluate(&macro_set, "__STDC__ && __STDC_VERSION__ == 199901L"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__STDC_HOSTED__"), ConditionValue::Signed(0));
+
+    let macro_set = MacroSet::with_predefined(Profile::clang("x86_64-apple-darwin").with_standard(Standard::C89));
+    assert_eq!(evaluate(&macro_set, "defined(__STDC_VERSION__)"), ConditionValue::Signed(0));
+    assert_eq!(evaluate(&macro_set, "__STDC_HOSTED__"), ConditionValue::Signed(1));
+  }
+
+  #[test]
+  fn big_endian() {
+    let macro_set = MacroSet::with_predefined(Profile::gcc("powerpc64-unknown-linux-gnu"));
+
+    assert_eq!(evaluate(&macro_set, "__BYTE_ORDER__ == __ORDER_BIG_ENDIAN__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__powerpc64__ && __LP64__"), ConditionValue::Signed(1));
+
+    let macro_set = MacroSet::with_predefined(Profile::gcc("aarch64_be-unknown-linux-gnu"));
+    assert_eq!(evaluate(&macro_set, "__BYTE_ORDER__ == __ORDER_BIG_ENDIAN__"), ConditionValue::Signed(1));
+    assert_eq!(evaluate(&macro_set, "__aarch64__ && __LP64__"), ConditionValue::Signed(1));
+  }
+}
@@ -0,0 +1,233 @@
+use super::{is_whitespace, MacroDefinition, MacroKind, MacroSet};
+use crate::SourceLocation;
+
+/// The previous definition of a redefined macro, see [`Redefinition`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PreviousDefinition {
+  args: Option<Vec<String>>,
+  body: Vec<String>,
+  location: Option<SourceLocation>,
+}
+
+impl PreviousDefinition {
+  /// Whether the macro was variable-like or function-like.
+  pub fn kind(&self) -> MacroKind {
+    if self.args.is_some() {
+      MacroKind::Fn
+    } else {
+      MacroKind::Var
+    }
+  }
+
+  /// The parameters of a function-like macro, or `None` for a variable-like macro.
+  pub fn args(&self) -> Option<&[String]> {
+    self.args.as_deref()
+  }
+
+  /// The body of the macro.
+  pub fn body(&self) -> &[String] {
+    &self.body
+  }
+
+  /// The location of the definition, if known.
+  pub fn location(&self) -> Option<&SourceLocation> {
+    self.location.as_ref()
+  }
+}
+
+impl From<MacroDefinition<'_>> for PreviousDefinition {
+  fn from(definition: MacroDefinition<'_>) -> Self {
+    Self {
+      args: definition.args().map(|args| args.to_vec()),
+      body: definition.body().to_vec(),
+      location: definition.location().cloned(),
+    }
+  }
+}
+
+/// The result of defining a macro, see [`MacroSet::define_var_macro`] and [`MacroSet::define_fn_macro`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Redefinition {
+  /// The macro was not defined before.
+  New,
+  /// The macro was already defined identically.
+  Identical(PreviousDefinition),
+  /// The macro was already defined with different parameters or a different
+  /// replacement list, see C11 6.10.3p2.
+  Incompatible(PreviousDefinition),
+  /// The macro was already defined as a function-like macro and is now
+  /// variable-like, or vice versa.
+  KindChanged(PreviousDefinition),
+  /// The macro was not defined since the current conditional group is inactive.
+  Skipped,
+}
+
+impl Redefinition {
+  /// The previous definition, if the macro was already defined.
+  pub fn previous(&self) -> Option<&PreviousDefinition> {
+    match self {
+      Self::Identical(previous) | Self::Incompatible(previous) | Self::KindChanged(previous) => Some(previous),
+      Self::New | Self::Skipped => None,
+    }
+  }
+
+  /// Check whether the previous definition was replaced by a different one.
+  pub fn is_incompatible(&self) -> bool {
+    matches!(self, Self::Incompatible(_) | Self::KindChanged(_))
+  }
+}
+
+/// The tokens of a replacement list along with whether they are preceded by whitespace.
+///
+/// Comments count as whitespace, whitespace before the first and after the last token is ignored.
+fn spaced_tokens(body: &[String]) -> impl Iterator<Item = (bool, &str)> {
+  let mut space_before = false;
+  let mut first = true;
+
+  body.iter().filter_map(move |token| {
+    if is_whitespace(token) {
+      space_before = !first;
+      return None
+    }
+
+    let spaced = (space_before, token.as_str());
+    space_before = false;
+    first = false;
+    Some(spaced)
+  })
+}
+
+/// Check whether two definitions are identical, see C11 6.10.3p2.
+///
+/// Both definitions must be of the same kind with the same parameters, and the
+/// replacement lists must have the same tokens with the same whitespace separation,
+/// where any amount of whitespace is considered identical.
+pub(super) fn is_identical(definition: &MacroDefinition<'_>, other: &MacroDefinition<'_>) -> bool {
+  let args_identical = match (definition.args(), other.args()) {
+    (Some(args), Some(other_args)) => {
+      args.iter().filter(|arg| !is_whitespace(arg)).eq(other_args.iter().filter(|arg| !is_whitespace(arg)))
+    },
+    (None, None) => true,
+    _ => false,
+  };
+
+  args_identical && spaced_tokens(definition.body()).eq(spaced_tokens(other.body()))
+}
+
+impl MacroSet {
+  /// Enable or disable strict redefinitions.
+  ///
+  /// In strict mode, defining a macro which is already defined differently, i.e.
+  /// where [`Redefinition::is_incompatible`] would be true, returns
+  /// [`ExpansionError::IncompatibleRedefinition`](super::ExpansionError::IncompatibleRedefinition)
+  /// and keeps the previous definition.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::{ExpansionError, MacroSet, Redefinition};
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.set_strict_redefinitions(true);
+  ///
+  /// assert_eq!(macro_set.define_var_macro("A", ["1", " ", "+", " ", "2"])?, Redefinition::New);
+  /// let redefinition = macro_set.define_var_macro("A", ["1", "  ", "+", "/* plus */", "2"])?;
+  /// assert!(matches!(redefinition, Redefinition::Identical(_)));
+  /// assert_eq!(
+  ///   macro_set.define_var_macro("A", ["1", "+", "2"]),
+  ///   Err(ExpansionError::IncompatibleRedefinition("A".into())),
+  /// );
+  /// assert_eq!(macro_set.definition("A").unwrap().body(), ["1", "  ", "+", "/* plus */", "2"]);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn set_strict_redefinitions(&mut self, enabled: bool) {
+    self.strict_redefinitions = enabled;
+  }
+
+  /// Check whether strict redefinitions are enabled.
+  pub fn strict_redefinitions(&self) -> bool {
+    self.strict_redefinitions
+  }
+
+  /// Compare a new definition of a macro with its current definition.
+  pub(super) fn redefinition(&self, definition: &MacroDefinition<'_>) -> Redefinition {
+    let previous = match self.definition(definition.name()) {
+      Some(previous) => previous,
+      None => return Redefinition::New,
+    };
+
+    if previous.kind() != definition.kind() {
+      Redefinition::KindChanged(previous.into())
+    } else if is_identical(&previous, definition) {
+      Redefinition::Identical(previous.into())
+    } else {
+      Redefinition::Incompatible(previous.into())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ExpansionError;
+
+  #[test]
+  fn redefinition() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(macro_set.define_var_macro("A", ["A"]), Ok(Redefinition::New));
+    assert!(matches!(macro_set.define_var_macro("A", [" ", "A", "/* a */"]), Ok(Redefinition::Identical(_))));
+
+    // A longer body is not identical.
+    let redefinition = macro_set.define_var_macro("A", ["A", "+", "1"]).unwrap();
+    assert!(matches!(redefinition, Redefinition::Incompatible(_)));
+    assert_eq!(redefinition.previous().unwrap().body(), [" ", "A", "/* a */"]);
+
+    // Whitespace separation must be the same.
+    let redefinition = macro_set.define_var_macro("A", ["A", " ", "+", "1"]).unwrap();
+    assert!(redefinition.is_incompatible());
+
+    let redefinition = macro_set.define_fn_macro_with_location("A", ["x"], ["x"], SourceLocation::new(2, 9)).unwrap();
+    assert!(matches!(redefinition, Redefinition::KindChanged(_)));
+    assert_eq!(redefinition.previous().unwrap().kind(), MacroKind::Var);
+
+    let redefinition = macro_set.define_fn_macro("A", ["y"], ["y"]).unwrap();
+    assert!(matches!(redefinition, Redefinition::Incompatible(_)));
+    assert_eq!(redefinition.previous().unwrap().args(), Some(&["x".to_owned()][..]));
+    assert_eq!(redefinition.previous().unwrap().location(), Some(&SourceLocation::new(2, 9)));
+
+    macro_set.if_directive(&["0"]).unwrap();
+    assert_eq!(macro_set.define_var_macro("A", ["1"]), Ok(Redefinition::Skipped));
+    macro_set.endif_directive().unwrap();
+  }
+
+  #[test]
+  fn redefinition_from_source() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_strict_redefinitions(true);
+
+    macro_set.define_from_source("#define A (1-1)\n#define A  (1-1) /* one */\n#define A (1-1)").unwrap();
+    assert_eq!(
+      macro_set.define_from_source("#define A (1 - 1)"),
+      Err(ExpansionError::IncompatibleRedefinition("A".into()).with_location(SourceLocation::new(1, 9)).into())
+    );
+  }
+
+  #[test]
+  fn strict_redefinitions() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_strict_redefinitions(true);
+
+    macro_set.define_from_source("#define A 1\n#define A /* one */ 1\n#define F(x) x").unwrap();
+    assert_eq!(
+      macro_set.define_from_source("#define B 2\n#define F 1"),
+      Err(ExpansionError::IncompatibleRedefinition("F".into()).with_location(SourceLocation::new(2, 9)).into())
+    );
+    assert_eq!(macro_set.definition("F").unwrap().kind(), MacroKind::Fn);
+    assert!(macro_set.definition("B").is_some());
+  }
+}
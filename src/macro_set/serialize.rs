@@ -0,0 +1,155 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Limits, MacroDefinition, MacroSet};
+use crate::SourceLocation;
+
+/// The serialized form of a [`MacroSet`].
+#[derive(Serialize)]
+struct SerializeMacroSet<'s> {
+  gnu_extensions: bool,
+  strict_redefinitions: bool,
+  limits: Limits,
+  macros: Vec<MacroDefinition<'s>>,
+}
+
+/// The deserialized form of a [`MacroSet`].
+#[derive(Deserialize)]
+struct DeserializeMacroSet {
+  #[serde(default)]
+  gnu_extensions: bool,
+  #[serde(default)]
+  strict_redefinitions: bool,
+  #[serde(default)]
+  limits: Limits,
+  macros: Vec<DeserializeMacroDefinition>,
+}
+
+/// The deserialized form of a [`MacroDefinition`].
+#[derive(Deserialize)]
+struct DeserializeMacroDefinition {
+  name: String,
+  #[serde(default)]
+  args: Option<Vec<String>>,
+  body: Vec<String>,
+  #[serde(default)]
+  location: Option<SourceLocation>,
+}
+
+/// A [`MacroSet`] is serialized as its settings and the definitions of all macros
+/// visible in it, in definition order:
+///
+/// ```json
+/// {
+///   "gnu_extensions": false,
+///   "strict_redefinitions": false,
+///   "limits": { "max_depth": null, "max_tokens": null, "max_argument_tokens": null },
+///   "macros": [
+///     { "name": "VAR", "args": null, "body": ["1"], "location": null },
+///     { "name": "FUNC", "args": ["x"], "body": ["x"], "location": { "file": "a.h", "line": 2, "column": 9 } }
+///   ]
+/// }
+/// ```
+///
+/// Conditional directives being processed, macros saved with [`MacroSet::push_macro`],
+/// include paths, the feature query and the timestamp are not serialized. Overlays
+/// (see [`MacroSet::overlay`]) are serialized without their parent, i.e. flattened.
+impl Serialize for MacroSet {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    SerializeMacroSet {
+      gnu_extensions: self.gnu_extensions,
+      strict_redefinitions: self.strict_redefinitions,
+      limits: self.limits,
+      macros: self.definitions().collect(),
+    }
+    .serialize(serializer)
+  }
+}
+
+/// A [`MacroSet`] is deserialized by defining all serialized macros in order,
+/// see [`Serialize`](#impl-Serialize-for-MacroSet) for the format.
+impl<'de> Deserialize<'de> for MacroSet {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let serialized = DeserializeMacroSet::deserialize(deserializer)?;
+
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(serialized.gnu_extensions);
+    macro_set.set_limits(serialized.limits);
+
+    for definition in serialized.macros {
+      let DeserializeMacroDefinition { name, args, body, location } = definition;
+
+      let result = match (args, location) {
+        (Some(args), Some(location)) => macro_set.define_fn_macro_with_location(&name, args, body, location),
+        (Some(args), None) => macro_set.define_fn_macro(&name, args, body),
+        (None, Some(location)) => macro_set.define_var_macro_with_location(&name, body, location),
+        (None, None) => macro_set.define_var_macro(&name, body),
+      };
+
+      result.map_err(|err| de::Error::custom(format!("invalid macro {name}: {err}")))?;
+    }
+
+    macro_set.set_strict_redefinitions(serialized.strict_redefinitions);
+
+    Ok(macro_set)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::{ExpansionError, MacroToken};
+
+  #[test]
+  fn round_trip() {
+    let mut macro_set = MacroSet::new();
+    macro_set.set_gnu_extensions(true);
+    macro_set.define_var_macro("VAR", ["1", "+", "FUNC", "(", "2", ")"]).unwrap();
+    macro_set.define_fn_macro_with_location("FUNC", ["args..."], ["args"], SourceLocation::new(2, 9)).unwrap();
+
+    let value = serde_json::to_value(&macro_set).unwrap();
+    assert_eq!(
+      value,
+      json!({
+        "gnu_extensions": true,
+        "strict_redefinitions": false,
+        "limits": { "max_depth": null, "max_tokens": null, "max_argument_tokens": null },
+        "macros": [
+          { "name": "VAR", "args": null, "body": ["1", "+", "FUNC", "(", "2", ")"], "location": null },
+          { "name": "FUNC", "args": ["args..."], "body": ["args"], "location": { "file": null, "line": 2, "column": 9 } },
+        ],
+      })
+    );
+
+    let deserialized: MacroSet = serde_json::from_value(value).unwrap();
+    assert!(deserialized.diff(&macro_set).is_empty());
+    assert_eq!(deserialized.location("FUNC"), Some(&SourceLocation::new(2, 9)));
+    assert_eq!(deserialized.expand_var_macro("VAR"), macro_set.expand_var_macro("VAR"));
+
+    let invalid = json!({ "macros": [{ "name": "F", "args": ["x", "x"], "body": [] }] });
+    assert!(serde_json::from_value::<MacroSet>(invalid).is_err());
+  }
+
+  #[test]
+  fn tokens_and_errors() {
+    let macro_set = MacroSet::new();
+    let tokens = macro_set.expand(&["a", "+=", "1", "/* c */"]).unwrap();
+    let value = serde_json::to_value(&tokens).unwrap();
+    assert_eq!(
+      value,
+      json!([
+        { "Identifier": "a" },
+        { "Punctuation": "+=" },
+        { "Lit": { "Int": { "value": 1, "suffix": null } } },
+        { "Comment": " c " },
+      ])
+    );
+    assert_eq!(serde_json::from_value::<Vec<MacroToken>>(value).unwrap(), tokens);
+    assert!(serde_json::from_value::<MacroToken>(json!({ "Punctuation": "@" })).is_err());
+
+    let err = ExpansionError::MacroNotFound.with_location(SourceLocation::new(1, 2));
+    let value = serde_json::to_value(&err).unwrap();
+    assert_eq!(serde_json::from_value::<ExpansionError>(value).unwrap(), err);
+  }
+}
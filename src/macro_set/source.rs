@@ -0,0 +1,479 @@
+use std::{error::Error, fmt, path::Path};
+
+use super::{ExpansionError, FileLookup, MacroSet};
+use crate::{
+  ast::{Identifier, Lit, LitString},
+  tokenizer::{tokenize_lines, SourceToken},
+  ParserError, SourceLocation,
+};
+
+/// An error while processing C source code.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SourceError {
+  /// Invalid directive.
+  Parser(ParserError),
+  /// Invalid conditional directive.
+  Expansion(ExpansionError),
+  /// `#include` is not followed by a header name.
+  InvalidInclude,
+  /// Included file not found.
+  IncludeNotFound(String),
+  /// Maximum include depth exceeded, e.g. because of recursive includes.
+  IncludeDepthExceeded,
+  /// Conditional directive is not terminated at the end of a file.
+  UnterminatedConditional,
+  /// An `#include` or conditional directive error at the given location.
+  Located {
+    /// The location of the `#include` directive, or of the `#if`, `#ifdef` or `#ifndef`
+    /// directive of an unterminated conditional.
+    location: SourceLocation,
+    /// The error.
+    error: Box<Self>,
+  },
+}
+
+impl fmt::Display for SourceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Parser(err) => err.fmt(f),
+      Self::Expansion(err) => err.fmt(f),
+      Self::InvalidInclude => "`#include` is not followed by a header name".fmt(f),
+      Self::IncludeNotFound(name) => write!(f, "included file {name} not found"),
+      Self::IncludeDepthExceeded => "maximum include depth exceeded".fmt(f),
+      Self::UnterminatedConditional => "conditional directive is not terminated".fmt(f),
+      Self::Located { location, error } => write!(f, "{location}: {error}"),
+    }
+  }
+}
+
+impl Error for SourceError {}
+
+impl SourceError {
+  /// Add the location of a directive to an error, unless it already has one.
+  ///
+  /// The location of a parser or expansion error is added to the inner error.
+  fn with_location(self, location: SourceLocation) -> Self {
+    match self {
+      Self::Parser(err) => Self::Parser(err.with_location(location)),
+      Self::Expansion(err) => Self::Expansion(err.with_location(location)),
+      err @ (Self::InvalidInclude
+      | Self::IncludeNotFound(_)
+      | Self::IncludeDepthExceeded
+      | Self::UnterminatedConditional) => Self::Located { location, error: Box::new(err) },
+      err => err,
+    }
+  }
+}
+
+impl From<ParserError> for SourceError {
+  fn from(err: ParserError) -> Self {
+    Self::Parser(err)
+  }
+}
+
+impl From<ExpansionError> for SourceError {
+  fn from(err: ExpansionError) -> Self {
+    Self::Expansion(err)
+  }
+}
+
+/// Parse a macro name.
+fn macro_name(token: Option<&SourceToken>) -> Result<&str, ParserError> {
+  match token {
+    Some(token) if token.text != "defined" && Identifier::try_from(token.text.as_str()).is_ok() => Ok(&token.text),
+    _ => Err(ParserError::InvalidMacroName),
+  }
+}
+
+/// Parse the parameters of a function-like macro, i.e. the tokens between the parentheses.
+///
+/// With GNU extensions, the last parameter may be a named variadic parameter, e.g. `args...`.
+fn macro_args(tokens: &[SourceToken], gnu_extensions: bool) -> Result<Vec<String>, ParserError> {
+  let mut args = vec![];
+  let mut it = tokens.iter().filter(|t| !t.is_comment()).map(|t| t.text.as_str()).peekable();
+
+  let mut arg = it.next();
+  if arg.is_none() {
+    return Ok(args)
+  }
+
+  loop {
+    match arg {
+      Some("...") => {
+        args.push("...".to_owned());
+
+        if it.next().is_some() {
+          return Err(ParserError::InvalidMacroArgs)
+        }
+
+        return Ok(args)
+      },
+      Some(name) if gnu_extensions && it.peek() == Some(&"...") && Identifier::try_from(name).is_ok() => {
+        args.push(format!("{name}..."));
+        it.next();
+
+        if it.next().is_some() {
+          return Err(ParserError::InvalidMacroArgs)
+        }
+
+        return Ok(args)
+      },
+      Some(name) if Identifier::try_from(name).is_ok() => args.push(name.to_owned()),
+      _ => return Err(ParserError::InvalidMacroArgs),
+    }
+
+    match it.next() {
+      Some(",") => arg = it.next(),
+      None => return Ok(args),
+      _ => return Err(ParserError::InvalidMacroArgs),
+    }
+  }
+}
+
+/// The tokens of a replacement list, with whitespace between tokens preceded by whitespace
+/// so that redefinitions can be compared, see C11 6.10.3p2.
+fn replacement_list(tokens: &[SourceToken]) -> impl Iterator<Item = &str> {
+  tokens.iter().enumerate().flat_map(|(i, t)| {
+    let space = if i > 0 && t.space_before { Some(" ") } else { None };
+    space.into_iter().chain(Some(t.text.as_str()))
+  })
+}
+
+/// Split a line into its directive name and the following tokens, if it is a directive.
+fn directive(line: &[SourceToken]) -> Option<(&str, &[SourceToken])> {
+  let mut it = line.iter().enumerate().filter(|(_, t)| !t.is_comment());
+
+  match it.next() {
+    Some((_, t)) if t.text == "#" || t.text == "%:" => (),
+    _ => return None,
+  }
+
+  let (i, directive) = it.next()?;
+  let tokens = &line[(i + 1)..];
+  let start = tokens.iter().position(|t| !t.is_comment()).unwrap_or(tokens.len());
+
+  Some((directive.text.as_str(), &tokens[start..]))
+}
+
+/// The file currently being processed.
+#[derive(Clone, Copy)]
+pub(super) struct SourceFile<'f> {
+  pub path: &'f Path,
+  pub lookup: &'f dyn FileLookup,
+  pub depth: usize,
+}
+
+/// The location of a token in the given file.
+fn location(token: &SourceToken, file: Option<SourceFile<'_>>) -> SourceLocation {
+  let location = SourceLocation::new(token.line, token.column);
+
+  match file {
+    Some(file) => location.with_file(file.path),
+    None => location,
+  }
+}
+
+impl MacroSet {
+  fn define_from_tokens(&mut self, tokens: &[SourceToken], file: Option<SourceFile<'_>>) -> Result<(), SourceError> {
+    let name = macro_name(tokens.first())?;
+    let location = location(&tokens[0], file);
+    let rest = &tokens[1..];
+
+    // A function-like macro requires the parenthesis to directly follow the name.
+    match rest.first() {
+      Some(token) if token.text == "(" && !token.space_before => {
+        let end = rest.iter().position(|t| t.text == ")").ok_or(ParserError::InvalidMacroArgs)?;
+        let args = macro_args(&rest[1..end], self.gnu_extensions)?;
+        self.define_fn_macro_with_location(name, args, replacement_list(&rest[(end + 1)..]), location)?;
+      },
+      _ => {
+        self.define_var_macro_with_location(name, replacement_list(rest), location)?;
+      },
+    }
+
+    Ok(())
+  }
+
+  /// Process the directives in the given lines.
+  pub(super) fn define_from_lines(
+    &mut self,
+    lines: &[Vec<SourceToken>],
+    file: Option<SourceFile<'_>>,
+  ) -> Result<(), SourceError> {
+    // Conditionals must be terminated in the same file.
+    let conditionals = self.conditionals.len();
+    let mut locations = Vec::new();
+
+    for line in lines {
+      let (directive, tokens) = match directive(line) {
+        Some(directive) => directive,
+        None => continue,
+      };
+
+      let depth = self.conditionals.len();
+      if let Err(err) = self.process_directive(directive, tokens, file, conditionals) {
+        // Leave the conditionals of this file, so that later definitions are not skipped.
+        self.conditionals.truncate(conditionals);
+        return Err(err.with_location(location(&line[0], file)))
+      }
+
+      // Keep track of where each conditional of this file was entered.
+      if self.conditionals.len() > depth {
+        locations.push(location(&line[0], file));
+      } else if self.conditionals.len() < depth {
+        locations.pop();
+      }
+    }
+
+    if let Some(location) = locations.pop() {
+      self.conditionals.truncate(conditionals);
+      return Err(SourceError::UnterminatedConditional.with_location(location))
+    }
+
+    Ok(())
+  }
+
+  /// Process a single directive.
+  fn process_directive(
+    &mut self,
+    directive: &str,
+    tokens: &[SourceToken],
+    file: Option<SourceFile<'_>>,
+    conditionals: usize,
+  ) -> Result<(), SourceError> {
+    let active = self.is_active();
+    let condition = || tokens.iter().filter(|t| !t.is_comment()).map(|t| t.text.as_str()).collect::<Vec<_>>();
+
+    if matches!(directive, "elif" | "else" | "endif") && self.conditionals.len() <= conditionals {
+      return Err(ExpansionError::UnmatchedConditional.into())
+    }
+
+    match directive {
+      "if" => {
+        self.if_directive(&condition())?;
+      },
+      "ifdef" | "ifndef" => {
+        // The macro name is only checked inside of an active group.
+        let name = if active { macro_name(tokens.first())? } else { "" };
+
+        if directive == "ifdef" {
+          self.ifdef_directive(name);
+        } else {
+          self.ifndef_directive(name);
+        }
+      },
+      "elif" => {
+        self.elif_directive(&condition())?;
+      },
+      "else" => {
+        self.else_directive()?;
+      },
+      "endif" => {
+        self.endif_directive()?;
+      },
+      _ if !active => (),
+      "define" => self.define_from_tokens(tokens, file)?,
+      "undef" => {
+        let name = macro_name(tokens.first())?;
+        if !self.undefine_var_macro(name) {
+          self.undefine_fn_macro(name);
+        }
+      },
+      "include" => {
+        if let Some(file) = file {
+          self.include(tokens, file)?;
+        }
+      },
+      "pragma" => match condition().as_slice() {
+        ["once"] => {
+          if let Some(file) = file {
+            self.includes.once.insert(file.path.to_owned());
+          }
+        },
+        [pragma @ ("push_macro" | "pop_macro"), "(", name, ")"] => {
+          // Pragmas with an invalid macro name are ignored.
+          if let Ok(Lit::String(LitString::Ordinary(name))) = Lit::try_from(*name) {
+            let name = String::from_utf8_lossy(&name);
+
+            if *pragma == "push_macro" {
+              self.push_macro(name);
+            } else {
+              self.pop_macro(name);
+            }
+          }
+        },
+        _ => (),
+      },
+      _ => (),
+    }
+
+    Ok(())
+  }
+
+  /// Define and undefine macros using C source code.
+  ///
+  /// The source is split into tokens using [`tokenize`](crate::tokenize). All
+  /// `#define` and `#undef` directives are applied in order, taking conditional
+  /// directives into account. `#include` directives and all other lines are
+  /// ignored, see [`MacroSet::define_from_file`] for resolving includes.
+  ///
+  /// The location of each definition is recorded, see [`MacroSet::location`].
+  /// Errors in directives contain the location of the directive.
+  ///
+  /// Conditional directives must be terminated in the same source. If an error
+  /// occurs, all conditionals entered by the source are left again, so that the
+  /// set is in the same conditional group as before.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// use cmacro::MacroSet;
+  ///
+  /// let mut macro_set = MacroSet::new();
+  /// macro_set.define_from_source(
+  ///   r#"
+  ///   // Add two numbers.
+  ///   #define ADD(a, b) ((a) + \
+  ///                      (b))
+  ///   #define THREE ADD(1, 2)
+  ///   "#,
+  /// )?;
+  ///
+  /// assert_eq!(macro_set.expand(&["THREE"])?, macro_set.expand(&["(", "(", "1", ")", "+", "(", "2", ")", ")"])?);
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn define_from_source(&mut self, source: &str) -> Result<(), SourceError> {
+    self.define_from_lines(&tokenize_lines(source)?, None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn define_from_source() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #include <stdio.h>
+        # /* comment */ define VAR (1 + 2)
+        #define FN(x, ...) x + __VA_ARGS__
+        #define NOT_FN (x) x
+        #define STR "a" \
+          "b"
+        %:define EMPTY
+        #define UNDEFINED 1
+        #undef UNDEFINED
+        int main(void) { return 0; }
+        "#,
+      )
+      .unwrap();
+
+    // Whitespace between tokens is kept for comparing redefinitions.
+    assert_eq!(macro_set.var_macros["VAR"].body, ["(", "1", " ", "+", " ", "2", ")"]);
+    assert_eq!(macro_set.fn_macros["FN"].args, ["x", "..."]);
+    assert_eq!(macro_set.fn_macros["FN"].body, ["x", " ", "+", " ", "__VA_ARGS__"]);
+    assert_eq!(macro_set.var_macros["NOT_FN"].body, ["(", "x", ")", " ", "x"]);
+    assert_eq!(macro_set.var_macros["STR"].body, [r#""a""#, " ", r#""b""#]);
+    assert!(macro_set.var_macros["EMPTY"].body.is_empty());
+    assert!(!macro_set.var_macros.contains_key("UNDEFINED"));
+  }
+
+  #[test]
+  fn define_from_source_conditional() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set
+      .define_from_source(
+        r#"
+        #ifdef _WIN32
+        #  define OS "windows"
+        #  define 1 invalid
+        #elif defined(__linux__) || VERSION > 1
+        #  define OS "linux"
+        #else
+        #  define OS "unknown"
+        #endif
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(macro_set.var_macros["OS"].body, [r#""unknown""#]);
+  }
+
+  #[test]
+  fn define_from_source_invalid() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(
+      macro_set.define_from_source("#define"),
+      Err(ParserError::InvalidMacroName.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#define 1 2"),
+      Err(ParserError::InvalidMacroName.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#undef defined"),
+      Err(ParserError::InvalidMacroName.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#define F(a, 1)"),
+      Err(ParserError::InvalidMacroArgs.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#define F(..., a)"),
+      Err(ParserError::InvalidMacroArgs.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#define F(a"),
+      Err(ParserError::InvalidMacroArgs.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(
+      macro_set.define_from_source("#define F(args...)"),
+      Err(ParserError::InvalidMacroArgs.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert_eq!(macro_set.define_from_source("#define F /* a"), Err(ParserError::UnterminatedComment.into()));
+    assert_eq!(
+      macro_set.define_from_source("#endif"),
+      Err(ExpansionError::UnmatchedConditional.with_location(SourceLocation::new(1, 1)).into())
+    );
+  }
+
+  #[test]
+  fn define_from_source_conditional_error() {
+    let mut macro_set = MacroSet::new();
+
+    assert_eq!(
+      macro_set.define_from_source("#if 1\n#if 0\n#else\n#define 1\n#endif\n#endif"),
+      Err(ParserError::InvalidMacroName.with_location(SourceLocation::new(4, 1)).into())
+    );
+    assert!(macro_set.is_active());
+    let unterminated = |line| SourceError::Located {
+      location: SourceLocation::new(line, 1),
+      error: Box::new(SourceError::UnterminatedConditional),
+    };
+    assert_eq!(macro_set.define_from_source("#define B 2\n#if 0\n#define A 1"), Err(unterminated(2)));
+    assert!(macro_set.is_active());
+    assert_eq!(macro_set.define_from_source("#if 1\n#endif\n#ifdef A\n#if 0\n#endif"), Err(unterminated(3)));
+    assert!(macro_set.is_active());
+
+    // Conditionals entered before are not left by the source.
+    macro_set.if_directive(&["0"]).unwrap();
+    assert_eq!(
+      macro_set.define_from_source("#endif"),
+      Err(ExpansionError::UnmatchedConditional.with_location(SourceLocation::new(1, 1)).into())
+    );
+    assert!(!macro_set.is_active());
+    macro_set.endif_directive().unwrap();
+
+    macro_set.define_from_source("#define A 1").unwrap();
+    assert_eq!(macro_set.expand(&["A"]), macro_set.expand(&["1"]));
+  }
+}
@@ -0,0 +1,278 @@
+use std::{
+  collections::HashSet,
+  fmt::{self, Write},
+  rc::Rc,
+};
+
+use super::{tokenize, traced, ExpansionError, ExpansionState, MacroSet, Token, Traced};
+use crate::MacroToken;
+
+/// A step in the expansion of a token, see [`MacroSet::expand_traced`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpansionStep {
+  /// The token is part of the replacement list of the given macro.
+  Macro(String),
+  /// The token was substituted for a parameter of a function-like macro.
+  Argument {
+    /// The macro name.
+    name: String,
+    /// The parameter name.
+    parameter: String,
+  },
+  /// The token was produced by stringifying a parameter of a function-like macro (`#`).
+  Stringify {
+    /// The macro name.
+    name: String,
+    /// The parameter name.
+    parameter: String,
+  },
+  /// The token was produced by concatenation (`##`).
+  Concat,
+}
+
+impl fmt::Display for ExpansionStep {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Macro(name) => write!(f, "expansion of macro {name}"),
+      Self::Argument { name, parameter } => write!(f, "argument {parameter} of macro {name}"),
+      Self::Stringify { name, parameter } => write!(f, "stringification of argument {parameter} of macro {name}"),
+      Self::Concat => "concatenation".fmt(f),
+    }
+  }
+}
+
+/// A fully expanded token along with the steps producing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedToken<'t> {
+  pub(crate) token: MacroToken<'t>,
+  pub(crate) spelling: String,
+  pub(crate) steps: Vec<ExpansionStep>,
+}
+
+impl<'t> TracedToken<'t> {
+  /// The token.
+  pub fn token(&self) -> &MacroToken<'t> {
+    &self.token
+  }
+
+  /// The spelling of the token.
+  pub fn spelling(&self) -> &str {
+    &self.spelling
+  }
+
+  /// The steps producing this token, starting with the outermost macro.
+  ///
+  /// Empty if the token was not produced by a macro.
+  pub fn steps(&self) -> &[ExpansionStep] {
+    &self.steps
+  }
+}
+
+/// Render traced tokens as a step-by-step explanation of their expansion.
+///
+/// The first line contains the expanded tokens, followed by each token and
+/// the numbered steps producing it.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use cmacro::{render_trace, MacroSet};
+///
+/// let mut macro_set = MacroSet::new();
+/// macro_set.define_fn_macro("ADD", ["a", "b"], ["a", "+", "b"])?;
+/// macro_set.define_var_macro("ONE", ["1"])?;
+///
+/// let tokens = macro_set.expand_traced(&["ADD", "(", "ONE", ",", "x", ")"])?;
+/// assert_eq!(
+///   render_trace(&tokens),
+///   "\
+/// 1 + x
+/// `1`
+///   1. expansion of macro ONE
+///   2. argument a of macro ADD
+/// `+`
+///   1. expansion of macro ADD
+/// `x`
+///   1. argument b of macro ADD
+/// "
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn render_trace(tokens: &[TracedToken<'_>]) -> String {
+  let mut s = tokens.iter().map(|t| t.spelling.as_str()).collect::<Vec<_>>().join(" ");
+  s.push('\n');
+
+  for token in tokens {
+    writeln!(s, "`{}`", token.spelling).unwrap();
+
+    for (i, step) in token.steps.iter().enumerate() {
+      writeln!(s, "  {}. {step}", i + 1).unwrap();
+    }
+  }
+
+  s
+}
+
+/// The steps producing an intermediate token, or nothing if tracing is disabled.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Trace(Option<Rc<Vec<ExpansionStep>>>);
+
+impl Trace {
+  fn enabled() -> Self {
+    Self(Some(Rc::default()))
+  }
+
+  /// Check whether steps are recorded.
+  pub fn is_enabled(&self) -> bool {
+    self.0.is_some()
+  }
+
+  /// Add a step if tracing is enabled.
+  pub fn with(&self, step: impl FnOnce() -> ExpansionStep) -> Self {
+    Self(self.0.as_ref().map(|steps| {
+      let mut steps = steps.as_ref().clone();
+      steps.push(step());
+      Rc::new(steps)
+    }))
+  }
+
+  fn into_steps(self) -> Vec<ExpansionStep> {
+    self.0.map(|steps| steps.as_ref().clone()).unwrap_or_default()
+  }
+}
+
+pub(super) fn spelling(token: &Token<'_>) -> String {
+  match token {
+    Token::NonReplacable(token) => spelling(token),
+    Token::VarArgs => "__VA_ARGS__".to_owned(),
+    Token::VarOpt => "__VA_OPT__".to_owned(),
+    Token::Punctuation(p) => p.as_str().to_owned(),
+    Token::Identifier(id) => id.as_str().to_owned(),
+    Token::IdentifierContinue(id_cont) => id_cont.as_str().to_owned(),
+    Token::Literal(_, s) | Token::Plain(s) => s.as_ref().to_owned(),
+    Token::Comment(comment) => format!("/*{}*/", comment.comment),
+    // Only used in the body of function-like macros.
+    Token::MacroArg(_) | Token::Placemarker => String::new(),
+  }
+}
+
+fn finish(tokens: Vec<Traced<'_>>) -> Result<Vec<TracedToken<'_>>, ExpansionError> {
+  let mut traced_tokens = vec![];
+
+  for Traced { token, trace } in tokens {
+    let spelling = spelling(&token);
+
+    if let Some(token) = token.detokenize(&[])? {
+      traced_tokens.push(TracedToken { token, spelling, steps: trace.into_steps() });
+    }
+  }
+
+  Ok(traced_tokens)
+}
+
+impl MacroSet {
+  /// Expand a macro expression like [`MacroSet::expand`], recording the steps
+  /// producing each token, i.e. macro invocations, argument substitutions,
+  /// stringification and concatenation.
+  ///
+  /// See [`render_trace`] for an example.
+  pub fn expand_traced<'s, 'b, 't, T>(&'s self, body: &'b [T]) -> Result<Vec<TracedToken<'t>>, ExpansionError>
+  where
+    's: 't,
+    'b: 't,
+    T: AsRef<str> + 't,
+  {
+    let body = traced(tokenize(&[], body), &Trace::enabled());
+    finish(self.expand_var_macro_body(HashSet::new(), "", &body, &mut ExpansionState::default())?)
+  }
+
+  /// Expand a variable-like macro like [`MacroSet::expand_var_macro`], recording
+  /// the steps producing each token.
+  pub fn expand_var_macro_traced<'s, 't>(&'s self, name: &str) -> Result<Vec<TracedToken<'t>>, ExpansionError>
+  where
+    's: 't,
+  {
+    let (name, var_macro) = self.var_macro(name).ok_or(ExpansionError::MacroNotFound)?;
+    let body = traced(var_macro.tokens.clone(), &Trace::enabled().with(|| ExpansionStep::Macro(name.clone())));
+    finish(
+      self
+        .expand_var_macro_body(HashSet::new(), name, &body, &mut ExpansionState::default())
+        .map_err(|err| self.locate(name, err))?,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn steps(tokens: &[TracedToken<'_>]) -> Vec<(String, Vec<String>)> {
+    tokens.iter().map(|t| (t.spelling().to_owned(), t.steps().iter().map(|step| step.to_string()).collect())).collect()
+  }
+
+  #[test]
+  fn trace_var_macro() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_var_macro("A", ["B", "(", "1", ")"]).unwrap();
+    macro_set.define_fn_macro("B", ["x"], ["x", "+", "C"]).unwrap();
+    macro_set.define_var_macro("C", ["2"]).unwrap();
+
+    let tokens = macro_set.expand_var_macro_traced("A").unwrap();
+
+    assert_eq!(tokens.iter().map(|t| t.token().clone()).collect::<Vec<_>>(), macro_set.expand_var_macro("A").unwrap());
+    assert_eq!(
+      steps(&tokens),
+      vec![
+        ("1".to_owned(), vec!["expansion of macro A".to_owned(), "argument x of macro B".to_owned()]),
+        ("+".to_owned(), vec!["expansion of macro A".to_owned(), "expansion of macro B".to_owned()]),
+        (
+          "2".to_owned(),
+          vec!["expansion of macro A".to_owned(), "expansion of macro B".to_owned(), "expansion of macro C".to_owned()]
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn trace_stringify_concat() {
+    let mut macro_set = MacroSet::new();
+
+    macro_set.define_fn_macro("STR", ["x"], ["#", "x"]).unwrap();
+    macro_set.define_fn_macro("CAT", ["a", "b"], ["a", "##", "b"]).unwrap();
+    macro_set.define_fn_macro("VAR", ["..."], ["f", "(", "__VA_ARGS__", ")"]).unwrap();
+
+    let tokens = macro_set.expand_traced(&["STR", "(", "a", "+", "b", ")", "CAT", "(", "x", ",", "1", ")"]).unwrap();
+    assert_eq!(
+      steps(&tokens),
+      vec![
+        (
+          "\"a + b\"".to_owned(),
+          vec!["expansion of macro STR".to_owned(), "stringification of argument x of macro STR".to_owned()]
+        ),
+        ("x1".to_owned(), vec!["argument a of macro CAT".to_owned(), "concatenation".to_owned()]),
+      ]
+    );
+
+    let tokens = macro_set.expand_traced(&["VAR", "(", "1", ",", "2", ")"]).unwrap();
+    assert_eq!(tokens.iter().map(|t| t.spelling()).collect::<Vec<_>>(), ["f", "(", "1", ",", "2", ")"]);
+    assert_eq!(
+      tokens[3].steps(),
+      [
+        ExpansionStep::Macro("VAR".into()),
+        ExpansionStep::Argument { name: "VAR".into(), parameter: "__VA_ARGS__".into() }
+      ]
+    );
+  }
+
+  #[test]
+  fn trace_not_expanded() {
+    let macro_set = MacroSet::new();
+
+    let tokens = macro_set.expand_traced(&["a", "+", "1"]).unwrap();
+    assert!(tokens.iter().all(|t| t.steps().is_empty()));
+    assert_eq!(render_trace(&tokens), "a + 1\n`a`\n`+`\n`1`\n");
+  }
+}
@@ -15,6 +15,7 @@ pub(crate) use tokens;
 
 /// A macro token.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MacroToken<'t> {
   /// A macro parameter for the argument at the given position.
   Arg(MacroArg),
@@ -0,0 +1,326 @@
+use crate::ParserError;
+
+/// Punctuators ordered by length, so that the longest one matches first.
+const PUNCTUATORS: &[&str] = &[
+  "%:%:", "...", "<<=", ">>=", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=",
+  "+=", "-=", "&=", "^=", "|=", "##", "<:", ":>", "<%", "%>", "%:",
+];
+
+/// A pre-processing token in a logical source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceToken {
+  /// The token spelling.
+  pub text: String,
+  /// Whether the token is preceded by whitespace.
+  pub space_before: bool,
+  /// The 1-based line of the first character.
+  pub line: usize,
+  /// The 1-based column of the first character.
+  pub column: usize,
+}
+
+impl SourceToken {
+  pub fn is_comment(&self) -> bool {
+    self.text.starts_with("/*") || self.text.starts_with("//")
+  }
+}
+
+fn is_identifier_start(c: char) -> bool {
+  unicode_ident::is_xid_start(c) || c == '_'
+}
+
+struct Lexer {
+  chars: Vec<char>,
+  /// The line and column of each character.
+  positions: Vec<(usize, usize)>,
+  pos: usize,
+}
+
+impl Lexer {
+  fn new(source: &str) -> Self {
+    // Remove line continuations, i.e. backslashes directly followed by a newline.
+    let mut chars = Vec::with_capacity(source.len());
+    let mut positions = Vec::with_capacity(source.len());
+    let mut it = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(c) = it.next() {
+      if c == '\\' {
+        match it.peek() {
+          Some('\n') => {
+            it.next();
+            (line, column) = (line + 1, 1);
+            continue
+          },
+          Some('\r') => {
+            let mut lookahead = it.clone();
+            lookahead.next();
+
+            if lookahead.peek() == Some(&'\n') {
+              it.next();
+              it.next();
+              (line, column) = (line + 1, 1);
+              continue
+            }
+          },
+          _ => (),
+        }
+      }
+
+      chars.push(c);
+      positions.push((line, column));
+
+      if c == '\n' {
+        (line, column) = (line + 1, 1);
+      } else {
+        column += 1;
+      }
+    }
+
+    Self { chars, positions, pos: 0 }
+  }
+
+  fn peek(&self, offset: usize) -> Option<char> {
+    self.chars.get(self.pos + offset).copied()
+  }
+
+  fn starts_with(&self, s: &str) -> bool {
+    let mut chars = self.chars[self.pos..].iter();
+    s.chars().all(|c| chars.next() == Some(&c))
+  }
+
+  fn take(&mut self, len: usize) -> String {
+    let end = (self.pos + len).min(self.chars.len());
+    let s = self.chars[self.pos..end].iter().collect();
+    self.pos = end;
+    s
+  }
+
+  /// Length of a universal character name at the given offset, if any.
+  fn universal_char_len(&self, offset: usize) -> Option<usize> {
+    let len = match (self.peek(offset), self.peek(offset + 1)) {
+      (Some('\\'), Some('u')) => 6,
+      (Some('\\'), Some('U')) => 10,
+      _ => return None,
+    };
+
+    if (2..len).all(|i| self.peek(offset + i).map(|c| c.is_ascii_hexdigit()).unwrap_or(false)) {
+      Some(len)
+    } else {
+      None
+    }
+  }
+
+  fn identifier_len(&self) -> usize {
+    let mut len = match self.peek(0) {
+      Some(c) if is_identifier_start(c) => 1,
+      _ => match self.universal_char_len(0) {
+        Some(len) => len,
+        None => return 0,
+      },
+    };
+
+    loop {
+      match self.peek(len) {
+        Some(c) if unicode_ident::is_xid_continue(c) => len += 1,
+        _ => match self.universal_char_len(len) {
+          Some(ucn_len) => len += ucn_len,
+          None => return len,
+        },
+      }
+    }
+  }
+
+  fn pp_number_len(&self) -> usize {
+    let mut len = 0;
+
+    loop {
+      match self.peek(len) {
+        Some('e' | 'E' | 'p' | 'P') if matches!(self.peek(len + 1), Some('+' | '-')) => len += 2,
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '.' => len += 1,
+        // Digit separators.
+        Some('\'') if self.peek(len + 1).map(|c| c.is_ascii_alphanumeric()).unwrap_or(false) => len += 2,
+        _ => return len,
+      }
+    }
+  }
+
+  /// Length of a string or character literal starting at the given offset, if it is terminated.
+  fn literal_len(&self, offset: usize) -> Option<usize> {
+    let quote = self.peek(offset)?;
+    let mut len = offset + 1;
+
+    loop {
+      match self.peek(len)? {
+        '\\' => len += 2,
+        '\n' => return None,
+        c if c == quote => return Some(len + 1),
+        _ => len += 1,
+      }
+    }
+  }
+
+  fn comment_len(&self) -> Result<Option<usize>, ParserError> {
+    if self.starts_with("//") {
+      let len = self.chars[self.pos..].iter().position(|&c| c == '\n').unwrap_or(self.chars.len() - self.pos);
+      return Ok(Some(len))
+    }
+
+    if self.starts_with("/*") {
+      let end = self.chars[(self.pos + 2)..].windows(2).position(|w| w == ['*', '/']);
+      return end.map(|end| Some(end + 4)).ok_or(ParserError::UnterminatedComment)
+    }
+
+    Ok(None)
+  }
+
+  fn token_len(&self) -> Result<usize, ParserError> {
+    if let Some(len) = self.comment_len()? {
+      return Ok(len)
+    }
+
+    let c = match self.peek(0) {
+      Some(c) => c,
+      None => return Ok(0),
+    };
+
+    let identifier_len = self.identifier_len();
+    if identifier_len > 0 {
+      // Check for a prefixed string or character literal.
+      if matches!(self.peek(identifier_len), Some('"' | '\'')) {
+        if let "u8" | "u" | "U" | "L" =
+          self.chars[self.pos..(self.pos + identifier_len)].iter().collect::<String>().as_str()
+        {
+          if let Some(len) = self.literal_len(identifier_len) {
+            return Ok(len)
+          }
+        }
+      }
+
+      return Ok(identifier_len)
+    }
+
+    if c.is_ascii_digit() || (c == '.' && self.peek(1).map(|c| c.is_ascii_digit()).unwrap_or(false)) {
+      return Ok(self.pp_number_len())
+    }
+
+    if c == '"' || c == '\'' {
+      // An unterminated quote is a token on its own.
+      return Ok(self.literal_len(0).unwrap_or(1))
+    }
+
+    Ok(PUNCTUATORS.iter().find(|p| self.starts_with(p)).map(|p| p.len()).unwrap_or(1))
+  }
+}
+
+/// Split C source code into logical lines of pre-processing tokens.
+pub(crate) fn tokenize_lines(source: &str) -> Result<Vec<Vec<SourceToken>>, ParserError> {
+  let mut lexer = Lexer::new(source);
+
+  let mut lines = vec![];
+  let mut line = vec![];
+  let mut space_before = false;
+
+  while let Some(c) = lexer.peek(0) {
+    if c == '\n' {
+      lexer.pos += 1;
+      lines.push(std::mem::take(&mut line));
+      space_before = false;
+      continue
+    }
+
+    if c.is_whitespace() {
+      lexer.pos += 1;
+      space_before = true;
+      continue
+    }
+
+    let len = lexer.token_len()?;
+    let (line_number, column) = lexer.positions[lexer.pos];
+    line.push(SourceToken { text: lexer.take(len), space_before, line: line_number, column });
+    space_before = false;
+  }
+
+  if !line.is_empty() {
+    lines.push(line);
+  }
+
+  Ok(lines)
+}
+
+/// Split C source code into pre-processing tokens.
+///
+/// Line continuations are removed before splitting the source into tokens.
+/// Comments are kept as separate tokens, whitespace and newlines are removed.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use cmacro::tokenize;
+///
+/// let tokens = tokenize("#define STR u8\"abc\" /* comment */\\\n  ## x")?;
+/// assert_eq!(tokens, ["#", "define", "STR", "u8\"abc\"", "/* comment */", "##", "x"]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn tokenize(source: &str) -> Result<Vec<String>, ParserError> {
+  Ok(tokenize_lines(source)?.into_iter().flatten().map(|token| token.text).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tokenize_punctuators() {
+    assert_eq!(tokenize("a+++++b").unwrap(), ["a", "++", "++", "+", "b"]);
+    assert_eq!(tokenize("x<<=y>>=z...%:%:").unwrap(), ["x", "<<=", "y", ">>=", "z", "...", "%:%:"]);
+    assert_eq!(tokenize("p->q.r").unwrap(), ["p", "->", "q", ".", "r"]);
+  }
+
+  #[test]
+  fn tokenize_numbers() {
+    assert_eq!(tokenize("1.5e+10f+0x1p-3 .5").unwrap(), ["1.5e+10f", "+", "0x1p-3", ".5"]);
+    assert_eq!(tokenize("123abc 1'000'000").unwrap(), ["123abc", "1'000'000"]);
+  }
+
+  #[test]
+  fn tokenize_literals() {
+    assert_eq!(tokenize(r#"u8"a\"b" L'c' U'\'' u "d""#).unwrap(), [r#"u8"a\"b""#, "L'c'", r"U'\''", "u", r#""d""#]);
+    assert_eq!(tokenize("don't").unwrap(), ["don", "'", "t"]);
+  }
+
+  #[test]
+  fn tokenize_comments() {
+    assert_eq!(tokenize("a /* b\n c */ d // e\nf").unwrap(), ["a", "/* b\n c */", "d", "// e", "f"]);
+    assert_eq!(tokenize("a /* b"), Err(ParserError::UnterminatedComment));
+  }
+
+  #[test]
+  fn tokenize_line_continuations() {
+    assert_eq!(tokenize("ab\\\ncd \\\r\n ef").unwrap(), ["abcd", "ef"]);
+
+    let lines = tokenize_lines("#define A \\\n 1\n#define B /*\n*/ 2\n\nC").unwrap();
+    let lines = lines.iter().map(|line| line.iter().map(|t| t.text.as_str()).collect::<Vec<_>>()).collect::<Vec<_>>();
+    assert_eq!(lines, vec![vec!["#", "define", "A", "1"], vec!["#", "define", "B", "/*\n*/", "2"], vec![], vec!["C"]]);
+  }
+
+  #[test]
+  fn tokenize_positions() {
+    let lines = tokenize_lines("#define A \\\n  1\n/*\n*/ B").unwrap();
+    let positions = lines
+      .iter()
+      .map(|line| line.iter().map(|t| (t.text.as_str(), t.line, t.column)).collect::<Vec<_>>())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      positions,
+      vec![vec![("#", 1, 1), ("define", 1, 2), ("A", 1, 9), ("1", 2, 3)], vec![("/*\n*/", 3, 1), ("B", 4, 4)]]
+    );
+  }
+
+  #[test]
+  fn tokenize_identifiers() {
+    assert_eq!(tokenize(r"été \u00e9t\u00E9 猫").unwrap(), ["été", r"\u00e9t\u00E9", "猫"]);
+  }
+}
@@ -4,7 +4,7 @@ use proc_macro2::TokenStream;
 use quote::TokenStreamExt;
 use semver::{Version, VersionReq};
 
-use crate::{ast::Lit, is_identifier, CodegenContext, Expr, LocalContext, MacroBody, MacroToken};
+use crate::{ast::Lit, is_identifier, CodegenContext, Expr, LocalContext, MacroBody, MacroToken, SourceLocation};
 
 /// A variable-like macro.
 ///
@@ -17,7 +17,7 @@ use crate::{ast::Lit, is_identifier, CodegenContext, Expr, LocalContext, MacroBo
 /// let mut macro_set = MacroSet::new();
 ///
 /// // #define VAR 4 + 7 + 82
-/// macro_set.define_var_macro("VAR", &["4", "+", "7", "*", "82"]);
+/// macro_set.define_var_macro("VAR", &["4", "+", "7", "*", "82"])?;
 ///
 /// let body = macro_set.expand_var_macro("VAR")?;
 /// let mut var_macro = VarMacro::parse("VAR", &body)?;
@@ -31,6 +31,7 @@ use crate::{ast::Lit, is_identifier, CodegenContext, Expr, LocalContext, MacroBo
 pub struct VarMacro<'t> {
   name: String,
   body: MacroBody<'t>,
+  location: Option<SourceLocation>,
 }
 
 impl<'t> VarMacro<'t> {
@@ -43,15 +44,42 @@ impl<'t> VarMacro<'t> {
       Err(_) => return Err(crate::ParserError::InvalidMacroBody),
     };
 
-    Ok(Self { name, body })
+    Ok(Self { name, body, location: None })
+  }
+
+  /// Parse a variable-like macro like [`VarMacro::parse`], recording the location of its definition,
+  /// e.g. from [`MacroSet::location`](crate::MacroSet::location).
+  ///
+  /// Errors when parsing the macro or generating code will contain this location.
+  pub fn parse_with_location(
+    name: &str,
+    value: &[MacroToken<'t>],
+    location: SourceLocation,
+  ) -> Result<Self, crate::ParserError> {
+    let mut var_macro = Self::parse(name, value).map_err(|err| err.with_location(location.clone()))?;
+    var_macro.location = Some(location);
+    Ok(var_macro)
   }
 
   /// Evaluate the value and type of this macro and generate corresponding Rust code.
   pub fn generate<C>(&mut self, cx: C) -> Result<(TokenStream, Option<TokenStream>), crate::CodegenError>
+  where
+    C: CodegenContext,
+  {
+    let location = self.location.clone();
+
+    self.generate_tokens(cx).map_err(|err| match location {
+      Some(location) => err.with_location(location),
+      None => err,
+    })
+  }
+
+  fn generate_tokens<C>(&mut self, cx: C) -> Result<(TokenStream, Option<TokenStream>), crate::CodegenError>
   where
     C: CodegenContext,
   {
     let mut ctx = LocalContext::new(&cx);
+    ctx.macro_name = self.name.clone();
     ctx.is_variable_macro = true;
 
     ctx.generate_cstr = ctx
@@ -92,6 +120,11 @@ impl<'t> VarMacro<'t> {
     &self.name
   }
 
+  /// The location of the definition of this variable macro, if known.
+  pub fn location(&self) -> Option<&SourceLocation> {
+    self.location.as_ref()
+  }
+
   /// The value of this variable macro.
   pub fn value(&self) -> Option<&Expr> {
     match &self.body {
@@ -0,0 +1,2 @@
+#define FUNC_NAME() __func__
+#define FUNCTION_NAME() __FUNCTION__
@@ -0,0 +1,17 @@
+#[allow(non_snake_case, unused_mut, unsafe_code)]
+#[inline(always)]
+pub unsafe extern "C" fn FUNC_NAME() -> *const c_char {
+  {
+    const BYTES: &[u8] = concat!("FUNC_NAME", '\0').as_bytes();
+    BYTES.as_ptr() as *const c_char
+  }
+}
+
+#[allow(non_snake_case, unused_mut, unsafe_code)]
+#[inline(always)]
+pub unsafe extern "C" fn FUNCTION_NAME() -> *const c_char {
+  {
+    const BYTES: &[u8] = concat!("FUNCTION_NAME", '\0').as_bytes();
+    BYTES.as_ptr() as *const c_char
+  }
+}
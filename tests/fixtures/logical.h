@@ -0,0 +1,10 @@
+#define BOTH 1 && 2
+#define EITHER 0 || 3
+#define NOT_BOTH !(1 && 0)
+#define NESTED (1 || 0) && !0 || 0 && 1
+
+#define AND(a, b) a && b
+#define OR_NOT(a, b) a || !b
+#define NOT_EQUAL(a, b) !(a == b) && !!a
+#define IN_RANGE(x, lo, hi) x >= lo && x <= hi
+#define SELECT(a, b) a && b ? 1 : 2
@@ -0,0 +1,49 @@
+pub const BOTH: bool = 1 != 0 && 2 != 0;
+pub const EITHER: bool = 0 != 0 || 3 != 0;
+pub const NOT_BOTH: bool = !(1 != 0 && 0 != 0);
+pub const NESTED: bool = (1 != 0 || 0 != 0) && 1 != 0 || 0 != 0 && 1 != 0;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmacro__AND {
+    ($a:expr, $b:expr) => {
+        $a != 0 && $b != 0
+    };
+}
+pub use __cmacro__AND as AND;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmacro__OR_NOT {
+    ($a:expr, $b:expr) => {
+        $a != 0 || $b == 0
+    };
+}
+pub use __cmacro__OR_NOT as OR_NOT;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmacro__NOT_EQUAL {
+    ($a:expr, $b:expr) => {
+        !($a == $b) && !($a == 0)
+    };
+}
+pub use __cmacro__NOT_EQUAL as NOT_EQUAL;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmacro__IN_RANGE {
+    ($x:expr, $lo:expr, $hi:expr) => {
+        $x >= $lo && $x <= $hi
+    };
+}
+pub use __cmacro__IN_RANGE as IN_RANGE;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmacro__SELECT {
+    ($a:expr, $b:expr) => {
+        if $a != 0 && $b != 0 { 1 } else { 2 }
+    };
+}
+pub use __cmacro__SELECT as SELECT;
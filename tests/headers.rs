@@ -10,7 +10,7 @@ use yansi::{
   Style,
 };
 
-use cmacro::{CodegenContext, ExpansionError, FnMacro, MacroSet, VarMacro};
+use cmacro::{CodegenContext, FnMacro, MacroKind, MacroSet, VarMacro};
 
 fn location_in_scope(r: &SourceRange) -> bool {
   let start = r.get_start();
@@ -96,7 +96,6 @@ fn file_visit_macros<F: FnMut(EntityKind, String, Option<Vec<String>>, Vec<Strin
 
 #[derive(Debug, Default)]
 struct TestContext {
-  pub macros: Vec<String>,
   pub functions: HashMap<String, (Vec<String>, String)>,
   pub macro_set: MacroSet,
 }
@@ -143,48 +142,53 @@ fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
         context.functions.insert(name, (args.unwrap(), value.remove(0)));
       },
       EntityKind::MacroDefinition => {
-        if let Some(args) = args {
-          context.macro_set.define_fn_macro(name.clone(), args, value);
+        let result = if let Some(args) = args {
+          context.macro_set.define_fn_macro(name.clone(), args, value)
         } else {
-          context.macro_set.define_var_macro(name.clone(), value);
-        }
+          context.macro_set.define_var_macro(name.clone(), value)
+        };
 
-        context.macros.push(name);
+        if let Err(err) = result {
+          eprintln!("Error defining macro {name}: {err}");
+        }
       },
       _ => (),
     });
 
+    context.macro_set.cache_expansions();
+
     let mut f = TokenStream::new();
 
-    for name in &context.macros {
-      match context.macro_set.expand_fn_macro(name) {
-        Ok((arg_names, fn_macro)) => match FnMacro::parse(name.as_str(), &arg_names, &fn_macro) {
-          Ok(mut fn_macro) => {
-            if let Ok(tokens) = fn_macro.generate(&context) {
-              f.append_all(tokens);
-            }
+    for definition in context.macro_set.definitions() {
+      let name = definition.name();
+
+      match definition.kind() {
+        MacroKind::Fn => match context.macro_set.expand_fn_macro(name) {
+          Ok((arg_names, fn_macro)) => match FnMacro::parse(name, &arg_names, &fn_macro) {
+            Ok(mut fn_macro) => {
+              if let Ok(tokens) = fn_macro.generate(&context) {
+                f.append_all(tokens);
+              }
+            },
+            Err(err) => eprintln!("Error parsing function-like macro {name}: {err}"),
           },
-          Err(err) => eprintln!("Error parsing function-like macro {name}: {err}"),
+          Err(err) => eprintln!("Error for {name}: {err:?}"),
         },
-        Err(ExpansionError::MacroNotFound) => (),
-        Err(err) => eprintln!("Error for {name}: {err:?}"),
-      }
-
-      match context.macro_set.expand_var_macro(name) {
-        Ok(var_macro) => match VarMacro::parse(name.as_str(), &var_macro) {
-          Ok(mut var_macro) => {
-            if let Ok((value, ty)) = var_macro.generate(&context) {
-              let name = Ident::new(name, Span::call_site());
-              let ty = ty.unwrap_or(quote! { _ });
-              f.append_all(quote! {
-                pub const #name: #ty = #value;
-              })
-            }
+        MacroKind::Var => match context.macro_set.expand_var_macro(name) {
+          Ok(var_macro) => match VarMacro::parse(name, &var_macro) {
+            Ok(mut var_macro) => {
+              if let Ok((value, ty)) = var_macro.generate(&context) {
+                let name = Ident::new(name, Span::call_site());
+                let ty = ty.unwrap_or(quote! { _ });
+                f.append_all(quote! {
+                  pub const #name: #ty = #value;
+                })
+              }
+            },
+            Err(err) => eprintln!("Error parsing variable-like macro {name}: {err}"),
           },
-          Err(err) => eprintln!("Error parsing variable-like macro {name}: {err}"),
+          Err(err) => eprintln!("Error for {name}: {err:?}"),
         },
-        Err(ExpansionError::MacroNotFound) => (),
-        Err(err) => eprintln!("Error for {name}: {err:?}"),
       }
     }
 